@@ -0,0 +1,75 @@
+//! Injected ADC conversions triggered by a PWM timer
+//!
+//! TIM1 drives a PWM output on PA8, its channel 4 starts the injected conversion of PA0 and PA1
+//! (for example the phase currents of a motor) and of PB0 (for example the supply voltage) in
+//! every PWM period. The end of an injected conversion also sets the EOC flag of the regular
+//! group, so no one-shot `read` is used while the trigger is active.
+
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use panic_semihosting as _;
+
+use cortex_m_rt::entry;
+use cortex_m_semihosting::hprintln;
+use stm32f1xx_hal::{
+    adc::{self, ChannelTimeSequence, InjectedTrigger},
+    pac::{self, adc1::cr2::JEXTSEL_A},
+    prelude::*,
+    pwm::Channel,
+    timer::{Tim1NoRemap, Timer},
+};
+
+#[entry]
+fn main() -> ! {
+    let p = pac::Peripherals::take().unwrap();
+    let mut flash = p.FLASH.constrain();
+    let mut rcc = p.RCC.constrain();
+    let clocks = rcc.cfgr.adcclk(2.mhz()).freeze(&mut flash.acr);
+
+    let mut afio = p.AFIO.constrain(&mut rcc.apb2);
+    let mut gpioa = p.GPIOA.split(&mut rcc.apb2);
+    let mut gpiob = p.GPIOB.split(&mut rcc.apb2);
+
+    let _current_a = gpioa.pa0.into_analog(&mut gpioa.crl);
+    let _current_b = gpioa.pa1.into_analog(&mut gpioa.crl);
+    let _supply = gpiob.pb0.into_analog(&mut gpiob.crl);
+
+    let mut adc1 = adc::Adc::adc1(p.ADC1, &mut rcc.apb2, clocks);
+    adc1.set_channel_sample_time(0, adc::SampleTime::T_7);
+    adc1.set_channel_sample_time(1, adc::SampleTime::T_7);
+    adc1.set_channel_sample_time(8, adc::SampleTime::T_7);
+    adc1.set_injected_sequence(&[0, 1, 8]);
+    // Remove the mid-scale bias of the current sensors, the results are signed
+    adc1.set_injected_offset(0, 2048);
+    adc1.set_injected_offset(1, 2048);
+    adc1.set_injected_trigger(InjectedTrigger::External(JEXTSEL_A::TIM1CC4));
+
+    let pins = (
+        gpioa.pa8.into_alternate_push_pull(&mut gpioa.crh),
+        gpioa.pa11.into_alternate_push_pull(&mut gpioa.crh),
+    );
+    let mut pwm = Timer::tim1(p.TIM1, &clocks, &mut rcc.apb2).pwm::<Tim1NoRemap, _, _, _>(
+        pins,
+        &mut afio.mapr,
+        10.khz(),
+    );
+    let max = pwm.get_max_duty();
+    pwm.set_duty(Channel::C1, max / 2);
+    // Sample shortly before the end of the period
+    pwm.set_duty(Channel::C4, max - max / 16);
+    pwm.enable(Channel::C1);
+    pwm.enable(Channel::C4);
+
+    loop {
+        while !adc1.is_injected_complete() {}
+        let current_a = adc1.read_injected(0);
+        let current_b = adc1.read_injected(1);
+        // No offset is set for the supply voltage, the result is never negative
+        let voltage = adc1.read_injected(2) as u16;
+        adc1.clear_injected_complete();
+
+        hprintln!("supply: {}, currents: {} {}", voltage, current_a, current_b).unwrap();
+    }
+}
@@ -10,7 +10,7 @@ use cortex_m_rt::entry;
 use embedded_hal::digital::v2::OutputPin;
 use nb::block;
 use stm32f1xx_hal::{
-    can::{Can, Filter, Frame},
+    can::{Can, CanBitrate, Filter, Frame},
     pac,
     prelude::*,
 };
@@ -24,7 +24,7 @@ fn main() -> ! {
 
     // To meet CAN clock accuracy requirements an external crystal or ceramic
     // resonator must be used.
-    rcc.cfgr.use_hse(8.mhz()).freeze(&mut flash.acr);
+    let clocks = rcc.cfgr.use_hse(8.mhz()).freeze(&mut flash.acr);
 
     #[cfg(not(feature = "connectivity"))]
     let mut can = Can::new(dp.CAN1, &mut rcc.apb1, dp.USB);
@@ -33,10 +33,13 @@ fn main() -> ! {
     let mut can = Can::new(dp.CAN1, &mut rcc.apb1);
 
     // Use loopback mode: No pins need to be assigned to peripheral.
+    // APB1 (PCLK1): 8MHz, Bit rate: 125kBit/s, Sample Point 87.5%
+    let timing = CanBitrate::new(clocks.pclk1(), 125_000.bps())
+        .sample_point(875)
+        .calculate()
+        .unwrap();
     can.configure(|config| {
-        // APB1 (PCLK1): 8MHz, Bit rate: 125kBit/s, Sample Point 87.5%
-        // Value was calculated with http://www.bittiming.can-wiki.info/
-        config.set_bit_timing(0x001c_0003);
+        config.set_bitrate(timing);
         config.set_loopback(true);
         config.set_silent(true);
     });
@@ -23,7 +23,7 @@ use heapless::{
 use panic_halt as _;
 use rtfm::app;
 use stm32f1xx_hal::{
-    can::{Can, Filter, Frame, Id, Rx, Tx},
+    can::{Can, CanBitrate, Filter, Frame, Id, Rx, Tx},
     pac::{Interrupt, CAN1},
     prelude::*,
 };
@@ -56,7 +56,7 @@ const APP: () = {
         let mut flash = cx.device.FLASH.constrain();
         let mut rcc = cx.device.RCC.constrain();
 
-        let _clocks = rcc
+        let clocks = rcc
             .cfgr
             .use_hse(8.mhz())
             .sysclk(64.mhz())
@@ -78,10 +78,12 @@ const APP: () = {
         let mut afio = cx.device.AFIO.constrain(&mut rcc.apb2);
         can.assign_pins((can_tx_pin, can_rx_pin), &mut afio.mapr);
 
+        // APB1 (PCLK1): 16MHz, Bit rate: 1000kBit/s, Sample Point 87.5%
+        let timing = CanBitrate::new(clocks.pclk1(), 1_000_000.bps())
+            .calculate()
+            .unwrap();
         can.configure(|config| {
-            // APB1 (PCLK1): 16MHz, Bit rate: 1000kBit/s, Sample Point 87.5%
-            // Value was calculated with http://www.bittiming.can-wiki.info/
-            config.set_bit_timing(0x001c_0000);
+            config.set_bitrate(timing);
         });
 
         // To share load between FIFOs use one filter for standard messages and another
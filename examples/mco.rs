@@ -0,0 +1,36 @@
+//! Outputs the HSE clock on PA8, e.g. to clock an external PHY or codec
+
+#![deny(unsafe_code)]
+#![no_main]
+#![no_std]
+
+use panic_halt as _;
+
+use cortex_m_rt::entry;
+use stm32f1xx_hal::{
+    pac,
+    prelude::*,
+    rcc::{Mco, McoSource},
+};
+
+#[entry]
+fn main() -> ! {
+    let p = pac::Peripherals::take().unwrap();
+
+    let mut flash = p.FLASH.constrain();
+    let mut rcc = p.RCC.constrain();
+    let clocks = rcc
+        .cfgr
+        .use_hse(8.mhz())
+        .sysclk(72.mhz())
+        .pclk1(36.mhz())
+        .freeze(&mut flash.acr);
+
+    let mut gpioa = p.GPIOA.split(&mut rcc.apb2);
+    let pa8 = gpioa.pa8.into_alternate_push_pull(&mut gpioa.crh);
+
+    let mco = Mco::new(pa8, McoSource::Hse, &clocks);
+    assert_eq!(mco.frequency().0, 8_000_000);
+
+    loop {}
+}
@@ -0,0 +1,69 @@
+//! Serial interface circular DMA RX transfer of variable length frames
+//!
+//! Every frame ends when the line goes idle. The same can be done from the
+//! USART interrupt by calling `listen_idle` before `with_dma`.
+
+#![deny(unsafe_code)]
+#![no_std]
+#![no_main]
+
+use panic_halt as _;
+
+use cortex_m::{asm, singleton};
+
+use cortex_m_rt::entry;
+use stm32f1xx_hal::{
+    pac,
+    prelude::*,
+    serial::{Config, Serial},
+};
+
+#[entry]
+fn main() -> ! {
+    let p = pac::Peripherals::take().unwrap();
+
+    let mut flash = p.FLASH.constrain();
+    let mut rcc = p.RCC.constrain();
+
+    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+
+    let mut afio = p.AFIO.constrain(&mut rcc.apb2);
+    let channels = p.DMA1.split(&mut rcc.ahb);
+
+    let mut gpioa = p.GPIOA.split(&mut rcc.apb2);
+
+    // USART1
+    let tx = gpioa.pa9.into_alternate_push_pull(&mut gpioa.crh);
+    let rx = gpioa.pa10;
+
+    let serial = Serial::usart1(
+        p.USART1,
+        (tx, rx),
+        &mut afio.mapr,
+        Config::default().baudrate(9_600.bps()),
+        clocks,
+        &mut rcc.apb2,
+    );
+
+    let rx = serial.split().1.with_dma(channels.5);
+    let buf = singleton!(: [u8; 64] = [0; 64]).unwrap();
+
+    let mut ring_buffer = rx.ring_read(buf);
+
+    let mut frame = [0u8; 64];
+    loop {
+        if ring_buffer.is_idle() {
+            ring_buffer.clear_idle_interrupt();
+
+            // the frames are lost if the buffer overflowed between two reads
+            if let Ok(len) = ring_buffer.read(|first, second| {
+                frame[..first.len()].copy_from_slice(first);
+                frame[first.len()..first.len() + second.len()].copy_from_slice(second);
+                first.len() + second.len()
+            }) {
+                let _frame = &frame[..len];
+                asm::bkpt();
+            }
+        }
+    }
+}
@@ -101,6 +101,36 @@ impl From<Align> for bool {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// Start of the injected group conversion
+///
+/// `T` is the `JEXTSEL_A` type of the ADC in the PAC, for example
+/// `pac::adc1::cr2::JEXTSEL_A`.
+pub enum InjectedTrigger<T> {
+    /// Started by `start_injected`
+    Software,
+    /// Started by a timer or EXTI event
+    ///
+    /// The end of the injected group also sets the EOC flag that `OneShot::read` waits for,
+    /// which can then return a stale result. Do not use `read` while the trigger is active,
+    /// convert the channels in the injected group instead.
+    External(T),
+    /// Converted automatically after the regular group
+    ///
+    /// Disables the discontinuous mode of the regular and the injected group, which cannot be
+    /// combined with the automatic conversion.
+    AfterRegular,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// ADC interrupt events
+pub enum Event {
+    /// End of a regular conversion (EOC)
+    EndOfConversion,
+    /// End of the injected group conversion (JEOC)
+    EndOfInjectedConversion,
+}
+
 macro_rules! adc_pins {
     ($ADC:ident, $($pin:ty => $chan:expr),+ $(,)*) => {
         $(
@@ -213,6 +243,15 @@ macro_rules! adc_hal {
                     cfg
                 }
 
+                /// Updates the stored clock frequencies after the clocks have been reconfigured
+                ///
+                /// The ADC prescaler is part of the RCC configuration and has already been applied
+                /// by `freeze`, the ADC is recalibrated for the new ADC clock.
+                pub fn reconfigure(&mut self, clocks: &Clocks) {
+                    self.clocks = *clocks;
+                    self.calibrate();
+                }
+
                 /// Set ADC sampling time
                 ///
                 /// Options can be found in [SampleTime](crate::adc::SampleTime).
@@ -240,6 +279,127 @@ macro_rules! adc_hal {
                     self.rb.cr2.modify(|_, w| w.extsel().variant(trigger))
                 }
 
+                /// Set the injected group sequence
+                ///
+                /// Up to four channels are converted in the given order, the result of the
+                /// n-th channel is stored in the injected data register n. Sample times are
+                /// set with `set_channel_sample_time`. Scan mode is enabled for more than one
+                /// channel.
+                pub fn set_injected_sequence(&mut self, channels: &[u8]) {
+                    let len = channels.len();
+                    assert!((1..=4).contains(&len), "the injected group has 1 to 4 channels");
+                    assert!(channels.iter().all(|&c| c <= 17), "the ADC has the channels 0 to 17");
+
+                    // with JL = len - 1 the sequence uses the last len of the JSQ1..JSQ4 fields
+                    let bits = channels.iter().enumerate().fold(0u32, |s, (i, c)|
+                        s | ((*c as u32) << ((4 - len + i) * 5))
+                    ) | ((len as u32 - 1) << 20);
+                    self.rb.jsqr.write(|w| unsafe { w.bits(bits) });
+
+                    if len > 1 {
+                        self.rb.cr1.modify(|_, w| w.scan().set_bit());
+                    }
+                }
+
+                /// Set the offset subtracted from the result of the n-th injected channel
+                ///
+                /// `rank` is 0 to 3. The result of an injected channel can become negative and
+                /// is read as a signed value.
+                pub fn set_injected_offset(&mut self, rank: usize, offset: u16) {
+                    assert!(offset < 1 << 12, "the offset has 12 bits");
+                    let offset = offset as u32;
+                    match rank {
+                        0 => self.rb.jofr1.write(|w| unsafe { w.bits(offset) }),
+                        1 => self.rb.jofr2.write(|w| unsafe { w.bits(offset) }),
+                        2 => self.rb.jofr3.write(|w| unsafe { w.bits(offset) }),
+                        3 => self.rb.jofr4.write(|w| unsafe { w.bits(offset) }),
+                        _ => panic!("the injected group has 4 ranks"),
+                    }
+                }
+
+                /// Set how the injected group conversion is started
+                pub fn set_injected_trigger(&mut self, trigger: InjectedTrigger<crate::pac::$adc::cr2::JEXTSEL_A>) {
+                    match trigger {
+                        InjectedTrigger::Software => {
+                            self.rb.cr1.modify(|_, w| w.jauto().clear_bit());
+                            self.rb.cr2.modify(|_, w| w
+                                .jextsel().variant(crate::pac::$adc::cr2::JEXTSEL_A::JSWSTART)
+                                .jexttrig().set_bit()
+                            );
+                        }
+                        InjectedTrigger::External(trigger) => {
+                            self.rb.cr1.modify(|_, w| w.jauto().clear_bit());
+                            self.rb.cr2.modify(|_, w| w
+                                .jextsel().variant(trigger)
+                                .jexttrig().set_bit()
+                            );
+                        }
+                        InjectedTrigger::AfterRegular => {
+                            // neither an external trigger nor the discontinuous modes may be
+                            // used together with JAUTO
+                            self.rb.cr2.modify(|_, w| w.jexttrig().clear_bit());
+                            self.rb.cr1.modify(|_, w| w
+                                .discen().clear_bit()
+                                .jdiscen().clear_bit()
+                                .jauto().set_bit()
+                            );
+                        }
+                    }
+                }
+
+                /// Start the injected group conversion with the software trigger
+                pub fn start_injected(&mut self) {
+                    self.rb.cr2.modify(|_, w| w.jswstart().set_bit());
+                }
+
+                /// Returns `true` if the injected group conversion is complete (JEOC)
+                pub fn is_injected_complete(&self) -> bool {
+                    self.rb.sr.read().jeoc().bit_is_set()
+                }
+
+                /// Clears the end of injected conversion flag
+                pub fn clear_injected_complete(&mut self) {
+                    // The flags are cleared by writing 0, writing 1 leaves them unchanged, so a
+                    // read-modify-write could clear an EOC set in between
+                    self.rb.sr.write(|w| w
+                        .awd().set_bit()
+                        .eoc().set_bit()
+                        .strt().set_bit()
+                        .jeoc().clear_bit()
+                        .jstrt().clear_bit()
+                    );
+                }
+
+                /// Reads the result of the n-th injected channel, `rank` is 0 to 3
+                ///
+                /// The offset of the channel has been subtracted, the result is sign extended
+                /// and keeps the configured alignment.
+                pub fn read_injected(&self, rank: usize) -> i16 {
+                    (match rank {
+                        0 => self.rb.jdr1.read().jdata().bits(),
+                        1 => self.rb.jdr2.read().jdata().bits(),
+                        2 => self.rb.jdr3.read().jdata().bits(),
+                        3 => self.rb.jdr4.read().jdata().bits(),
+                        _ => panic!("the injected group has 4 ranks"),
+                    }) as i16
+                }
+
+                /// Enables the interrupt for the given event
+                pub fn listen(&mut self, event: Event) {
+                    match event {
+                        Event::EndOfConversion => self.rb.cr1.modify(|_, w| w.eocie().set_bit()),
+                        Event::EndOfInjectedConversion => self.rb.cr1.modify(|_, w| w.jeocie().set_bit()),
+                    }
+                }
+
+                /// Disables the interrupt for the given event
+                pub fn unlisten(&mut self, event: Event) {
+                    match event {
+                        Event::EndOfConversion => self.rb.cr1.modify(|_, w| w.eocie().clear_bit()),
+                        Event::EndOfInjectedConversion => self.rb.cr1.modify(|_, w| w.jeocie().clear_bit()),
+                    }
+                }
+
                 fn power_up(&mut self) {
                     self.rb.cr2.modify(|_, w| w.adon().set_bit());
 
@@ -367,6 +527,10 @@ macro_rules! adc_hal {
                   The check for `cr2.swstart.bit_is_set` *should* fix it, but
                   does not. Therefore, ensure you do not do any no-op modifications
                   to `cr2` just before calling this function
+
+                  The end of an injected group conversion sets EOC as well, so
+                  this must not run while the injected group can be started by
+                  an external trigger or from an interrupt
                 */
                 fn convert(&mut self, chan: u8) -> u16 {
                     // Dummy read in case something accidentally triggered
@@ -10,11 +10,34 @@
   Write access to the backup domain is enabled in RCC using the `rcc::Rcc::BKP::constrain()`
   function.
 
-  Only the RTC functionality is currently implemented.
+  The RTC calibration and clock output on PC13 as well as the tamper detection on the same pin
+  are configured here as well.
 */
 
 use crate::pac::BKP;
 
+/// Signal output on the TAMPER-RTC pin (PC13)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcOutput {
+    /// The pin is not used by the RTC
+    None,
+    /// RTC clock divided by 64, used to measure the clock for calibration
+    CalibrationClock,
+    /// Pulse on every RTC alarm
+    Alarm,
+    /// Pulse on every RTC second
+    Second,
+}
+
+/// Active level of the TAMPER pin (PC13)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TamperLevel {
+    /// A high level on the pin is a tamper event
+    High,
+    /// A low level on the pin is a tamper event
+    Low,
+}
+
 /**
   The existence of this struct indicates that writing to the the backup
   domain has been enabled. It is acquired by calling `constrain` on `rcc::Rcc::BKP`
@@ -71,4 +94,75 @@ impl BackupDomain {
     pub fn write_data_register_high(&self, register: usize, data: u16) {
         write_drx!(self, bkp_dr, register, data)
     }
+
+    /// Sets the RTC calibration value (CAL)
+    ///
+    /// The RTC clock is slowed down by skipping `cal` out of every 2^20 clock pulses, which is
+    /// about 0.954 ppm per step. The value has to be below 128.
+    pub fn set_rtc_calibration(&mut self, cal: u8) {
+        assert!(cal < 128);
+        self._regs.rtccr.modify(|_, w| unsafe { w.cal().bits(cal) });
+    }
+
+    /// Returns the RTC calibration value (CAL)
+    pub fn rtc_calibration(&self) -> u8 {
+        self._regs.rtccr.read().cal().bits()
+    }
+
+    /// Selects the signal output on the TAMPER-RTC pin (PC13)
+    ///
+    /// The output can not be used together with tamper detection.
+    pub fn set_rtc_output(&mut self, output: RtcOutput) {
+        self._regs.rtccr.modify(|_, w| match output {
+            RtcOutput::None => w.cco().clear_bit().asoe().clear_bit(),
+            RtcOutput::CalibrationClock => w.cco().set_bit().asoe().clear_bit(),
+            RtcOutput::Alarm => w.cco().clear_bit().asoe().set_bit().asos().alarm(),
+            RtcOutput::Second => w.cco().clear_bit().asoe().set_bit().asos().second(),
+        });
+    }
+
+    /// Enables tamper detection on the TAMPER pin (PC13)
+    ///
+    /// A tamper event resets all backup data registers and sets the tamper event flag. Any
+    /// pending tamper event is cleared first.
+    pub fn enable_tamper(&mut self, level: TamperLevel) {
+        self._regs.cr.modify(|_, w| w.tpe().clear_bit());
+        self.clear_tamper_event();
+        self._regs.cr.modify(|_, w| {
+            match level {
+                TamperLevel::High => w.tpal().high(),
+                TamperLevel::Low => w.tpal().low(),
+            };
+            w.tpe().set_bit()
+        });
+    }
+
+    /// Disables tamper detection, PC13 can be used as a GPIO again
+    pub fn disable_tamper(&mut self) {
+        self._regs.cr.modify(|_, w| w.tpe().clear_bit());
+    }
+
+    /// Enables the TAMPER interrupt on tamper events
+    pub fn listen_tamper(&mut self) {
+        self._regs.csr.modify(|_, w| w.tpie().set_bit());
+    }
+
+    /// Disables the TAMPER interrupt
+    pub fn unlisten_tamper(&mut self) {
+        self._regs.csr.modify(|_, w| w.tpie().clear_bit());
+    }
+
+    /// Returns `true` if a tamper event was detected (TEF)
+    pub fn is_tamper_event(&self) -> bool {
+        self._regs.csr.read().tef().bit_is_set()
+    }
+
+    /// Clears the tamper event and interrupt flags
+    ///
+    /// No tamper event can be detected while the flag is set.
+    pub fn clear_tamper_event(&mut self) {
+        self._regs
+            .csr
+            .modify(|_, w| w.cte().set_bit().cti().set_bit());
+    }
 }
@@ -0,0 +1,1042 @@
+//! # Firmware update support for bootloaders
+//!
+//! Flash is divided into an active slot, from which the application runs, and an update slot,
+//! to which the application writes a new image. Every image starts with an
+//! [ImageHeader](struct.ImageHeader.html) of `HEADER_SIZE` bytes, the vector table of the
+//! application follows the header, so the application has to be linked to
+//! `FLASH_START + slot.start + HEADER_SIZE`.
+//!
+//! A [Layout](struct.Layout.html) either swaps both slots page by page through a scratch page,
+//! which keeps the previous image in the update slot for a rollback, or copies the update slot
+//! over the active slot. Each step of an update is recorded by a
+//! [StateStore](trait.StateStore.html), in flash with [FlashState](struct.FlashState.html) or in
+//! a backup data register with [BackupState](struct.BackupState.html), and every step can be
+//! repeated, so an update interrupted by a reset or power loss is finished by the next
+//! [boot](struct.Bootloader.html#method.boot).
+//!
+//! After a swap, the new image has to call [confirm](struct.Bootloader.html#method.confirm),
+//! otherwise the next `boot` swaps the slots back.
+//!
+//! The logic is generic over [Storage](../eeprom/trait.Storage.html), which is implemented for
+//! [FlashWriter](../flash/struct.FlashWriter.html), and [Checksum](trait.Checksum.html), which is
+//! implemented for the CRC unit.
+//!
+//! ```rust
+//! let dp = pac::Peripherals::take().unwrap();
+//! let cp = cortex_m::Peripherals::take().unwrap();
+//! let mut rcc = dp.RCC.constrain();
+//! let mut flash = dp.FLASH.constrain();
+//! let crc = dp.CRC.new(&mut rcc.ahb);
+//!
+//! let layout = Layout::swap(Slot::new(0x4000, 0xC000), Slot::new(0x10000, 0xC000), 0x1C000);
+//! let writer = flash.writer(SectorSize::Sz1K, FlashSize::Sz128K);
+//! let state = FlashState::new(0x1D000, 3);
+//! let mut bootloader = Bootloader::new(writer, state, crc, layout).unwrap();
+//!
+//! if bootloader.boot().is_ok() {
+//!     let address = FLASH_START + layout.active.application();
+//!     drop(bootloader);
+//!     unsafe { jump_to_application(cp, address) };
+//! }
+//! ```
+
+use crate::backup_domain::BackupDomain;
+use crate::eeprom::Storage;
+
+/// Size of the image header, the vector table of the application follows it
+///
+/// The vector table has to be aligned to a power of two that covers all vectors, 512 bytes
+/// are enough for every device of the family.
+pub const HEADER_SIZE: u32 = 0x200;
+
+/// First word of a valid image header
+pub const IMAGE_MAGIC: u32 = 0xB007_10AD;
+
+/// Value of an erased half-word
+const ERASED: u16 = 0xFFFF;
+
+/// Checksum used to verify images
+///
+/// The image is fed as little-endian words. If the length of the image is not a multiple of
+/// four, the last word is padded with `0xFF` bytes.
+pub trait Checksum {
+    /// Starts a new checksum
+    fn reset(&mut self);
+
+    /// Adds a word to the checksum
+    fn write(&mut self, word: u32);
+
+    /// Returns the checksum of the words written since the last reset
+    fn read(&self) -> u32;
+}
+
+/// Bootloader errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    /// Error of the underlying storage
+    Storage(E),
+    /// The slot contains no image with a valid header and checksum
+    InvalidImage,
+    /// The state store contains an unknown state
+    InvalidState,
+    /// The state store has no room for another state
+    Full,
+    /// An update is in progress or the swapped image has not been confirmed yet
+    Busy,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Storage(e)
+    }
+}
+
+/// Flash area that holds an image
+///
+/// Offsets are relative to the start of the storage and page aligned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slot {
+    /// Offset of the first page
+    pub start: u32,
+    /// Size in bytes
+    pub size: u32,
+}
+
+impl Slot {
+    pub const fn new(start: u32, size: u32) -> Self {
+        Slot { start, size }
+    }
+
+    /// Returns the offset of the vector table of the image in this slot
+    pub const fn application(&self) -> u32 {
+        self.start + HEADER_SIZE
+    }
+
+    /// Returns `true` if both areas share at least one byte
+    fn overlaps(&self, other: &Slot) -> bool {
+        self.start < other.start + other.size && other.start < self.start + self.size
+    }
+}
+
+/// Arrangement of the slots
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    /// Slot from which the application runs
+    pub active: Slot,
+    /// Slot that receives new images
+    pub update: Slot,
+    /// Page used to swap the slots, `None` if the update slot is copied over the active slot
+    pub scratch: Option<u32>,
+}
+
+impl Layout {
+    /// Swaps the slots on an update, the previous image is restored if the new image is not
+    /// confirmed
+    pub const fn swap(active: Slot, update: Slot, scratch: u32) -> Self {
+        Layout {
+            active,
+            update,
+            scratch: Some(scratch),
+        }
+    }
+
+    /// Copies the update slot over the active slot on an update, the update slot is a staging
+    /// area and there is no rollback
+    pub const fn copy(active: Slot, update: Slot) -> Self {
+        Layout {
+            active,
+            update,
+            scratch: None,
+        }
+    }
+}
+
+/// Header at the start of every image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageHeader {
+    /// Version of the image, not interpreted by the bootloader
+    pub version: u32,
+    /// Length of the image after the header in bytes
+    pub length: u32,
+    /// Checksum of the image after the header
+    pub checksum: u32,
+}
+
+impl ImageHeader {
+    /// Returns the header as the half-words stored at the start of the slot
+    pub fn to_half_words(&self) -> [u16; 8] {
+        let mut half_words = [0; 8];
+        for (i, word) in [IMAGE_MAGIC, self.version, self.length, self.checksum]
+            .iter()
+            .enumerate()
+        {
+            half_words[2 * i] = *word as u16;
+            half_words[2 * i + 1] = (*word >> 16) as u16;
+        }
+        half_words
+    }
+}
+
+/// Progress of the bootloader
+///
+/// `page` and `step` name the next step of an update, every step is stored after it has been
+/// completed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// No update in progress
+    Idle,
+    /// The slots are being swapped to install a new image
+    Swap { page: u16, step: u8 },
+    /// The slots are being swapped back because the new image was not confirmed
+    Revert { page: u16, step: u8 },
+    /// The update slot is being copied over the active slot
+    Copy { page: u16 },
+    /// The swapped image runs, but has not been confirmed yet
+    Testing,
+}
+
+impl State {
+    /// Encodes the state as a half-word, valid states are never encoded as `0xFFFF`
+    pub fn to_bits(self) -> u16 {
+        match self {
+            State::Idle => 0x0000,
+            State::Swap { page, step } => 0x2000 | (step as u16) << 11 | page,
+            State::Revert { page, step } => 0x4000 | (step as u16) << 11 | page,
+            State::Copy { page } => 0x6000 | page,
+            State::Testing => 0x8000,
+        }
+    }
+
+    /// Decodes a half-word, `0x0000` and `0xFFFF` are `Idle`
+    pub fn from_bits(bits: u16) -> Option<Self> {
+        let page = bits & 0x07FF;
+        let step = (bits >> 11) as u8 & 0b11;
+        match bits >> 13 {
+            _ if bits == ERASED || bits == 0 => Some(State::Idle),
+            1 if step < 3 => Some(State::Swap { page, step }),
+            2 if step < 3 => Some(State::Revert { page, step }),
+            3 if step == 0 => Some(State::Copy { page }),
+            4 if bits == 0x8000 => Some(State::Testing),
+            _ => None,
+        }
+    }
+}
+
+/// Persistent store of the bootloader state
+pub trait StateStore<S>
+where
+    S: Storage,
+{
+    /// Returns the last stored state, `Idle` if nothing was stored
+    fn load(&mut self, storage: &S) -> Result<State, Error<S::Error>>;
+
+    /// Stores a new state
+    fn store(&mut self, storage: &mut S, state: State) -> Result<(), Error<S::Error>>;
+
+    /// Removes all stored states, only called before an update is started
+    fn clear(&mut self, storage: &mut S) -> Result<(), Error<S::Error>>;
+
+    /// Returns the storage area that holds the states, `None` if they are not kept in the
+    /// storage
+    fn area(&self, _page_size: u32) -> Option<Slot> {
+        None
+    }
+}
+
+/// State store in flash pages
+///
+/// Every state is appended as a half-word followed by its complement, the pages are only erased
+/// when a new update is requested. An update needs room for three states per page of a slot
+/// when copying or swapping, and for as many again when a swap is reverted.
+///
+/// An interrupted write leaves bits set that should have been cleared, so the last state does
+/// not match its complement, and the state before it is loaded. Every interrupted write takes
+/// an additional word, so the pages have to hold twice the number of states an update needs.
+pub struct FlashState {
+    start: u32,
+    pages: u32,
+}
+
+impl FlashState {
+    /// Uses `pages` consecutive pages starting at `start`, which must not overlap the slots
+    pub const fn new(start: u32, pages: u32) -> Self {
+        FlashState { start, pages }
+    }
+
+    /// Returns the offset after the last stored state
+    fn end<S: Storage>(&self, storage: &S) -> Result<u32, Error<S::Error>> {
+        let start = self.start;
+        let mut offset = start + self.pages * storage.page_size();
+        while offset > start
+            && storage.read_u16(offset - 4)? == ERASED
+            && storage.read_u16(offset - 2)? == ERASED
+        {
+            offset -= 4;
+        }
+        Ok(offset)
+    }
+
+    /// Reads the state at `offset`, `None` if it does not match its complement
+    fn read<S: Storage>(&self, storage: &S, offset: u32) -> Result<Option<State>, Error<S::Error>> {
+        let bits = storage.read_u16(offset)?;
+        if storage.read_u16(offset + 2)? == !bits {
+            Ok(State::from_bits(bits))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<S> StateStore<S> for FlashState
+where
+    S: Storage,
+{
+    fn load(&mut self, storage: &S) -> Result<State, Error<S::Error>> {
+        let end = self.end(storage)?;
+        if end == self.start {
+            return Ok(State::Idle);
+        }
+        match self.read(storage, end - 4)? {
+            Some(state) => Ok(state),
+            // A write interrupted by a reset leaves a word that is no state, every step can be
+            // repeated, so continue from the state before it
+            None if end - 4 == self.start => Ok(State::Idle),
+            None => self.read(storage, end - 8)?.ok_or(Error::InvalidState),
+        }
+    }
+
+    fn store(&mut self, storage: &mut S, state: State) -> Result<(), Error<S::Error>> {
+        let end = self.end(storage)?;
+        if end == self.start + self.pages * storage.page_size() {
+            return Err(Error::Full);
+        }
+        let bits = state.to_bits();
+        storage.write_u16(end, bits)?;
+        Ok(storage.write_u16(end + 2, !bits)?)
+    }
+
+    fn clear(&mut self, storage: &mut S) -> Result<(), Error<S::Error>> {
+        let page_size = storage.page_size();
+        for page in 0..self.pages {
+            let start = self.start + page * page_size;
+            let mut offset = 0;
+            while offset < page_size {
+                if storage.read_u16(start + offset)? != ERASED {
+                    storage.erase_page(start)?;
+                    break;
+                }
+                offset += 2;
+            }
+        }
+        Ok(())
+    }
+
+    fn area(&self, page_size: u32) -> Option<Slot> {
+        Some(Slot::new(self.start, self.pages * page_size))
+    }
+}
+
+/// State store in a backup data register
+///
+/// The register keeps its value as long as VDD or VBAT is powered.
+pub struct BackupState<'a> {
+    bkp: &'a BackupDomain,
+    register: usize,
+}
+
+impl<'a> BackupState<'a> {
+    /// Uses the low-density backup data register `register`, see
+    /// [write_data_register_low](../backup_domain/struct.BackupDomain.html#method.write_data_register_low)
+    pub fn new(bkp: &'a BackupDomain, register: usize) -> Self {
+        BackupState { bkp, register }
+    }
+}
+
+impl<'a, S> StateStore<S> for BackupState<'a>
+where
+    S: Storage,
+{
+    fn load(&mut self, _storage: &S) -> Result<State, Error<S::Error>> {
+        State::from_bits(self.bkp.read_data_register_low(self.register)).ok_or(Error::InvalidState)
+    }
+
+    fn store(&mut self, _storage: &mut S, state: State) -> Result<(), Error<S::Error>> {
+        self.bkp
+            .write_data_register_low(self.register, state.to_bits());
+        Ok(())
+    }
+
+    fn clear(&mut self, storage: &mut S) -> Result<(), Error<S::Error>> {
+        self.store(storage, State::Idle)
+    }
+}
+
+/// Firmware update state machine
+pub struct Bootloader<S, T, C> {
+    storage: S,
+    state: T,
+    checksum: C,
+    layout: Layout,
+    page_size: u32,
+}
+
+impl<S, T, C> Bootloader<S, T, C>
+where
+    S: Storage,
+    T: StateStore<S>,
+    C: Checksum,
+{
+    /// Creates the bootloader, no update step is performed before [boot](#method.boot)
+    pub fn new(storage: S, state: T, checksum: C, layout: Layout) -> Result<Self, Error<S::Error>> {
+        let page_size = storage.page_size();
+        let aligned = |offset: u32| offset & (page_size - 1) == 0;
+        for slot in &[layout.active, layout.update] {
+            assert!(
+                aligned(slot.start) && aligned(slot.size) && slot.size > HEADER_SIZE,
+                "slots have to be page aligned"
+            );
+            assert!(slot.size / page_size <= 0x07FF, "slot has too many pages");
+        }
+        assert!(
+            !layout.active.overlaps(&layout.update),
+            "slots must not overlap"
+        );
+        let slots = [layout.active, layout.update];
+        assert!(
+            layout.update.size <= layout.active.size,
+            "update slot must fit into the active slot"
+        );
+        if let Some(scratch) = layout.scratch {
+            assert!(aligned(scratch), "scratch page has to be page aligned");
+            assert!(
+                layout.active.size == layout.update.size,
+                "swapped slots have to be of the same size"
+            );
+            let scratch = Slot::new(scratch, page_size);
+            assert!(
+                slots.iter().all(|slot| !slot.overlaps(&scratch)),
+                "scratch page must not overlap the slots"
+            );
+        }
+        if let Some(area) = state.area(page_size) {
+            assert!(
+                slots.iter().all(|slot| !slot.overlaps(&area)),
+                "state pages must not overlap the slots"
+            );
+            if let Some(scratch) = layout.scratch {
+                assert!(
+                    !area.overlaps(&Slot::new(scratch, page_size)),
+                    "state pages must not overlap the scratch page"
+                );
+            }
+            // Every page takes three states to swap and three more to revert, plus the
+            // states that start the swap and the revert. The area is never compacted during an
+            // update, so there is room for every one of these writes to be interrupted once.
+            let pages = layout.active.size / page_size;
+            assert!(
+                area.size / 4 >= 2 * (6 * pages + 2),
+                "state pages are too small for an update"
+            );
+        }
+
+        Ok(Bootloader {
+            storage,
+            state,
+            checksum,
+            layout,
+            page_size,
+        })
+    }
+
+    /// Returns the current state
+    ///
+    /// Fails with `InvalidState` if the state names a page outside of the slots.
+    pub fn state(&mut self) -> Result<State, Error<S::Error>> {
+        let state = self.state.load(&self.storage)?;
+        let pages = |slot: Slot| slot.size / self.page_size;
+        let valid = match state {
+            State::Swap { page, .. } | State::Revert { page, .. } => {
+                (page as u32) < pages(self.layout.active)
+            }
+            State::Copy { page } => (page as u32) < pages(self.layout.update),
+            State::Idle | State::Testing => true,
+        };
+        if valid {
+            Ok(state)
+        } else {
+            Err(Error::InvalidState)
+        }
+    }
+
+    /// Reads the header of the image in `slot`, `None` if the slot holds no image
+    pub fn header(&self, slot: &Slot) -> Result<Option<ImageHeader>, Error<S::Error>> {
+        if self.read_u32(slot.start)? != IMAGE_MAGIC {
+            return Ok(None);
+        }
+        let header = ImageHeader {
+            version: self.read_u32(slot.start + 4)?,
+            length: self.read_u32(slot.start + 8)?,
+            checksum: self.read_u32(slot.start + 12)?,
+        };
+        if header.length > slot.size - HEADER_SIZE {
+            return Ok(None);
+        }
+        Ok(Some(header))
+    }
+
+    /// Returns the header of the image in `slot` if the checksum of the image matches
+    pub fn verify(&mut self, slot: &Slot) -> Result<ImageHeader, Error<S::Error>> {
+        let header = self.header(slot)?.ok_or(Error::InvalidImage)?;
+
+        self.checksum.reset();
+        let start = slot.application();
+        let mut offset = 0;
+        while offset < header.length {
+            let mut word = self.read_u32(start + offset)?;
+            let remaining = header.length - offset;
+            if remaining < 4 {
+                word |= !0 << (8 * remaining);
+            }
+            self.checksum.write(word);
+            offset += 4;
+        }
+
+        if self.checksum.read() == header.checksum {
+            Ok(header)
+        } else {
+            Err(Error::InvalidImage)
+        }
+    }
+
+    /// Starts installing the image in the update slot with the next [boot](#method.boot)
+    ///
+    /// Fails with `Busy` while an update is in progress or a swapped image is not confirmed.
+    pub fn request_update(&mut self) -> Result<(), Error<S::Error>> {
+        if self.state()? != State::Idle {
+            return Err(Error::Busy);
+        }
+        let update = self.layout.update;
+        self.verify(&update)?;
+
+        // Nothing has been changed yet, losing power before the first state is stored
+        // leaves the bootloader idle
+        self.state.clear(&mut self.storage)?;
+        let first = if self.layout.scratch.is_some() {
+            State::Swap { page: 0, step: 0 }
+        } else {
+            State::Copy { page: 0 }
+        };
+        self.state.store(&mut self.storage, first)
+    }
+
+    /// Marks the running image as good, so the next boot does not revert the swap
+    pub fn confirm(&mut self) -> Result<(), Error<S::Error>> {
+        if self.state()? == State::Testing {
+            self.state.store(&mut self.storage, State::Idle)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes an interrupted update, performs a requested update or reverts an image that
+    /// was not confirmed, and returns the header of the verified image in the active slot
+    ///
+    /// The application starts at [Slot::application](struct.Slot.html#method.application) of
+    /// the active slot.
+    pub fn boot(&mut self) -> Result<ImageHeader, Error<S::Error>> {
+        loop {
+            let next = match self.state()? {
+                State::Idle => break,
+                State::Swap { page, step } => self.swap_step(page, step, false)?,
+                State::Revert { page, step } => self.swap_step(page, step, true)?,
+                State::Copy { page } => self.copy_step(page)?,
+                State::Testing => {
+                    // The image was booted once and did not confirm itself
+                    State::Revert { page: 0, step: 0 }
+                }
+            };
+            self.state.store(&mut self.storage, next)?;
+            if next == State::Testing {
+                break;
+            }
+        }
+
+        let active = self.layout.active;
+        self.verify(&active)
+    }
+
+    /// Releases the storage, the state store and the checksum
+    pub fn release(self) -> (S, T, C) {
+        (self.storage, self.state, self.checksum)
+    }
+
+    /// Performs one step of swapping `page` of both slots and returns the next state
+    ///
+    /// Step 0 saves the active page to the scratch page, step 1 copies the update page to the
+    /// active page and step 2 copies the scratch page to the update page.
+    fn swap_step(&mut self, page: u16, step: u8, revert: bool) -> Result<State, Error<S::Error>> {
+        let scratch = self.layout.scratch.ok_or(Error::InvalidState)?;
+        let active = self.layout.active.start + page as u32 * self.page_size;
+        let update = self.layout.update.start + page as u32 * self.page_size;
+
+        match step {
+            0 => self.copy_page(active, scratch)?,
+            1 => self.copy_page(update, active)?,
+            _ => self.copy_page(scratch, update)?,
+        }
+
+        let pages = self.layout.active.size / self.page_size;
+        Ok(if step < 2 {
+            let step = step + 1;
+            if revert {
+                State::Revert { page, step }
+            } else {
+                State::Swap { page, step }
+            }
+        } else if (page as u32) + 1 < pages {
+            let page = page + 1;
+            if revert {
+                State::Revert { page, step: 0 }
+            } else {
+                State::Swap { page, step: 0 }
+            }
+        } else if revert {
+            State::Idle
+        } else {
+            State::Testing
+        })
+    }
+
+    /// Copies `page` of the update slot to the active slot and returns the next state
+    fn copy_step(&mut self, page: u16) -> Result<State, Error<S::Error>> {
+        let update = self.layout.update;
+        let header = self.header(&update)?.ok_or(Error::InvalidImage)?;
+
+        let offset = page as u32 * self.page_size;
+        self.copy_page(update.start + offset, self.layout.active.start + offset)?;
+
+        let used = HEADER_SIZE + header.length;
+        Ok(if offset + self.page_size < used {
+            State::Copy { page: page + 1 }
+        } else {
+            State::Idle
+        })
+    }
+
+    /// Erases the page at `to` and copies the page at `from` to it
+    fn copy_page(&mut self, from: u32, to: u32) -> Result<(), Error<S::Error>> {
+        self.storage.erase_page(to)?;
+        let mut offset = 0;
+        while offset < self.page_size {
+            let value = self.storage.read_u16(from + offset)?;
+            if value != ERASED {
+                self.storage.write_u16(to + offset, value)?;
+            }
+            offset += 2;
+        }
+        Ok(())
+    }
+
+    fn read_u32(&self, offset: u32) -> Result<u32, Error<S::Error>> {
+        let low = self.storage.read_u16(offset)? as u32;
+        let high = self.storage.read_u16(offset + 2)? as u32;
+        Ok(high << 16 | low)
+    }
+}
+
+/// Starts the application whose vector table is at `address`
+///
+/// Disables all interrupts and the SysTick timer, resets all peripherals on the APB buses and,
+/// on connectivity line devices, on the AHB bus, restores the reset value of the AHB clock
+/// enables, switches SYSCLK back to HSI and disables HSE and the PLLs, so the application starts
+/// with the clock configuration after reset. Then VTOR is set to `address`, the main stack pointer
+/// is loaded from the first entry of the vector table and the reset handler is called.
+///
+/// Only available when building for ARM targets.
+///
+/// # Safety
+///
+/// `address` has to point to a valid vector table. Nothing may access peripherals after this
+/// function was called, which is why it takes the core peripherals.
+#[cfg(target_arch = "arm")]
+pub unsafe fn jump_to_application(mut cp: cortex_m::Peripherals, address: u32) -> ! {
+    cortex_m::interrupt::disable();
+
+    cp.SYST.disable_interrupt();
+    cp.SYST.disable_counter();
+    for (icer, icpr) in cp.NVIC.icer.iter().zip(cp.NVIC.icpr.iter()) {
+        icer.write(!0);
+        icpr.write(!0);
+    }
+
+    let rcc = &*crate::pac::RCC::ptr();
+    rcc.cr.modify(|_, w| w.hsion().set_bit());
+    while rcc.cr.read().hsirdy().bit_is_clear() {}
+    rcc.cfgr.reset();
+    while !rcc.cfgr.read().sws().is_hsi() {}
+    rcc.cr.modify(|_, w| {
+        w.hseon()
+            .clear_bit()
+            .csson()
+            .clear_bit()
+            .pllon()
+            .clear_bit()
+    });
+    #[cfg(feature = "connectivity")]
+    rcc.cr
+        .modify(|_, w| w.pll2on().clear_bit().pll3on().clear_bit());
+    #[cfg(feature = "connectivity")]
+    rcc.cfgr2.reset();
+    rcc.cir.reset();
+
+    rcc.apb1rstr.write(|w| w.bits(!0));
+    rcc.apb1rstr.reset();
+    rcc.apb2rstr.write(|w| w.bits(!0));
+    rcc.apb2rstr.reset();
+    rcc.apb1enr.reset();
+    rcc.apb2enr.reset();
+    #[cfg(feature = "connectivity")]
+    {
+        rcc.ahbrstr.write(|w| w.bits(!0));
+        rcc.ahbrstr.reset();
+    }
+    rcc.ahbenr.reset();
+
+    cp.SCB.vtor.write(address);
+    cortex_m::peripheral::SCB::clear_pendsv();
+    cortex_m::peripheral::SCB::clear_pendst();
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    cortex_m::interrupt::enable();
+
+    let sp = core::ptr::read_volatile(address as *const u32);
+    let reset = core::ptr::read_volatile((address + 4) as *const u32);
+    // Nothing may touch the stack between loading MSP and the branch
+    core::arch::asm!(
+        "msr MSP, {sp}",
+        "bx {reset}",
+        sp = in(reg) sp,
+        reset = in(reg) reset,
+        options(noreturn)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::eeprom::tests::{Fault, Ram};
+    use std::vec::Vec;
+
+    const PAGE_SIZE: u32 = 256;
+    const SLOT_SIZE: u32 = 0x400;
+    const ACTIVE: Slot = Slot::new(0, SLOT_SIZE);
+    const UPDATE: Slot = Slot::new(SLOT_SIZE, SLOT_SIZE);
+    const SCRATCH: u32 = 2 * SLOT_SIZE;
+    const STATE: u32 = SCRATCH + PAGE_SIZE;
+
+    /// Rotating sum, enough to tell the test images apart
+    struct Sum(u32);
+
+    impl Checksum for Sum {
+        fn reset(&mut self) {
+            self.0 = 0;
+        }
+
+        fn write(&mut self, word: u32) {
+            self.0 = self.0.rotate_left(5).wrapping_add(word);
+        }
+
+        fn read(&self) -> u32 {
+            self.0
+        }
+    }
+
+    type Loader<'a> = Bootloader<&'a mut Ram, FlashState, Sum>;
+
+    fn bootloader(ram: &mut Ram, layout: Layout) -> Loader<'_> {
+        Bootloader::new(ram, FlashState::new(STATE, 1), Sum(0), layout).unwrap()
+    }
+
+    fn ram() -> Ram {
+        Ram::new(STATE + PAGE_SIZE, PAGE_SIZE)
+    }
+
+    /// Contents of an image of `length` bytes, the version is part of every half-word
+    fn image(version: u32, length: u32) -> Vec<u16> {
+        (0..length)
+            .step_by(2)
+            .map(|byte| (version as u16) << 12 | (byte / 2) as u16)
+            .collect()
+    }
+
+    /// Writes an image with a valid header to `slot`
+    fn install(ram: &mut Ram, slot: &Slot, version: u32, length: u32) {
+        let data = image(version, length);
+        let mut sum = Sum(0);
+        for i in (0..data.len()).step_by(2) {
+            let low = data[i] as u32;
+            let high = data.get(i + 1).map_or(0xFFFF, |&high| high as u32);
+            let mut word = high << 16 | low;
+            if length & 3 != 0 && i + 2 >= data.len() {
+                word |= !0 << (8 * (length & 3));
+            }
+            sum.write(word);
+        }
+        let header = ImageHeader {
+            version,
+            length,
+            checksum: sum.read(),
+        };
+        let start = (slot.start / 2) as usize;
+        for word in &mut ram.mem[start..(start + slot.size as usize / 2)] {
+            *word = ERASED;
+        }
+        ram.mem[start..start + 8].copy_from_slice(&header.to_half_words());
+        let application = (slot.application() / 2) as usize;
+        ram.mem[application..application + data.len()].copy_from_slice(&data);
+    }
+
+    fn version(ram: &mut Ram, slot: &Slot) -> Option<u32> {
+        let mut bootloader = bootloader(ram, Layout::copy(ACTIVE, UPDATE));
+        bootloader.verify(slot).ok().map(|header| header.version)
+    }
+
+    /// Installs version 1 in the active slot and requests an update to version 2
+    fn requested(layout: Layout) -> Ram {
+        let mut ram = ram();
+        install(&mut ram, &ACTIVE, 1, 300);
+        install(&mut ram, &UPDATE, 2, 403);
+        bootloader(&mut ram, layout).request_update().unwrap();
+        ram
+    }
+
+    #[test]
+    fn swap() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        let mut ram = requested(layout);
+
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.request_update(), Err(Error::Busy));
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(2));
+        assert_eq!(bootloader.state(), Ok(State::Testing));
+        bootloader.confirm().unwrap();
+        assert_eq!(bootloader.state(), Ok(State::Idle));
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(2));
+
+        assert_eq!(version(&mut ram, &UPDATE), Some(1));
+    }
+
+    #[test]
+    fn copy() {
+        let layout = Layout::copy(ACTIVE, UPDATE);
+        let mut ram = requested(layout);
+
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(2));
+        assert_eq!(bootloader.state(), Ok(State::Idle));
+
+        assert_eq!(version(&mut ram, &UPDATE), Some(2));
+    }
+
+    #[test]
+    fn revert_unconfirmed() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        let mut ram = requested(layout);
+
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(2));
+        // The new image never confirms itself
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(1));
+        assert_eq!(bootloader.state(), Ok(State::Idle));
+
+        assert_eq!(version(&mut ram, &UPDATE), Some(2));
+    }
+
+    #[test]
+    fn invalid_update() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        let mut ram = ram();
+        install(&mut ram, &ACTIVE, 1, 300);
+        install(&mut ram, &UPDATE, 2, 300);
+        ram.mem[(UPDATE.application() / 2) as usize + 7] ^= 1;
+
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.request_update(), Err(Error::InvalidImage));
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(1));
+    }
+
+    /// Cuts the power after every erase or write of a boot and checks that the next boot ends
+    /// like an uninterrupted one
+    fn interrupted(layout: Layout, setup: impl Fn() -> Ram) {
+        let mut expected = setup();
+        bootloader(&mut expected, layout).boot().unwrap();
+
+        let mut cut = 0;
+        loop {
+            let mut ram = setup();
+            ram.cut_after(cut);
+            match bootloader(&mut ram, layout).boot() {
+                Err(Error::Storage(Fault::PowerLoss)) => {}
+                Ok(_) => break,
+                Err(e) => panic!("cut after {} steps: {:?}", cut, e),
+            }
+            ram.restore();
+            let mut loader = bootloader(&mut ram, layout);
+            loader.boot().unwrap();
+            let state = loader.state();
+            // A cut state write leaves an additional word in the state pages
+            let slots = (STATE / 2) as usize;
+            assert!(
+                ram.mem[..slots] == expected.mem[..slots],
+                "cut after {} steps",
+                cut
+            );
+            assert_eq!(
+                state,
+                bootloader(&mut expected, layout).state(),
+                "cut after {} steps",
+                cut
+            );
+            cut += 1;
+        }
+        assert!(cut > 0);
+    }
+
+    #[test]
+    fn swap_power_loss() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        interrupted(layout, || requested(layout));
+    }
+
+    #[test]
+    fn copy_power_loss() {
+        let layout = Layout::copy(ACTIVE, UPDATE);
+        interrupted(layout, || requested(layout));
+    }
+
+    #[test]
+    fn revert_power_loss() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        let tested = || {
+            let mut ram = requested(layout);
+            bootloader(&mut ram, layout).boot().unwrap();
+            ram
+        };
+        interrupted(layout, tested);
+    }
+
+    #[test]
+    fn torn_state() {
+        let layout = Layout::swap(ACTIVE, UPDATE, SCRATCH);
+        let mut expected = requested(layout);
+        bootloader(&mut expected, layout).boot().unwrap();
+
+        let mut ram = requested(layout);
+        ram.cut_after(5);
+        assert_eq!(
+            bootloader(&mut ram, layout).boot(),
+            Err(Error::Storage(Fault::PowerLoss))
+        );
+        ram.restore();
+        // The next state write was cut while programming its bits
+        let state = (STATE / 2) as usize;
+        let end = ram.mem[state..].iter().position(|&bits| bits == ERASED);
+        ram.mem[state + end.unwrap()] = 0x7FFF;
+
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.boot().map(|header| header.version), Ok(2));
+        assert_eq!(bootloader.state(), Ok(State::Testing));
+        assert!(ram.mem[..state] == expected.mem[..state]);
+    }
+
+    /// Writes `previous` and the start of `state` with the bits of one of both half-words only
+    /// partially programmed, for every combination of bits that could be left set
+    #[test]
+    fn torn_state_bits() {
+        let pages = (SLOT_SIZE / PAGE_SIZE) as u16;
+        let mut states = Vec::new();
+        states.push(State::Idle);
+        states.push(State::Testing);
+        for page in 0..pages {
+            states.push(State::Copy { page });
+            for step in 0..3 {
+                states.push(State::Swap { page, step });
+                states.push(State::Revert { page, step });
+            }
+        }
+
+        let previous = State::Swap { page: 1, step: 2 };
+        let mut ram = ram();
+        let mut store = FlashState::new(STATE, 1);
+        store.store(&mut &mut ram, previous).unwrap();
+        let offset = (STATE / 2) as usize + 2;
+        for state in states {
+            let bits = state.to_bits();
+            for &complement in &[false, true] {
+                let intended = if complement { !bits } else { bits };
+                let unprogrammed = !intended;
+                let mut left = unprogrammed;
+                while left != 0 {
+                    if complement {
+                        ram.mem[offset] = bits;
+                        ram.mem[offset + 1] = !bits | left;
+                    } else {
+                        ram.mem[offset] = bits | left;
+                        ram.mem[offset + 1] = ERASED;
+                    }
+                    if ram.mem[offset..offset + 2] != [ERASED, ERASED] {
+                        let loaded = store.load(&&mut ram);
+                        assert_eq!(loaded, Ok(previous), "{:?} {:04x}", state, left);
+                    }
+                    left = (left - 1) & unprogrammed;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn state_out_of_slots() {
+        let layout = Layout::copy(ACTIVE, UPDATE);
+        let mut ram = ram();
+        let page = (SLOT_SIZE / PAGE_SIZE) as u16;
+        FlashState::new(STATE, 1)
+            .store(&mut &mut ram, State::Copy { page })
+            .unwrap();
+        let mut bootloader = bootloader(&mut ram, layout);
+        assert_eq!(bootloader.state(), Err(Error::InvalidState));
+        assert_eq!(bootloader.boot(), Err(Error::InvalidState));
+    }
+
+    #[test]
+    #[should_panic(expected = "scratch page must not overlap the slots")]
+    fn scratch_overlap() {
+        let mut ram = ram();
+        bootloader(&mut ram, Layout::swap(ACTIVE, UPDATE, UPDATE.start));
+    }
+
+    #[test]
+    #[should_panic(expected = "update slot must fit into the active slot")]
+    fn copy_into_smaller_slot() {
+        let mut ram = ram();
+        let active = Slot::new(0, SLOT_SIZE - PAGE_SIZE);
+        bootloader(&mut ram, Layout::copy(active, UPDATE));
+    }
+
+    #[test]
+    #[should_panic(expected = "state pages must not overlap the slots")]
+    fn state_overlap() {
+        let mut ram = ram();
+        let layout = Layout::copy(ACTIVE, UPDATE);
+        Bootloader::new(&mut ram, FlashState::new(ACTIVE.start, 1), Sum(0), layout).ok();
+    }
+
+    #[test]
+    #[should_panic(expected = "state pages are too small for an update")]
+    fn state_capacity() {
+        let mut ram = Ram::new(0x4000, PAGE_SIZE);
+        let layout = Layout::swap(Slot::new(0, 0x1800), Slot::new(0x1800, 0x1800), 0x3000);
+        Bootloader::new(&mut ram, FlashState::new(0x3100, 1), Sum(0), layout).ok();
+    }
+}
@@ -34,6 +34,7 @@ use crate::pac::CAN2;
 #[cfg(not(feature = "connectivity"))]
 use crate::pac::USB;
 use crate::rcc::APB1;
+use crate::time::{Bps, Hertz};
 use core::{
     cmp::{Ord, Ordering},
     convert::{Infallible, TryInto},
@@ -316,6 +317,189 @@ pub const NUM_FILTER_BANKS: usize = 14;
 #[cfg(feature = "connectivity")]
 pub const NUM_FILTER_BANKS: usize = 28;
 
+/// Error returned when no bit timing can be found for the requested bitrate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitTimingError {
+    /// The bitrate is zero or larger than the peripheral clock allows.
+    InvalidBitrate,
+    /// The sample point is outside the range 50.0%..=90.0%.
+    InvalidSamplePoint,
+    /// The synchronization jump width is outside the range 1..=4.
+    InvalidSjw,
+    /// The bitrate cannot be generated exactly from the peripheral clock.
+    NoSolution,
+}
+
+/// Bit timing parameters of the CAN peripheral.
+///
+/// Use `CanBitrate` to calculate them from a target bitrate and pass them to
+/// `CanConfig::set_bitrate()`. The fields can only be set by `CanBitrate`, so
+/// they are always in range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitTiming {
+    prescaler: u16,
+    seg1: u8,
+    seg2: u8,
+    sjw: u8,
+}
+
+impl BitTiming {
+    /// Returns the baud rate prescaler (1..=1024).
+    pub fn prescaler(&self) -> u16 {
+        self.prescaler
+    }
+
+    /// Returns the time quanta in bit segment 1, including the propagation
+    /// segment (1..=16).
+    pub fn seg1(&self) -> u8 {
+        self.seg1
+    }
+
+    /// Returns the time quanta in bit segment 2 (1..=8).
+    pub fn seg2(&self) -> u8 {
+        self.seg2
+    }
+
+    /// Returns the resynchronization jump width in time quanta (1..=4).
+    pub fn sjw(&self) -> u8 {
+        self.sjw
+    }
+
+    /// Returns the value of the BTR register without the mode bits.
+    pub fn btr(&self) -> u32 {
+        (u32::from(self.sjw) - 1) << 24
+            | (u32::from(self.seg2) - 1) << 20
+            | (u32::from(self.seg1) - 1) << 16
+            | (u32::from(self.prescaler) - 1)
+    }
+
+    /// Returns the number of time quanta per bit.
+    pub fn time_quanta(&self) -> u32 {
+        1 + u32::from(self.seg1) + u32::from(self.seg2)
+    }
+
+    /// Returns the sample point in tenths of a percent (875 = 87.5%).
+    pub fn sample_point(&self) -> u32 {
+        (1 + u32::from(self.seg1)) * 1000 / self.time_quanta()
+    }
+}
+
+/// Calculates the CAN bit timing for a target bitrate.
+///
+/// ```rust
+/// let timing = CanBitrate::new(clocks.pclk1(), 500_000.bps())
+///     .sample_point(875)
+///     .calculate()
+///     .unwrap();
+/// ```
+///
+/// The calculation does not touch any registers.
+#[derive(Clone, Copy, Debug)]
+pub struct CanBitrate {
+    pclk1: Hertz,
+    bitrate: Bps,
+    sample_point: u32,
+    sjw: u8,
+}
+
+impl CanBitrate {
+    // Bit times with fewer time quanta make resynchronization too coarse.
+    const MIN_TIME_QUANTA: u32 = 8;
+    const MAX_TIME_QUANTA: u32 = 25;
+    // Sample points within 2.0% of the closest one are equally good.
+    const SAMPLE_POINT_TOLERANCE: u32 = 20;
+
+    /// Creates a calculator for the APB1 (PCLK1) frequency `pclk1`.
+    ///
+    /// The sample point defaults to 87.5% and the synchronization jump width
+    /// to one time quantum.
+    pub fn new<F>(pclk1: F, bitrate: Bps) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        Self {
+            pclk1: pclk1.into(),
+            bitrate,
+            sample_point: 875,
+            sjw: 1,
+        }
+    }
+
+    /// Sets the desired sample point in tenths of a percent (875 = 87.5%).
+    pub fn sample_point(mut self, sample_point: u32) -> Self {
+        self.sample_point = sample_point;
+        self
+    }
+
+    /// Sets the resynchronization jump width in time quanta (1..=4).
+    ///
+    /// The value is limited to the length of bit segment 2.
+    pub fn sjw(mut self, sjw: u8) -> Self {
+        self.sjw = sjw;
+        self
+    }
+
+    /// Searches for the prescaler and bit segment lengths.
+    ///
+    /// Only timings that generate the bitrate exactly are considered. Of those
+    /// whose sample point is within 2.0% of the closest one to the requested
+    /// sample point, the one with the most time quanta per bit is returned.
+    pub fn calculate(&self) -> Result<BitTiming, BitTimingError> {
+        if !(500..=900).contains(&self.sample_point) {
+            return Err(BitTimingError::InvalidSamplePoint);
+        }
+        if !(1..=4).contains(&self.sjw) {
+            return Err(BitTimingError::InvalidSjw);
+        }
+
+        let bitrate = self.bitrate.0;
+        if bitrate == 0 || bitrate > self.pclk1.0 / Self::MIN_TIME_QUANTA {
+            return Err(BitTimingError::InvalidBitrate);
+        }
+
+        let candidates = || {
+            (Self::MIN_TIME_QUANTA..=Self::MAX_TIME_QUANTA)
+                .rev()
+                .filter_map(move |tq| self.timing(tq))
+        };
+        let min_error = candidates()
+            .map(|timing| self.error(&timing))
+            .min()
+            .ok_or(BitTimingError::NoSolution)?;
+        candidates()
+            .find(|timing| self.error(timing) <= min_error + Self::SAMPLE_POINT_TOLERANCE)
+            .ok_or(BitTimingError::NoSolution)
+    }
+
+    /// Returns the timing with `tq` time quanta per bit, if the bitrate can be
+    /// generated exactly with it.
+    fn timing(&self, tq: u32) -> Option<BitTiming> {
+        let tq_clk = self.bitrate.0 * tq;
+        let prescaler = self.pclk1.0 / tq_clk;
+        if prescaler * tq_clk != self.pclk1.0 || prescaler > 1024 {
+            return None;
+        }
+
+        // Sample point is at the end of bit segment 1, round to the nearest
+        // time quantum.
+        let seg1 = ((self.sample_point * tq + 500) / 1000).saturating_sub(1);
+        let seg1 = seg1.max((tq - 1).saturating_sub(8)).min(16);
+        let seg2 = tq - 1 - seg1;
+
+        Some(BitTiming {
+            prescaler: prescaler as u16,
+            seg1: seg1 as u8,
+            seg2: seg2 as u8,
+            sjw: self.sjw.min(seg2 as u8),
+        })
+    }
+
+    /// Returns the distance of the sample point of `timing` to the requested one.
+    fn error(&self, timing: &BitTiming) -> u32 {
+        timing.sample_point().abs_diff(self.sample_point)
+    }
+}
+
 /// Configuration proxy to be used with `Can::configure()`.
 pub struct CanConfig<Instance> {
     _can: PhantomData<Instance>,
@@ -336,6 +520,11 @@ where
         });
     }
 
+    /// Configures the bit timings from parameters calculated by `CanBitrate`.
+    pub fn set_bitrate(&mut self, timing: BitTiming) {
+        self.set_bit_timing(timing.btr());
+    }
+
     /// Enables or disables loopback mode: Internally connects the TX and RX
     /// signals together.
     pub fn set_loopback(&mut self, enabled: bool) {
@@ -1029,3 +1218,123 @@ where
         bb::clear(&can.ier, 4); // FMPIE1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::U32Ext;
+
+    fn check_exact(pclk1: u32, bitrate: u32, timing: BitTiming) {
+        assert_eq!(
+            pclk1 / (u32::from(timing.prescaler) * timing.time_quanta()),
+            bitrate
+        );
+        assert_eq!(
+            pclk1 % (u32::from(timing.prescaler) * timing.time_quanta()),
+            0
+        );
+        assert!((1..=16).contains(&timing.seg1));
+        assert!((1..=8).contains(&timing.seg2));
+        assert!((1..=4).contains(&timing.sjw) && timing.sjw <= timing.seg2);
+    }
+
+    #[test]
+    fn bitrate_500k_at_36mhz() {
+        let timing = CanBitrate::new(36.mhz(), 500_000.bps())
+            .calculate()
+            .unwrap();
+        check_exact(36_000_000, 500_000, timing);
+        // 8 time quanta hit 87.5% exactly, 18 time quanta are close enough
+        assert_eq!(timing.sample_point(), 888);
+        assert_eq!(
+            timing,
+            BitTiming {
+                prescaler: 4,
+                seg1: 15,
+                seg2: 2,
+                sjw: 1
+            }
+        );
+    }
+
+    #[test]
+    fn example_btr_values() {
+        // APB1 (PCLK1): 8MHz, Bit rate: 125kBit/s, Sample Point 87.5%
+        let timing = CanBitrate::new(8.mhz(), 125_000.bps()).calculate().unwrap();
+        assert_eq!(timing.sample_point(), 875);
+        assert_eq!(timing.btr(), 0x001c_0003);
+
+        // APB1 (PCLK1): 16MHz, Bit rate: 1000kBit/s, Sample Point 87.5%
+        let timing = CanBitrate::new(16.mhz(), 1_000_000.bps())
+            .calculate()
+            .unwrap();
+        assert_eq!(timing.sample_point(), 875);
+        assert_eq!(timing.btr(), 0x001c_0000);
+    }
+
+    #[test]
+    fn common_bitrates() {
+        for &pclk1 in &[8_000_000, 24_000_000, 36_000_000] {
+            for &bitrate in &[10_000, 20_000, 50_000, 125_000, 250_000, 500_000, 1_000_000] {
+                if bitrate * 8 > pclk1 {
+                    continue;
+                }
+                let timing = CanBitrate::new(pclk1.hz(), bitrate.bps())
+                    .sample_point(800)
+                    .sjw(2)
+                    .calculate()
+                    .unwrap();
+                check_exact(pclk1, bitrate, timing);
+                let sample_point = timing.sample_point();
+                assert!((700..=900).contains(&sample_point), "{}", sample_point);
+            }
+        }
+    }
+
+    #[test]
+    fn sjw_limited_to_seg2() {
+        let timing = CanBitrate::new(36.mhz(), 500_000.bps())
+            .sjw(4)
+            .calculate()
+            .unwrap();
+        assert_eq!(timing.sjw, timing.seg2);
+    }
+
+    #[test]
+    fn invalid_parameters() {
+        let can = CanBitrate::new(36.mhz(), 500_000.bps());
+        assert_eq!(
+            can.sample_point(499).calculate(),
+            Err(BitTimingError::InvalidSamplePoint)
+        );
+        assert_eq!(
+            can.sample_point(901).calculate(),
+            Err(BitTimingError::InvalidSamplePoint)
+        );
+        assert_eq!(can.sjw(0).calculate(), Err(BitTimingError::InvalidSjw));
+        assert_eq!(can.sjw(5).calculate(), Err(BitTimingError::InvalidSjw));
+    }
+
+    #[test]
+    fn unreachable_bitrates() {
+        // Zero and faster than 8 time quanta per bit
+        assert_eq!(
+            CanBitrate::new(36.mhz(), 0.bps()).calculate(),
+            Err(BitTimingError::InvalidBitrate)
+        );
+        assert_eq!(
+            CanBitrate::new(8.mhz(), 1_000_001.bps()).calculate(),
+            Err(BitTimingError::InvalidBitrate)
+        );
+        // Not an integer division of the clock
+        assert_eq!(
+            CanBitrate::new(36.mhz(), 33_333.bps()).calculate(),
+            Err(BitTimingError::NoSolution)
+        );
+        // Would need a prescaler above 1024
+        assert_eq!(
+            CanBitrate::new(36.mhz(), 1_000.bps()).calculate(),
+            Err(BitTimingError::NoSolution)
+        );
+    }
+}
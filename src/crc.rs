@@ -1,8 +1,33 @@
 //! CRC
+//!
+//! The CRC unit computes a CRC-32 with the polynomial `0x04C11DB7` and the initial value
+//! `0xFFFFFFFF` over 32-bit words, most significant bit first. [write](struct.Crc.html#method.write)
+//! and [read](struct.Crc.html#method.read) use it directly.
+//!
+//! [digest](struct.Crc.html#method.digest) computes the CRC of a byte stream. Bytes that do not
+//! fill a word are handled in software, and with [Config::crc32](struct.Config.html#method.crc32)
+//! the result matches the CRC-32 of zlib, Ethernet and PNG.
+//!
+//! Large buffers of words can be fed with a memory-to-memory DMA transfer by
+//! [write_dma](struct.Crc.html#method.write_dma). The DMA cannot reflect the data, so it only
+//! computes the word-wise CRC of the unit.
+//!
+//! ```rust
+//! let mut crc = dp.CRC.new(&mut rcc.ahb);
+//!
+//! let mut digest = crc.digest(Config::crc32());
+//! digest.update(b"1234");
+//! digest.update(b"56789");
+//! assert_eq!(digest.finish(), 0xCBF4_3926);
+//! ```
 
+use crate::dma::{self, MemToMem};
 use crate::pac::CRC;
 use crate::rcc::{Enable, AHB};
 
+/// Generator polynomial of the CRC unit
+const POLYNOMIAL: u32 = 0x04C1_1DB7;
+
 /// Extension trait to constrain the CRC peripheral
 pub trait CrcExt {
     /// Constrains the CRC peripheral to play nicely with the other abstractions
@@ -16,6 +41,38 @@ impl CrcExt for CRC {
     }
 }
 
+/// Parameters of a byte stream CRC
+///
+/// The polynomial and the initial value are fixed by the hardware. The default configuration
+/// is CRC-32/MPEG-2.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Config {
+    /// Reflect every input byte and the result
+    pub reflect: bool,
+    /// Value XORed with the result
+    pub final_xor: u32,
+}
+
+impl Config {
+    /// CRC-32 as used by zlib, Ethernet and PNG
+    pub fn crc32() -> Config {
+        Config {
+            reflect: true,
+            final_xor: 0xFFFF_FFFF,
+        }
+    }
+
+    pub fn reflect(mut self, reflect: bool) -> Self {
+        self.reflect = reflect;
+        self
+    }
+
+    pub fn final_xor(mut self, final_xor: u32) -> Self {
+        self.final_xor = final_xor;
+        self
+    }
+}
+
 /// Constrained CRC peripheral
 pub struct Crc {
     crc: CRC,
@@ -36,4 +93,250 @@ impl Crc {
         // inserting single nop() seems to solve the problem.
         cortex_m::asm::nop();
     }
+
+    /// Writes every word of `data` to the CRC unit with a memory-to-memory DMA transfer
+    ///
+    /// The result is the same as calling [write](#method.write) for every word, the CPU waits
+    /// until the transfer is complete. If the DMA reports a bus error, the transfer is aborted
+    /// and the CRC only covers part of `data`.
+    pub fn write_dma<CHANNEL>(
+        &mut self,
+        channel: &mut CHANNEL,
+        data: &[u32],
+    ) -> Result<(), dma::Error>
+    where
+        CHANNEL: MemToMem,
+    {
+        // NOTE(unsafe) the data register of the CRC unit accepts any word
+        unsafe { channel.write_words(data, &self.crc.dr as *const _ as u32) }
+    }
+
+    /// Reads the independent data register
+    ///
+    /// The register is not used by the CRC unit and not changed by [reset](#method.reset), it
+    /// is free to hold one byte of scratch data.
+    pub fn read_idr(&self) -> u8 {
+        self.crc.idr.read().idr().bits()
+    }
+
+    /// Writes the independent data register
+    pub fn write_idr(&mut self, value: u8) {
+        self.crc.idr.write(|w| w.idr().bits(value))
+    }
+
+    /// Resets the CRC unit and starts the CRC of a byte stream
+    pub fn digest(&mut self, config: Config) -> Digest<'_> {
+        self.reset();
+        Digest {
+            crc: self,
+            stream: Stream::new(config),
+        }
+    }
+
+    /// Returns the CRC of `data`
+    pub fn checksum(&mut self, config: Config, data: &[u8]) -> u32 {
+        let mut digest = self.digest(config);
+        digest.update(data);
+        digest.finish()
+    }
+}
+
+/// CRC of a byte stream
+///
+/// Complete words are written to the CRC unit, the CRC of the remaining bytes is computed in
+/// software by [finish](#method.finish).
+pub struct Digest<'a> {
+    crc: &'a mut Crc,
+    stream: Stream,
+}
+
+impl<'a> Digest<'a> {
+    /// Adds `data` to the CRC
+    pub fn update(&mut self, data: &[u8]) {
+        let crc = &mut *self.crc;
+        self.stream.update(data, |word| crc.write(word));
+    }
+
+    /// Returns the CRC of all bytes added
+    pub fn finish(self) -> u32 {
+        self.stream.finish(self.crc.read())
+    }
+}
+
+/// Splits a byte stream into the words written to the CRC unit
+struct Stream {
+    config: Config,
+    pending: [u8; 4],
+    pending_len: usize,
+}
+
+impl Stream {
+    fn new(config: Config) -> Self {
+        Stream {
+            config,
+            pending: [0; 4],
+            pending_len: 0,
+        }
+    }
+
+    /// Passes every completed word of the stream to `write`
+    fn update(&mut self, mut data: &[u8], mut write: impl FnMut(u32)) {
+        if self.pending_len > 0 {
+            while self.pending_len < 4 && !data.is_empty() {
+                self.pending[self.pending_len] = data[0];
+                self.pending_len += 1;
+                data = &data[1..];
+            }
+            if self.pending_len < 4 {
+                return;
+            }
+            write(word(self.pending, self.config.reflect));
+            self.pending_len = 0;
+        }
+
+        let mut words = data.chunks_exact(4);
+        for bytes in &mut words {
+            write(word(
+                [bytes[0], bytes[1], bytes[2], bytes[3]],
+                self.config.reflect,
+            ));
+        }
+
+        let remainder = words.remainder();
+        self.pending[..remainder.len()].copy_from_slice(remainder);
+        self.pending_len = remainder.len();
+    }
+
+    /// Returns the CRC of the stream, `crc` is the CRC of the unit over the written words
+    fn finish(&self, crc: u32) -> u32 {
+        finish(crc, &self.pending[..self.pending_len], self.config)
+    }
+}
+
+/// Returns the word written to the CRC unit for four bytes of the stream
+fn word(bytes: [u8; 4], reflect: bool) -> u32 {
+    if reflect {
+        u32::from_le_bytes(bytes).reverse_bits()
+    } else {
+        u32::from_be_bytes(bytes)
+    }
+}
+
+/// Adds the bytes that do not fill a word to the CRC `crc` of the unit and returns the result
+fn finish(mut crc: u32, bytes: &[u8], config: Config) -> u32 {
+    for &byte in bytes {
+        let byte = if config.reflect {
+            byte.reverse_bits()
+        } else {
+            byte
+        };
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+        }
+    }
+    if config.reflect {
+        crc = crc.reverse_bits();
+    }
+    crc ^ config.final_xor
+}
+
+impl crate::bootloader::Checksum for Crc {
+    fn reset(&mut self) {
+        Crc::reset(self)
+    }
+
+    fn write(&mut self, word: u32) {
+        Crc::write(self, word)
+    }
+
+    fn read(&self) -> u32 {
+        Crc::read(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    /// Model of the CRC unit
+    fn unit(mut crc: u32, word: u32) -> u32 {
+        crc ^= word;
+        for _ in 0..32 {
+            crc = if crc & 0x8000_0000 != 0 {
+                crc << 1 ^ POLYNOMIAL
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    }
+
+    fn checksum(config: Config, chunks: &[&[u8]]) -> u32 {
+        let mut crc = 0xFFFF_FFFF;
+        let mut stream = Stream::new(config);
+        for chunk in chunks {
+            stream.update(chunk, |word| crc = unit(crc, word));
+        }
+        stream.finish(crc)
+    }
+
+    /// Bytewise CRC-32 of zlib
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    crc >> 1 ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn check_values() {
+        assert_eq!(checksum(Config::crc32(), &[CHECK]), 0xCBF4_3926);
+        assert_eq!(checksum(Config::default(), &[CHECK]), 0x0376_E6E7);
+        assert_eq!(
+            checksum(Config::default().final_xor(0xFFFF_FFFF), &[CHECK]),
+            0xFC89_1918
+        );
+    }
+
+    #[test]
+    fn unaligned_tails() {
+        let data = b"The quick brown fox";
+        for len in 0..=data.len() {
+            let data = &data[..len];
+            assert_eq!(checksum(Config::crc32(), &[data]), crc32(data), "{}", len);
+        }
+    }
+
+    #[test]
+    fn split_updates() {
+        for split in 0..=CHECK.len() {
+            let (first, second) = CHECK.split_at(split);
+            assert_eq!(checksum(Config::crc32(), &[first, second]), 0xCBF4_3926);
+            assert_eq!(
+                checksum(Config::crc32(), &[first, &[], second, &[]]),
+                0xCBF4_3926
+            );
+        }
+        let bytes: [&[u8]; 9] = [b"1", b"2", b"3", b"4", b"5", b"6", b"7", b"8", b"9"];
+        assert_eq!(checksum(Config::crc32(), &bytes), 0xCBF4_3926);
+        assert_eq!(
+            checksum(Config::crc32(), &[b"12", b"345", b"6789"]),
+            0xCBF4_3926
+        );
+    }
 }
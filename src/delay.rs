@@ -21,6 +21,11 @@ impl Delay {
         Delay { syst, clocks }
     }
 
+    /// Updates the stored clock frequencies after the clocks have been reconfigured
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.clocks = *clocks;
+    }
+
     /// Releases the system timer (SysTick) resource
     pub fn free(self) -> SYST {
         self.syst
@@ -9,6 +9,8 @@ use crate::rcc::AHB;
 #[derive(Debug)]
 pub enum Error {
     Overrun,
+    /// A bus error occurred while accessing the memory or the peripheral
+    TransferError,
     #[doc(hidden)]
     _Extensible,
 }
@@ -43,6 +45,56 @@ impl<BUFFER, PAYLOAD> CircBuffer<BUFFER, PAYLOAD> {
     }
 }
 
+/// Circular reception into a single buffer that can be read at any point,
+/// e.g. when the line goes idle
+pub struct RingBuffer<BUFFER, PAYLOAD>
+where
+    BUFFER: 'static,
+{
+    buffer: &'static mut BUFFER,
+    payload: PAYLOAD,
+    capacity: usize,
+    read_pos: usize,
+}
+
+impl<BUFFER, PAYLOAD> RingBuffer<BUFFER, PAYLOAD> {
+    pub(crate) fn new(buf: &'static mut BUFFER, capacity: usize, payload: PAYLOAD) -> Self {
+        RingBuffer {
+            buffer: buf,
+            payload,
+            capacity,
+            read_pos: 0,
+        }
+    }
+}
+
+/// Returns whether the DMA passed the middle and the end of a ring buffer of `capacity` on its
+/// way from `read_pos` to `write_pos`
+///
+/// `half` and `complete` are the flags of the events since the last read. A flag that is set
+/// although the DMA did not pass its position belongs to an earlier lap, the DMA wrapped around
+/// and overwrote data that was not read yet.
+fn ring_laps(
+    capacity: usize,
+    read_pos: usize,
+    write_pos: usize,
+    half: bool,
+    complete: bool,
+) -> Result<(bool, bool), Error> {
+    let distance = |to: usize| (to + capacity - read_pos) % capacity;
+    let passed = |pos: usize| (1..=distance(write_pos)).contains(&distance(pos % capacity));
+
+    // the middle is at either side of it for odd lengths
+    let passed_half = passed(capacity / 2) || passed(capacity - capacity / 2);
+    let passed_end = passed(0);
+
+    if (half && !passed_half) || (complete && !passed_end) {
+        Err(Error::Overrun)
+    } else {
+        Ok((passed_half, passed_end))
+    }
+}
+
 pub trait Static<B> {
     fn borrow(&self) -> &B;
 }
@@ -72,8 +124,8 @@ pub trait TransferPayload {
 
 pub struct Transfer<MODE, BUFFER, PAYLOAD> {
     _mode: PhantomData<MODE>,
-    buffer: BUFFER,
-    payload: PAYLOAD,
+    pub(crate) buffer: BUFFER,
+    pub(crate) payload: PAYLOAD,
 }
 
 impl<BUFFER, PAYLOAD> Transfer<R, BUFFER, PAYLOAD> {
@@ -116,6 +168,7 @@ macro_rules! dma {
             $chX:ident,
             $htifX:ident,
             $tcifX:ident,
+            $teifX:ident,
             $chtifX:ident,
             $ctcifX:ident,
             $cgifX:ident
@@ -128,7 +181,7 @@ macro_rules! dma {
 
                 use crate::pac::{$DMAX, dma1};
 
-                use crate::dma::{CircBuffer, DmaExt, Error, Event, Half, Transfer, W, RxDma, TxDma, TransferPayload};
+                use crate::dma::{ring_laps, CircBuffer, DmaExt, Error, Event, Half, MemToMem, RingBuffer, Transfer, W, RxDma, TxDma, RxTxDma, TransferPayload};
                 use crate::rcc::{AHB, Enable};
 
                 pub struct Channels((), $(pub $CX),+);
@@ -176,6 +229,11 @@ macro_rules! dma {
                         pub fn in_progress(&self) -> bool {
                             self.isr().$tcifX().bit_is_clear()
                         }
+
+                        /// Returns `true` if the transfer was aborted by a bus error
+                        pub fn transfer_error(&self) -> bool {
+                            self.isr().$teifX().bit_is_set()
+                        }
                     }
 
                     impl $CX {
@@ -218,6 +276,39 @@ macro_rules! dma {
                         }
                     }
 
+                    impl MemToMem for $CX {
+                        unsafe fn write_words(&mut self, data: &[u32], address: u32) -> Result<(), Error> {
+                            for chunk in data.chunks(u16::MAX as usize) {
+                                self.stop();
+                                self.set_peripheral_address(address, false);
+                                self.set_memory_address(chunk.as_ptr() as u32, true);
+                                self.set_transfer_length(chunk.len());
+                                self.ch().cr.modify(|_, w| {
+                                    w.mem2mem().set_bit()
+                                        .dir().set_bit()
+                                        .circ().clear_bit()
+                                        .msize().bits32()
+                                        .psize().bits32()
+                                });
+
+                                atomic::compiler_fence(Ordering::Release);
+                                self.start();
+                                while self.in_progress() {
+                                    if self.transfer_error() {
+                                        // The channel is disabled by the hardware
+                                        self.stop();
+                                        self.ch().cr.modify(|_, w| w.mem2mem().clear_bit());
+                                        return Err(Error::TransferError);
+                                    }
+                                }
+                                atomic::compiler_fence(Ordering::Acquire);
+                            }
+                            self.stop();
+                            self.ch().cr.modify(|_, w| w.mem2mem().clear_bit());
+                            Ok(())
+                        }
+                    }
+
                     impl<B, PAYLOAD> CircBuffer<B, RxDma<PAYLOAD, $CX>>
                     where
                         RxDma<PAYLOAD, $CX>: TransferPayload,
@@ -294,6 +385,91 @@ macro_rules! dma {
                         }
                     }
 
+                    impl<B, PAYLOAD> RingBuffer<B, RxDma<PAYLOAD, $CX>>
+                    where
+                        RxDma<PAYLOAD, $CX>: TransferPayload,
+                    {
+                        /// Position in the buffer the DMA writes to next
+                        fn write_pos(&self) -> usize {
+                            let pending = self.payload.channel.get_ndtr() as usize;
+                            (self.capacity - pending) % self.capacity
+                        }
+
+                        /// Returns the write position and which of the half transfer and
+                        /// transfer complete events are part of the data since the last `read`
+                        fn laps(&self) -> Result<(usize, bool, bool), Error> {
+                            let isr = self.payload.channel.isr();
+                            let half = isr.$htifX().bit_is_set();
+                            let complete = isr.$tcifX().bit_is_set();
+                            // the flags have to be read before the position, so every flag
+                            // that is set belongs to the data before the write position
+                            let write_pos = self.write_pos();
+                            let (passed_half, passed_end) =
+                                ring_laps(self.capacity, self.read_pos, write_pos, half, complete)?;
+                            Ok((write_pos, passed_half, passed_end))
+                        }
+
+                        /// Returns the number of elements received since the last `read`
+                        ///
+                        /// Returns `Error::Overrun` if the DMA wrapped around and overwrote
+                        /// data that was not read yet, until the next `read`. This is only
+                        /// detected if `read` is called at least once for every half of the
+                        /// buffer that is received.
+                        pub fn available(&self) -> Result<usize, Error> {
+                            let (write_pos, _, _) = self.laps()?;
+                            Ok((write_pos + self.capacity - self.read_pos) % self.capacity)
+                        }
+
+                        /// Reads the elements received since the last `read`
+                        ///
+                        /// `f` is called with the received data. The second slice is only
+                        /// non-empty if the data wraps around the end of the buffer.
+                        ///
+                        /// Returns `Error::Overrun` without calling `f` if the DMA overwrote
+                        /// data that was not read yet, see [available](#method.available). The
+                        /// data received so far is dropped and the next `read` continues with
+                        /// the data received after this call.
+                        pub fn read<T, R, F>(&mut self, f: F) -> Result<R, Error>
+                        where
+                            B: as_slice::AsSlice<Element = T>,
+                            F: FnOnce(&[T], &[T]) -> R,
+                        {
+                            let laps = self.laps();
+                            let (write_pos, passed_half, passed_end) = match laps {
+                                Ok(laps) => laps,
+                                Err(_) => (self.write_pos(), true, true),
+                            };
+                            // clear the flags of the events since the last read, an event after
+                            // `write_pos` is flagged again or belongs to the next read
+                            if passed_half {
+                                self.payload.channel.ifcr().write(|w| w.$chtifX().set_bit());
+                            }
+                            if passed_end {
+                                self.payload.channel.ifcr().write(|w| w.$ctcifX().set_bit());
+                            }
+                            let read_pos = self.read_pos;
+                            self.read_pos = write_pos;
+                            laps?;
+
+                            // the DMA may still write into the buffer after this point
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            let slice = self.buffer.as_slice();
+                            Ok(if write_pos >= read_pos {
+                                f(&slice[read_pos..write_pos], &[])
+                            } else {
+                                f(&slice[read_pos..], &slice[..write_pos])
+                            })
+                        }
+
+                        /// Stops the transfer and returns the underlying buffer and RxDma
+                        pub fn stop(mut self) -> (&'static mut B, RxDma<PAYLOAD, $CX>) {
+                            self.payload.stop();
+
+                            (self.buffer, self.payload)
+                        }
+                    }
+
                     impl<BUFFER, PAYLOAD, MODE> Transfer<MODE, BUFFER, RxDma<PAYLOAD, $CX>>
                     where
                         RxDma<PAYLOAD, $CX>: TransferPayload,
@@ -346,6 +522,33 @@ macro_rules! dma {
                         }
                     }
 
+                    impl<BUFFER, PAYLOAD, MODE, TXC> Transfer<MODE, BUFFER, RxTxDma<PAYLOAD, $CX, TXC>>
+                    where
+                        RxTxDma<PAYLOAD, $CX, TXC>: TransferPayload,
+                    {
+                        pub fn is_done(&self) -> bool {
+                            // the receive channel always finishes after the transmit channel
+                            !self.payload.rxchannel.in_progress()
+                        }
+
+                        pub fn wait(mut self) -> (BUFFER, RxTxDma<PAYLOAD, $CX, TXC>) {
+                            while !self.is_done() {}
+
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            self.payload.stop();
+
+                            // we need a read here to make the Acquire fence effective
+                            // we do *not* need this if `dma.stop` does a RMW operation
+                            unsafe { ptr::read_volatile(&0); }
+
+                            // we need a fence here for the same reason we need one in `Transfer.wait`
+                            atomic::compiler_fence(Ordering::Acquire);
+
+                            (self.buffer, self.payload)
+                        }
+                    }
+
                     impl<BUFFER, PAYLOAD> Transfer<W, &'static mut BUFFER, RxDma<PAYLOAD, $CX>>
                     where
                         RxDma<PAYLOAD, $CX>: TransferPayload,
@@ -387,37 +590,37 @@ dma! {
     DMA1: (dma1, {
         C1: (
             ch1,
-            htif1, tcif1,
+            htif1, tcif1, teif1,
             chtif1, ctcif1, cgif1
         ),
         C2: (
             ch2,
-            htif2, tcif2,
+            htif2, tcif2, teif2,
             chtif2, ctcif2, cgif2
         ),
         C3: (
             ch3,
-            htif3, tcif3,
+            htif3, tcif3, teif3,
             chtif3, ctcif3, cgif3
         ),
         C4: (
             ch4,
-            htif4, tcif4,
+            htif4, tcif4, teif4,
             chtif4, ctcif4, cgif4
         ),
         C5: (
             ch5,
-            htif5, tcif5,
+            htif5, tcif5, teif5,
             chtif5, ctcif5, cgif5
         ),
         C6: (
             ch6,
-            htif6, tcif6,
+            htif6, tcif6, teif6,
             chtif6, ctcif6, cgif6
         ),
         C7: (
             ch7,
-            htif7, tcif7,
+            htif7, tcif7, teif7,
             chtif7, ctcif7, cgif7
         ),
     }),
@@ -425,27 +628,27 @@ dma! {
     DMA2: (dma2, {
         C1: (
             ch1,
-            htif1, tcif1,
+            htif1, tcif1, teif1,
             chtif1, ctcif1, cgif1
         ),
         C2: (
             ch2,
-            htif2, tcif2,
+            htif2, tcif2, teif2,
             chtif2, ctcif2, cgif2
         ),
         C3: (
             ch3,
-            htif3, tcif3,
+            htif3, tcif3, teif3,
             chtif3, ctcif3, cgif3
         ),
         C4: (
             ch4,
-            htif4, tcif4,
+            htif4, tcif4, teif4,
             chtif4, ctcif4, cgif4
         ),
         C5: (
             ch5,
-            htif5, tcif5,
+            htif5, tcif5, teif5,
             chtif5, ctcif5, cgif5
         ),
     }),
@@ -488,6 +691,17 @@ where
     fn circ_read(self, buffer: &'static mut [B; 2]) -> CircBuffer<B, Self>;
 }
 
+pub trait RingReadDma<B, RS>: Receive
+where
+    B: as_slice::AsMutSlice<Element = RS>,
+    Self: core::marker::Sized,
+{
+    /// Receives continuously into `buffer`, wrapping around at its end
+    ///
+    /// Panics if `buffer` is empty.
+    fn ring_read(self, buffer: &'static mut B) -> RingBuffer<B, Self>;
+}
+
 pub trait ReadDma<B, RS>: Receive
 where
     B: as_slice::AsMutSlice<Element = RS>,
@@ -496,6 +710,36 @@ where
     fn read(self, buffer: &'static mut B) -> Transfer<W, &'static mut B, Self>;
 }
 
+pub trait ReadWriteDma<RXB, TXB, TS>: Transmit
+where
+    RXB: as_slice::AsMutSlice<Element = TS>,
+    TXB: as_slice::AsSlice<Element = TS>,
+    Self: core::marker::Sized,
+{
+    /// Transmits `tx` and receives into `rx` at the same time
+    ///
+    /// Panics if the buffers are not of the same length.
+    fn read_write(
+        self,
+        rx: &'static mut RXB,
+        tx: &'static TXB,
+    ) -> Transfer<W, (&'static mut RXB, &'static TXB), Self>;
+}
+
+/// Memory-to-memory transfers, supported by every channel
+pub trait MemToMem {
+    /// Writes every word of `data` to the register at `address` and waits until the transfer is
+    /// complete
+    ///
+    /// Returns `TransferError` if the DMA reported a bus error, the transfer is aborted then.
+    ///
+    /// # Safety
+    ///
+    /// `address` has to be a register that accepts word writes, the DMA writes to it without
+    /// any further check.
+    unsafe fn write_words(&mut self, data: &[u32], address: u32) -> Result<(), Error>;
+}
+
 pub trait WriteDma<A, B, TS>: Transmit
 where
     A: as_slice::AsSlice<Element = TS>,
@@ -504,3 +748,31 @@ where
 {
     fn write(self, buffer: B) -> Transfer<R, B, Self>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_laps_within_buffer() {
+        assert_eq!(ring_laps(8, 0, 0, false, false).ok(), Some((false, false)));
+        assert_eq!(ring_laps(8, 0, 3, false, false).ok(), Some((false, false)));
+        assert_eq!(ring_laps(8, 0, 4, true, false).ok(), Some((true, false)));
+        // the flag may be set after the position was read
+        assert_eq!(ring_laps(8, 2, 4, false, false).ok(), Some((true, false)));
+        assert_eq!(ring_laps(8, 6, 0, false, true).ok(), Some((false, true)));
+        assert_eq!(ring_laps(8, 6, 5, true, true).ok(), Some((true, true)));
+        assert_eq!(ring_laps(7, 0, 3, true, false).ok(), Some((true, false)));
+        assert_eq!(ring_laps(7, 0, 4, true, false).ok(), Some((true, false)));
+    }
+
+    #[test]
+    fn ring_laps_overrun() {
+        // a whole lap without any new data
+        assert!(ring_laps(8, 2, 2, true, true).is_err());
+        assert!(ring_laps(8, 0, 3, false, true).is_err());
+        assert!(ring_laps(8, 5, 7, true, false).is_err());
+        assert!(ring_laps(8, 5, 2, true, true).is_err());
+        assert!(ring_laps(7, 4, 6, true, false).is_err());
+    }
+}
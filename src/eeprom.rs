@@ -0,0 +1,676 @@
+//! # EEPROM emulation
+//!
+//! Stores 16-bit values under 16-bit virtual addresses in two or more flash pages, following
+//! ST's application note AN2594.
+//!
+//! One page is active at a time. A write appends a record with the virtual address and the new
+//! value to the active page, a read returns the value of the last record with the requested
+//! address. When the active page is full, the latest value of every variable is copied to the
+//! next page, which then becomes active. Every page starts with a status half-word:
+//!
+//! - `0xFFFF`: the page is erased
+//! - `0xEEEE`: the page receives the variables of the active page during a page transfer
+//! - `0x0000`: the page is the active page
+//!
+//! Each step of a page transfer leaves the pages in a state that [Eeprom::new](struct.Eeprom.html#method.new)
+//! recognizes, so power can be lost at any point without losing variables.
+//!
+//! The logic is generic over [Storage](trait.Storage.html), which is implemented for
+//! [FlashWriter](../flash/struct.FlashWriter.html).
+//!
+//! ```rust
+//! let mut flash = dp.FLASH.constrain();
+//! let writer = flash.writer(SectorSize::Sz1K, FlashSize::Sz64K);
+//!
+//! // use the last two pages of the flash
+//! let mut eeprom = Eeprom::new(writer, 62 * 1024, 2).unwrap();
+//! eeprom.write(0x0001, 1234).unwrap();
+//! assert_eq!(eeprom.read(0x0001).unwrap(), Some(1234));
+//! ```
+
+/// Page status: the page is erased
+const ERASED: u16 = 0xFFFF;
+/// Page status: the page receives the variables during a page transfer
+const RECEIVE_DATA: u16 = 0xEEEE;
+/// Page status: the page is the active page
+const VALID_PAGE: u16 = 0x0000;
+
+/// Size of the page header and of every record in bytes
+const RECORD_SIZE: u32 = 4;
+
+/// Flash pages used by the EEPROM emulation
+///
+/// Offsets are in bytes and half-word aligned. An erased half-word reads as `0xFFFF` and every
+/// half-word is written at most once between erases, except for writing `0x0000`.
+pub trait Storage {
+    type Error;
+
+    /// Returns the size of a page in bytes
+    fn page_size(&self) -> u32;
+
+    /// Reads the half-word at `offset`
+    fn read_u16(&self, offset: u32) -> Result<u16, Self::Error>;
+
+    /// Writes the half-word at `offset`
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), Self::Error>;
+
+    /// Erases the page starting at `offset`
+    fn erase_page(&mut self, offset: u32) -> Result<(), Self::Error>;
+}
+
+/// EEPROM emulation errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    /// Error of the underlying storage
+    Storage(E),
+    /// `0xFFFF` is not a valid virtual address, it marks unused records
+    InvalidAddress,
+    /// The number of distinct variables exceeds the capacity of a page
+    Full,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Storage(e)
+    }
+}
+
+/// EEPROM emulation on flash pages
+pub struct Eeprom<S> {
+    storage: S,
+    start: u32,
+    pages: u32,
+    page_size: u32,
+    /// Index of the active page
+    active: u32,
+    /// Offset of the next free record in the active page
+    next: u32,
+}
+
+impl<S> Eeprom<S>
+where
+    S: Storage,
+{
+    /// Uses `pages` consecutive pages starting at `start`
+    ///
+    /// Recovers from an interrupted page transfer and formats the pages if none of them is
+    /// valid.
+    pub fn new(storage: S, start: u32, pages: u32) -> Result<Self, Error<S::Error>> {
+        assert!(pages >= 2, "the EEPROM emulation needs at least two pages");
+        let page_size = storage.page_size();
+        assert!(start & (page_size - 1) == 0, "start has to be page aligned");
+
+        let mut eeprom = Eeprom {
+            storage,
+            start,
+            pages,
+            page_size,
+            active: 0,
+            next: RECORD_SIZE,
+        };
+        eeprom.recover()?;
+        Ok(eeprom)
+    }
+
+    /// Returns the last value written to `address`, `None` if it was never written
+    pub fn read(&self, address: u16) -> Result<Option<u16>, Error<S::Error>> {
+        if address == ERASED {
+            return Err(Error::InvalidAddress);
+        }
+        self.find(self.active, RECORD_SIZE, self.next, address)
+    }
+
+    /// Writes `value` to `address`
+    ///
+    /// Starts a page transfer if the active page is full.
+    pub fn write(&mut self, address: u16, value: u16) -> Result<(), Error<S::Error>> {
+        if address == ERASED {
+            return Err(Error::InvalidAddress);
+        }
+        if self.read(address)? == Some(value) {
+            return Ok(());
+        }
+
+        if self.next < self.page_size {
+            let offset = self.page_offset(self.active) + self.next;
+            self.next += RECORD_SIZE;
+            self.write_record(offset, address, value)
+        } else {
+            self.transfer(address, value)
+        }
+    }
+
+    /// Erases all pages and removes all variables
+    pub fn format(&mut self) -> Result<(), Error<S::Error>> {
+        for page in 0..self.pages {
+            self.erase_if_needed(page)?;
+        }
+        self.set_status(0, VALID_PAGE)?;
+        self.active = 0;
+        self.next = RECORD_SIZE;
+        Ok(())
+    }
+
+    /// Releases the storage
+    pub fn release(self) -> S {
+        self.storage
+    }
+
+    /// Brings the pages into a state with exactly one valid page
+    fn recover(&mut self) -> Result<(), Error<S::Error>> {
+        let mut valid = None;
+        let mut receive = None;
+        let mut valid_count = 0;
+        let mut receive_count = 0;
+
+        for page in 0..self.pages {
+            match self.status(page)? {
+                VALID_PAGE => {
+                    valid = Some(page);
+                    valid_count += 1;
+                }
+                RECEIVE_DATA => {
+                    receive = Some(page);
+                    receive_count += 1;
+                }
+                ERASED => {}
+                // A page with an invalid status is erased below
+                _ => {}
+            }
+        }
+
+        if valid_count > 1 || receive_count > 1 {
+            return self.format();
+        }
+
+        match (valid, receive) {
+            (Some(valid), Some(receive)) => {
+                // The page transfer was interrupted while copying the variables
+                let next = self.free_offset(receive)?;
+                self.copy(valid, receive, next)?;
+                self.erase_if_needed(valid)?;
+                self.activate(receive)
+            }
+            (None, Some(receive)) => {
+                // The page transfer was interrupted after erasing the old page
+                self.activate(receive)
+            }
+            (Some(valid), None) => {
+                self.active = valid;
+                self.next = self.free_offset(valid)?;
+                self.erase_others()
+            }
+            (None, None) => self.format(),
+        }
+    }
+
+    /// Copies the latest value of every variable to the next page and makes it the active page
+    ///
+    /// The new value of `address` is written to the new page before the other variables.
+    fn transfer(&mut self, address: u16, value: u16) -> Result<(), Error<S::Error>> {
+        let old = self.active;
+        let new = (old + 1) % self.pages;
+
+        // The new page has to hold the latest value of every variable
+        let end = self.next;
+        let mut variables = 1;
+        let mut offset = RECORD_SIZE;
+        while offset < end {
+            let other = self.read_address(old, offset)?;
+            if other != ERASED
+                && other != address
+                && self.find(old, offset + RECORD_SIZE, end, other)?.is_none()
+            {
+                variables += 1;
+            }
+            offset += RECORD_SIZE;
+        }
+        if RECORD_SIZE * (variables + 1) > self.page_size {
+            return Err(Error::Full);
+        }
+
+        self.erase_if_needed(new)?;
+        self.set_status(new, RECEIVE_DATA)?;
+        self.write_record(self.page_offset(new) + RECORD_SIZE, address, value)?;
+        self.copy(old, new, 2 * RECORD_SIZE)?;
+
+        self.storage.erase_page(self.page_offset(old))?;
+        self.activate(new)
+    }
+
+    /// Copies the latest value of every variable in `from` that is not yet in `to`, starting at
+    /// the record `next` of `to`
+    fn copy(&mut self, from: u32, to: u32, mut next: u32) -> Result<(), Error<S::Error>> {
+        let end = self.free_offset(from)?;
+        let mut offset = end;
+        while offset > RECORD_SIZE {
+            offset -= RECORD_SIZE;
+            let address = self.read_address(from, offset)?;
+            if address == ERASED || self.find(to, RECORD_SIZE, next, address)?.is_some() {
+                continue;
+            }
+            if next >= self.page_size {
+                return Err(Error::Full);
+            }
+            let value = self.storage.read_u16(self.page_offset(from) + offset)?;
+            self.write_record(self.page_offset(to) + next, address, value)?;
+            next += RECORD_SIZE;
+        }
+        Ok(())
+    }
+
+    /// Marks `page` as the active page
+    fn activate(&mut self, page: u32) -> Result<(), Error<S::Error>> {
+        self.set_status(page, VALID_PAGE)?;
+        self.active = page;
+        self.next = self.free_offset(page)?;
+        self.erase_others()
+    }
+
+    /// Erases all pages except the active one that are not erased
+    fn erase_others(&mut self) -> Result<(), Error<S::Error>> {
+        for page in 0..self.pages {
+            if page != self.active && self.status(page)? != ERASED {
+                self.storage.erase_page(self.page_offset(page))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Erases `page` unless every half-word is already erased
+    fn erase_if_needed(&mut self, page: u32) -> Result<(), Error<S::Error>> {
+        let start = self.page_offset(page);
+        let mut offset = 0;
+        while offset < self.page_size {
+            if self.storage.read_u16(start + offset)? != ERASED {
+                self.storage.erase_page(start)?;
+                break;
+            }
+            offset += 2;
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the last record with `address` from `start` to `end` in `page`
+    fn find(
+        &self,
+        page: u32,
+        start: u32,
+        end: u32,
+        address: u16,
+    ) -> Result<Option<u16>, Error<S::Error>> {
+        let mut offset = end;
+        while offset > start {
+            offset -= RECORD_SIZE;
+            if self.read_address(page, offset)? == address {
+                let value = self.storage.read_u16(self.page_offset(page) + offset)?;
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the offset after the last used record of `page`
+    ///
+    /// Records that were only partially written are counted as used.
+    fn free_offset(&self, page: u32) -> Result<u32, Error<S::Error>> {
+        let start = self.page_offset(page);
+        let mut offset = self.page_size;
+        while offset > RECORD_SIZE {
+            let value = self.storage.read_u16(start + offset - RECORD_SIZE)?;
+            let address = self.storage.read_u16(start + offset - RECORD_SIZE + 2)?;
+            if value != ERASED || address != ERASED {
+                break;
+            }
+            offset -= RECORD_SIZE;
+        }
+        Ok(offset)
+    }
+
+    /// Writes a record, the value first so that a record with an erased address is never read
+    fn write_record(
+        &mut self,
+        offset: u32,
+        address: u16,
+        value: u16,
+    ) -> Result<(), Error<S::Error>> {
+        self.storage.write_u16(offset, value)?;
+        self.storage.write_u16(offset + 2, address)?;
+        Ok(())
+    }
+
+    fn read_address(&self, page: u32, offset: u32) -> Result<u16, Error<S::Error>> {
+        Ok(self.storage.read_u16(self.page_offset(page) + offset + 2)?)
+    }
+
+    fn status(&self, page: u32) -> Result<u16, Error<S::Error>> {
+        Ok(self.storage.read_u16(self.page_offset(page))?)
+    }
+
+    fn set_status(&mut self, page: u32, status: u16) -> Result<(), Error<S::Error>> {
+        Ok(self.storage.write_u16(self.page_offset(page), status)?)
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        self.start + page * self.page_size
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    /// Failures of the RAM backed storage
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub(crate) enum Fault {
+        /// Power was lost before the write or erase
+        PowerLoss,
+        /// A programmed half-word was written with a value other than 0
+        Program,
+    }
+
+    /// Flash model in RAM that can lose power after a number of writes and erases
+    pub(crate) struct Ram {
+        pub(crate) mem: Vec<u16>,
+        page_size: u32,
+        budget: Option<usize>,
+    }
+
+    impl Ram {
+        pub(crate) fn new(size: u32, page_size: u32) -> Self {
+            Ram {
+                mem: vec![ERASED; size as usize / 2],
+                page_size,
+                budget: None,
+            }
+        }
+
+        /// Loses power after `steps` more writes or erases
+        pub(crate) fn cut_after(&mut self, steps: usize) {
+            self.budget = Some(steps);
+        }
+
+        /// Powers up again
+        pub(crate) fn restore(&mut self) {
+            self.budget = None;
+        }
+
+        fn step(&mut self) -> Result<(), Fault> {
+            match self.budget {
+                Some(0) => return Err(Fault::PowerLoss),
+                Some(ref mut budget) => *budget -= 1,
+                None => {}
+            }
+            Ok(())
+        }
+    }
+
+    impl Storage for &mut Ram {
+        type Error = Fault;
+
+        fn page_size(&self) -> u32 {
+            self.page_size
+        }
+
+        fn read_u16(&self, offset: u32) -> Result<u16, Fault> {
+            assert!(offset & 1 == 0);
+            Ok(self.mem[offset as usize / 2])
+        }
+
+        fn write_u16(&mut self, offset: u32, value: u16) -> Result<(), Fault> {
+            assert!(offset & 1 == 0);
+            self.step()?;
+            let word = &mut self.mem[offset as usize / 2];
+            if *word != ERASED && value != 0 {
+                return Err(Fault::Program);
+            }
+            *word = value;
+            Ok(())
+        }
+
+        fn erase_page(&mut self, offset: u32) -> Result<(), Fault> {
+            assert!(offset & (self.page_size - 1) == 0);
+            self.step()?;
+            let start = offset as usize / 2;
+            let end = start + self.page_size as usize / 2;
+            for word in &mut self.mem[start..end] {
+                *word = ERASED;
+            }
+            Ok(())
+        }
+    }
+
+    const START: u32 = 0x100;
+    const PAGE_SIZE: u32 = 64;
+    /// Records per page
+    const RECORDS: u32 = PAGE_SIZE / RECORD_SIZE - 1;
+
+    fn ram(pages: u32) -> Ram {
+        Ram::new(START + pages * PAGE_SIZE, PAGE_SIZE)
+    }
+
+    fn statuses(ram: &Ram, pages: u32) -> Vec<u16> {
+        (0..pages)
+            .map(|page| ram.mem[((START + page * PAGE_SIZE) / 2) as usize])
+            .collect()
+    }
+
+    /// Writes a page header and records directly
+    fn fill(ram: &mut Ram, page: u32, status: u16, records: &[(u16, u16)]) {
+        let start = ((START + page * PAGE_SIZE) / 2) as usize;
+        ram.mem[start] = status;
+        for (i, &(address, value)) in records.iter().enumerate() {
+            ram.mem[start + 2 + 2 * i] = value;
+            ram.mem[start + 3 + 2 * i] = address;
+        }
+    }
+
+    #[test]
+    fn read_write() {
+        let mut ram = ram(2);
+        let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+        assert_eq!(eeprom.read(1), Ok(None));
+        eeprom.write(1, 0x1234).unwrap();
+        eeprom.write(2, 0).unwrap();
+        eeprom.write(1, 0x5678).unwrap();
+        assert_eq!(eeprom.read(1), Ok(Some(0x5678)));
+        assert_eq!(eeprom.read(2), Ok(Some(0)));
+        assert_eq!(eeprom.write(ERASED, 1), Err(Error::InvalidAddress));
+        assert_eq!(eeprom.read(ERASED), Err(Error::InvalidAddress));
+
+        // Nothing before START is touched
+        assert!(ram.mem[..START as usize / 2].iter().all(|&w| w == ERASED));
+        let eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+        assert_eq!(eeprom.read(1), Ok(Some(0x5678)));
+        assert_eq!(eeprom.read(2), Ok(Some(0)));
+    }
+
+    #[test]
+    fn page_transfers() {
+        for &pages in &[2, 3] {
+            let mut ram = ram(pages);
+            let mut eeprom = Eeprom::new(&mut ram, START, pages).unwrap();
+            for i in 0..10 * RECORDS {
+                let address = (i % 5) as u16;
+                eeprom.write(address, i as u16).unwrap();
+                for other in 0..5 {
+                    let expected = i.checked_sub((address as u32 + 5 - other) % 5);
+                    assert_eq!(eeprom.read(other as u16), Ok(expected.map(|v| v as u16)));
+                }
+            }
+
+            let valid = statuses(&ram, pages);
+            assert_eq!(valid.iter().filter(|&&s| s == VALID_PAGE).count(), 1);
+            assert_eq!(
+                valid.iter().filter(|&&s| s == ERASED).count(),
+                pages as usize - 1
+            );
+            let eeprom = Eeprom::new(&mut ram, START, pages).unwrap();
+            assert_eq!(eeprom.read(4), Ok(Some(10 * RECORDS as u16 - 1)));
+        }
+    }
+
+    #[test]
+    fn full() {
+        let mut ram = ram(2);
+        let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+        for address in 0..RECORDS as u16 {
+            eeprom.write(address, address).unwrap();
+        }
+        // A transfer needs room for the new value of every variable
+        assert_eq!(eeprom.write(0, 100), Ok(()));
+        assert_eq!(eeprom.read(0), Ok(Some(100)));
+        for address in 1..RECORDS as u16 {
+            assert_eq!(eeprom.read(address), Ok(Some(address)));
+        }
+        for address in RECORDS as u16..2 * RECORDS as u16 {
+            if let Err(e) = eeprom.write(address, address) {
+                assert_eq!(e, Error::Full);
+                return;
+            }
+        }
+        panic!("page never filled up");
+    }
+
+    /// Loses power after every write and erase of a write that starts a page transfer, and
+    /// again after every step of the following recovery
+    #[test]
+    fn power_loss_during_transfer() {
+        let prepare = || {
+            let mut ram = ram(2);
+            let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+            for i in 0..RECORDS {
+                eeprom.write((i % 4) as u16, i as u16).unwrap();
+            }
+            ram
+        };
+        let old = |address: u16| {
+            (0..RECORDS)
+                .rev()
+                .find(|i| i % 4 == u32::from(address))
+                .map(|i| i as u16)
+        };
+
+        let mut cut = 0;
+        loop {
+            let mut ram = prepare();
+            let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+            eeprom.storage.cut_after(cut);
+            let result = eeprom.write(0, 0xABCD);
+            ram.restore();
+            let interrupted = match result {
+                Ok(()) => false,
+                Err(e) => {
+                    assert_eq!(e, Error::Storage(Fault::PowerLoss));
+                    true
+                }
+            };
+
+            let snapshot = ram.mem.clone();
+            let mut recovery_cut = 0;
+            loop {
+                ram.mem.copy_from_slice(&snapshot);
+                ram.cut_after(recovery_cut);
+                let recovered = Eeprom::new(&mut ram, START, 2).is_ok();
+                ram.restore();
+
+                let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+                let value = eeprom.read(0).unwrap();
+                if interrupted {
+                    assert!(value == old(0) || value == Some(0xABCD), "{:?}", value);
+                } else {
+                    assert_eq!(value, Some(0xABCD));
+                }
+                for address in 1..4 {
+                    assert_eq!(eeprom.read(address), Ok(old(address)));
+                }
+                eeprom.write(5, 5).unwrap();
+                assert_eq!(eeprom.read(5), Ok(Some(5)));
+                assert_eq!(
+                    statuses(&ram, 2)
+                        .iter()
+                        .filter(|&&s| s == VALID_PAGE)
+                        .count(),
+                    1
+                );
+
+                if recovered {
+                    break;
+                }
+                recovery_cut += 1;
+            }
+
+            if !interrupted {
+                break;
+            }
+            cut += 1;
+        }
+        // Two status writes, the records of the four variables and the erase
+        assert_eq!(cut, 2 + 2 * 4 + 1);
+    }
+
+    #[test]
+    fn recover_valid_and_receive() {
+        let mut ram = ram(2);
+        fill(
+            &mut ram,
+            0,
+            VALID_PAGE,
+            &[(1, 10), (2, 20), (1, 11), (3, 30)],
+        );
+        fill(&mut ram, 1, RECEIVE_DATA, &[(4, 40), (3, 30)]);
+        let eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+        assert_eq!(eeprom.read(1), Ok(Some(11)));
+        assert_eq!(eeprom.read(2), Ok(Some(20)));
+        assert_eq!(eeprom.read(3), Ok(Some(30)));
+        assert_eq!(eeprom.read(4), Ok(Some(40)));
+        assert_eq!(statuses(&ram, 2), [ERASED, VALID_PAGE]);
+    }
+
+    #[test]
+    fn recover_receive_only() {
+        let mut ram = ram(3);
+        fill(&mut ram, 2, RECEIVE_DATA, &[(1, 10), (2, 20)]);
+        let mut eeprom = Eeprom::new(&mut ram, START, 3).unwrap();
+        assert_eq!(eeprom.read(1), Ok(Some(10)));
+        assert_eq!(eeprom.read(2), Ok(Some(20)));
+        eeprom.write(3, 30).unwrap();
+        assert_eq!(statuses(&ram, 3), [ERASED, ERASED, VALID_PAGE]);
+    }
+
+    #[test]
+    fn recover_valid_only() {
+        let mut ram = ram(2);
+        fill(&mut ram, 1, VALID_PAGE, &[(1, 10)]);
+        // Leftovers of an interrupted erase
+        fill(&mut ram, 0, 0x1234, &[(2, 20)]);
+        let mut eeprom = Eeprom::new(&mut ram, START, 2).unwrap();
+        assert_eq!(eeprom.read(1), Ok(Some(10)));
+        assert_eq!(eeprom.read(2), Ok(None));
+        eeprom.write(2, 20).unwrap();
+        assert_eq!(statuses(&ram, 2), [ERASED, VALID_PAGE]);
+    }
+
+    #[test]
+    fn format_invalid_states() {
+        let invalid: [&[u16]; 4] = [
+            &[VALID_PAGE, VALID_PAGE, ERASED],
+            &[RECEIVE_DATA, ERASED, RECEIVE_DATA],
+            &[0x1234, ERASED, ERASED],
+            &[ERASED, ERASED, ERASED],
+        ];
+        for statuses_before in invalid.iter() {
+            let mut ram = ram(3);
+            for (page, &status) in statuses_before.iter().enumerate() {
+                fill(&mut ram, page as u32, status, &[(1, page as u16)]);
+            }
+            let eeprom = Eeprom::new(&mut ram, START, 3).unwrap();
+            assert_eq!(eeprom.read(1), Ok(None));
+            assert_eq!(statuses(&ram, 3), [VALID_PAGE, ERASED, ERASED]);
+        }
+    }
+}
@@ -1,16 +1,31 @@
 //! Flash memory
 
 use crate::pac::{flash, FLASH};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
 
 pub const FLASH_START: u32 = 0x0800_0000;
 pub const FLASH_END: u32 = 0x080F_FFFF;
 
-const _RDPRT_KEY: u16 = 0x00A5;
+const RDPRT_KEY: u16 = 0x00A5;
 const KEY1: u32 = 0x45670123;
 const KEY2: u32 = 0xCDEF89AB;
 
 pub const SZ_1K: u16 = 1024;
 
+/// Size of a flash page, the smallest unit that can be erased
+#[cfg(any(feature = "high", feature = "connectivity"))]
+const PAGE_SIZE: u32 = 2048;
+/// Size of a flash page, the smallest unit that can be erased
+#[cfg(not(any(feature = "high", feature = "connectivity")))]
+const PAGE_SIZE: u32 = 1024;
+
+/// Address of the option bytes
+pub const OPTION_BYTES_START: u32 = 0x1FFF_F800;
+
+/// Size of the first flash bank, XL-density devices have a second bank above it
+#[cfg(feature = "xl")]
+pub const BANK1_SIZE: u32 = 512 * 1024;
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
@@ -25,6 +40,8 @@ pub enum Error {
     VerifyError,
     UnlockError,
     LockError,
+    ReadoutProtectionNotConfirmed,
+    SectorSizeMismatch,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
@@ -57,6 +74,101 @@ impl FlashSize {
     }
 }
 
+/// Readout protection level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RdpLevel {
+    /// The flash can be read by the debugger and the system bootloader
+    Level0,
+    /// The flash can only be read by code running from flash. Going back to level 0 erases the
+    /// whole flash.
+    Level1,
+}
+
+/// Option bytes
+///
+/// Read with [Parts::option_bytes](struct.Parts.html#method.option_bytes) and programmed with
+/// [Parts::program_option_bytes](struct.Parts.html#method.program_option_bytes).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptionBytes {
+    /// Readout protection level (RDP)
+    pub readout_protection: RdpLevel,
+    /// Write protected flash pages, a set bit protects a group of 4 pages (2 pages on high-density
+    /// and connectivity line devices), bit 31 protects all remaining pages
+    pub write_protection: u32,
+    /// Software watchdog: the independent watchdog is started by software, not at reset (WDG_SW)
+    pub watchdog_sw: bool,
+    /// Entering Stop mode generates a reset (inverse of nRST_STOP)
+    pub reset_on_stop: bool,
+    /// Entering Standby mode generates a reset (inverse of nRST_STDBY)
+    pub reset_on_standby: bool,
+    /// User data byte 0
+    pub data0: u8,
+    /// User data byte 1
+    pub data1: u8,
+    readout_protection_confirmed: bool,
+}
+
+impl OptionBytes {
+    /// Confirms a change of the readout protection level, or any change of the option bytes
+    /// while the readout protection is at `Level1`
+    ///
+    /// Enabling the readout protection disables debug access to the flash. Erasing the option
+    /// bytes at `Level1`, which every change requires, erases the whole flash, including the
+    /// running program.
+    pub fn confirm_readout_protection_change(mut self) -> Self {
+        self.readout_protection_confirmed = true;
+        self
+    }
+}
+
+/// Flash bank with its own KEYR, SR, CR and AR registers
+#[derive(Clone, Copy, PartialEq)]
+enum Bank {
+    First,
+    #[cfg(feature = "xl")]
+    Second,
+}
+
+impl Bank {
+    /// Returns the bank that contains `FLASH_START + offset`
+    #[cfg(feature = "xl")]
+    fn of(offset: u32) -> Self {
+        if offset < BANK1_SIZE {
+            Bank::First
+        } else {
+            Bank::Second
+        }
+    }
+
+    /// Returns the bank that contains `FLASH_START + offset`
+    #[cfg(not(feature = "xl"))]
+    fn of(_offset: u32) -> Self {
+        Bank::First
+    }
+}
+
+/// Registers of the second flash bank (FLASH_KEYR2, FLASH_SR2, FLASH_CR2 and FLASH_AR2)
+///
+/// They have the same layout as the registers of the first bank, but are not part of the PAC.
+#[cfg(feature = "xl")]
+#[repr(C)]
+struct Bank2 {
+    keyr: flash::KEYR,
+    _reserved: u32,
+    sr: flash::SR,
+    cr: flash::CR,
+    ar: flash::AR,
+}
+
+#[cfg(feature = "xl")]
+impl Bank2 {
+    fn regs() -> &'static Bank2 {
+        // NOTE(unsafe) the registers of the second bank start at offset 0x44 of the FLASH
+        // peripheral and are only accessed through `Parts`, which owns the FLASH peripheral
+        unsafe { &*((FLASH::ptr() as *const u8).add(0x44) as *const Bank2) }
+    }
+}
+
 pub struct FlashWriter<'a> {
     flash: &'a mut Parts,
     sector_sz: SectorSize,
@@ -64,41 +176,12 @@ pub struct FlashWriter<'a> {
     verify: bool,
 }
 impl<'a> FlashWriter<'a> {
-    fn unlock(&mut self) -> Result<()> {
-        // Wait for any ongoing operations
-        while self.flash.sr.sr().read().bsy().bit_is_set() {}
-
-        // NOTE(unsafe) write Keys to the key register. This is safe because the
-        // only side effect of these writes is to unlock the flash control
-        // register, which is the intent of this function. Do not rearrange the
-        // order of these writes or the control register will be permanently
-        // locked out until reset.
-        unsafe {
-            self.flash.keyr.keyr().write(|w| w.key().bits(KEY1));
-        }
-        unsafe {
-            self.flash.keyr.keyr().write(|w| w.key().bits(KEY2));
-        }
-
-        // Verify success
-        match self.flash.cr.cr().read().lock().bit_is_clear() {
-            true => Ok(()),
-            false => Err(Error::UnlockError),
-        }
+    fn unlock(&mut self, bank: Bank) -> Result<()> {
+        self.flash.unlock(bank)
     }
 
-    fn lock(&mut self) -> Result<()> {
-        //Wait for ongoing flash operations
-        while self.flash.sr.sr().read().bsy().bit_is_set() {}
-
-        // Set lock bit
-        self.flash.cr.cr().modify(|_, w| w.lock().set_bit());
-
-        // Verify success
-        match self.flash.cr.cr().read().lock().bit_is_set() {
-            true => Ok(()),
-            false => Err(Error::LockError),
-        }
+    fn lock(&mut self, bank: Bank) -> Result<()> {
+        self.flash.lock(bank)
     }
 
     fn valid_address(&self, offset: u32) -> Result<()> {
@@ -111,6 +194,15 @@ impl<'a> FlashWriter<'a> {
         }
     }
 
+    /// The pages erased by `NorFlash` have to be the sectors of the writer
+    fn valid_sector_size(&self) -> Result<()> {
+        if u32::from(self.sector_sz.kbytes()) == PAGE_SIZE {
+            Ok(())
+        } else {
+            Err(Error::SectorSizeMismatch)
+        }
+    }
+
     fn valid_length(&self, offset: u32, length: usize) -> Result<()> {
         if offset + length as u32 > self.flash_sz.kbytes() as u32 {
             Err(Error::LengthTooLong)
@@ -124,12 +216,13 @@ impl<'a> FlashWriter<'a> {
     /// Erase sector which contains `start_offset`
     pub fn page_erase(&mut self, start_offset: u32) -> Result<()> {
         self.valid_address(start_offset)?;
+        let bank = Bank::of(start_offset);
 
         // Unlock Flash
-        self.unlock()?;
+        self.unlock(bank)?;
 
         // Set Page Erase
-        self.flash.cr.cr().modify(|_, w| w.per().set_bit());
+        self.flash.bank_cr(bank).modify(|_, w| w.per().set_bit());
 
         // Write address bits
         // NOTE(unsafe) This sets the page address in the Address Register.
@@ -138,28 +231,27 @@ impl<'a> FlashWriter<'a> {
         // call to self.valid_address() above.
         unsafe {
             self.flash
-                .ar
-                .ar()
+                .bank_ar(bank)
                 .write(|w| w.far().bits(FLASH_START + start_offset));
         }
 
         // Start Operation
-        self.flash.cr.cr().modify(|_, w| w.strt().set_bit());
+        self.flash.bank_cr(bank).modify(|_, w| w.strt().set_bit());
 
         // Wait for operation to finish
-        while self.flash.sr.sr().read().bsy().bit_is_set() {}
+        while self.flash.bank_sr(bank).read().bsy().bit_is_set() {}
 
         // Check for errors
-        let sr = self.flash.sr.sr().read();
+        let sr = self.flash.bank_sr(bank).read();
 
         // Remove Page Erase Operation bit
-        self.flash.cr.cr().modify(|_, w| w.per().clear_bit());
+        self.flash.bank_cr(bank).modify(|_, w| w.per().clear_bit());
 
         // Re-lock flash
-        self.lock()?;
+        self.lock(bank)?;
 
         if sr.wrprterr().bit_is_set() {
-            self.flash.sr.sr().modify(|_, w| w.wrprterr().clear_bit());
+            self.flash.bank_sr(bank).write(|w| w.wrprterr().set_bit());
             Err(Error::EraseError)
         } else {
             if self.verify {
@@ -222,8 +314,25 @@ impl<'a> FlashWriter<'a> {
     pub fn write(&mut self, offset: u32, data: &[u8]) -> Result<()> {
         self.valid_length(offset, data.len())?;
 
+        // Each bank is programmed through its own registers
+        #[cfg(feature = "xl")]
+        {
+            if offset < BANK1_SIZE && offset + data.len() as u32 > BANK1_SIZE {
+                let (first, second) = data.split_at((BANK1_SIZE - offset) as usize);
+                self.write_bank(offset, first)?;
+                return self.write_bank(BANK1_SIZE, second);
+            }
+        }
+
+        self.write_bank(offset, data)
+    }
+
+    /// Write data that lies within one bank to `FLASH_START + offset`
+    fn write_bank(&mut self, offset: u32, data: &[u8]) -> Result<()> {
+        let bank = Bank::of(offset);
+
         // Unlock Flash
-        self.unlock()?;
+        self.unlock(bank)?;
 
         for idx in (0..data.len()).step_by(2) {
             self.valid_address(offset + idx as u32)?;
@@ -231,9 +340,9 @@ impl<'a> FlashWriter<'a> {
             let write_address = (FLASH_START + offset + idx as u32) as *mut u16;
 
             // Set Page Programming to 1
-            self.flash.cr.cr().modify(|_, w| w.pg().set_bit());
+            self.flash.bank_cr(bank).modify(|_, w| w.pg().set_bit());
 
-            while self.flash.sr.sr().read().bsy().bit_is_set() {}
+            while self.flash.bank_sr(bank).read().bsy().bit_is_set() {}
 
             // Flash is written 16 bits at a time, so combine two bytes to get a
             // half-word
@@ -243,35 +352,35 @@ impl<'a> FlashWriter<'a> {
             unsafe { core::ptr::write_volatile(write_address, hword) };
 
             // Wait for write
-            while self.flash.sr.sr().read().bsy().bit_is_set() {}
+            while self.flash.bank_sr(bank).read().bsy().bit_is_set() {}
 
             // Set Page Programming to 0
-            self.flash.cr.cr().modify(|_, w| w.pg().clear_bit());
+            self.flash.bank_cr(bank).modify(|_, w| w.pg().clear_bit());
 
             // Check for errors
-            if self.flash.sr.sr().read().pgerr().bit_is_set() {
-                self.flash.sr.sr().modify(|_, w| w.pgerr().clear_bit());
+            if self.flash.bank_sr(bank).read().pgerr().bit_is_set() {
+                self.flash.bank_sr(bank).write(|w| w.pgerr().set_bit());
 
-                self.lock()?;
+                self.lock(bank)?;
                 return Err(Error::ProgrammingError);
-            } else if self.flash.sr.sr().read().wrprterr().bit_is_set() {
-                self.flash.sr.sr().modify(|_, w| w.wrprterr().clear_bit());
+            } else if self.flash.bank_sr(bank).read().wrprterr().bit_is_set() {
+                self.flash.bank_sr(bank).write(|w| w.wrprterr().set_bit());
 
-                self.lock()?;
+                self.lock(bank)?;
                 return Err(Error::WriteError);
             } else if self.verify {
                 // Verify written WORD
                 // NOTE(unsafe) read with no side effects within FLASH area
                 let verify: u16 = unsafe { core::ptr::read_volatile(write_address) };
                 if verify != hword {
-                    self.lock()?;
+                    self.lock(bank)?;
                     return Err(Error::VerifyError);
                 }
             }
         }
 
         // Lock Flash and report success
-        self.lock()?;
+        self.lock(bank)?;
         Ok(())
     }
 
@@ -290,6 +399,88 @@ impl<'a> FlashWriter<'a> {
     }
 }
 
+impl<'a> crate::eeprom::Storage for FlashWriter<'a> {
+    type Error = Error;
+
+    /// The page size of the device, `erase_page` fails with `Error::SectorSizeMismatch` if the
+    /// sector size of the writer differs from it
+    fn page_size(&self) -> u32 {
+        PAGE_SIZE
+    }
+
+    fn read_u16(&self, offset: u32) -> Result<u16> {
+        let bytes = self.read(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn write_u16(&mut self, offset: u32, value: u16) -> Result<()> {
+        self.write(offset, &value.to_le_bytes())
+    }
+
+    fn erase_page(&mut self, offset: u32) -> Result<()> {
+        self.valid_sector_size()?;
+        self.page_erase(offset)
+    }
+}
+
+impl embedded_storage::nor_flash::NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::AddressLargerThanFlash | Error::LengthTooLong => NorFlashErrorKind::OutOfBounds,
+            Error::AddressMisaligned | Error::LengthNotMultiple2 => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<'a> ErrorType for FlashWriter<'a> {
+    type Error = Error;
+}
+
+impl<'a> ReadNorFlash for FlashWriter<'a> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<()> {
+        if offset as usize + bytes.len() > self.capacity() {
+            return Err(Error::LengthTooLong);
+        }
+        let address = (FLASH_START + offset) as *const u8;
+        // NOTE(unsafe) read with no side effects within the flash
+        bytes.copy_from_slice(unsafe { core::slice::from_raw_parts(address, bytes.len()) });
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.flash_sz.kbytes() as usize
+    }
+}
+
+impl<'a> NorFlash for FlashWriter<'a> {
+    const WRITE_SIZE: usize = 2;
+    /// The page size of the device selected by the density features, 2 KB for high-density,
+    /// XL-density and connectivity line devices and 1 KB otherwise, `erase` fails with
+    /// `Error::SectorSizeMismatch` if the sector size of the writer differs from it
+    const ERASE_SIZE: usize = PAGE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<()> {
+        self.valid_sector_size()?;
+        if from > to || to as usize > self.capacity() {
+            return Err(Error::LengthTooLong);
+        }
+        if from & (PAGE_SIZE - 1) != 0 || to & (PAGE_SIZE - 1) != 0 {
+            return Err(Error::AddressMisaligned);
+        }
+        for offset in (from..to).step_by(PAGE_SIZE as usize) {
+            self.page_erase(offset)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<()> {
+        FlashWriter::write(self, offset, bytes)
+    }
+}
+
 /// Extension trait to constrain the FLASH peripheral
 pub trait FlashExt {
     /// Constrains the FLASH peripheral to play nicely with the other abstractions
@@ -303,10 +494,10 @@ impl FlashExt for FLASH {
             ar: AR { _0: () },
             cr: CR { _0: () },
             keyr: KEYR { _0: () },
-            _obr: OBR { _0: () },
-            _optkeyr: OPTKEYR { _0: () },
+            obr: OBR { _0: () },
+            optkeyr: OPTKEYR { _0: () },
             sr: SR { _0: () },
-            _wrpr: WRPR { _0: () },
+            wrpr: WRPR { _0: () },
         }
     }
 }
@@ -326,18 +517,234 @@ pub struct Parts {
     pub(crate) keyr: KEYR,
 
     /// Opaque OBR register
-    pub(crate) _obr: OBR,
+    pub(crate) obr: OBR,
 
     /// Opaque OPTKEYR register
-    pub(crate) _optkeyr: OPTKEYR,
+    pub(crate) optkeyr: OPTKEYR,
 
     /// Opaque SR register
     pub(crate) sr: SR,
 
     /// Opaque WRPR register
-    pub(crate) _wrpr: WRPR,
+    pub(crate) wrpr: WRPR,
 }
 impl Parts {
+    fn unlock(&mut self, bank: Bank) -> Result<()> {
+        // Wait for any ongoing operations
+        while self.bank_sr(bank).read().bsy().bit_is_set() {}
+
+        // NOTE(unsafe) write Keys to the key register. This is safe because the
+        // only side effect of these writes is to unlock the flash control
+        // register, which is the intent of this function. Do not rearrange the
+        // order of these writes or the control register will be permanently
+        // locked out until reset.
+        unsafe {
+            self.bank_keyr(bank).write(|w| w.key().bits(KEY1));
+        }
+        unsafe {
+            self.bank_keyr(bank).write(|w| w.key().bits(KEY2));
+        }
+
+        // Verify success
+        match self.bank_cr(bank).read().lock().bit_is_clear() {
+            true => Ok(()),
+            false => Err(Error::UnlockError),
+        }
+    }
+
+    fn lock(&mut self, bank: Bank) -> Result<()> {
+        //Wait for ongoing flash operations
+        while self.bank_sr(bank).read().bsy().bit_is_set() {}
+
+        // Set lock bit
+        self.bank_cr(bank).modify(|_, w| w.lock().set_bit());
+
+        // Verify success
+        match self.bank_cr(bank).read().lock().bit_is_set() {
+            true => Ok(()),
+            false => Err(Error::LockError),
+        }
+    }
+
+    fn bank_keyr(&mut self, bank: Bank) -> &flash::KEYR {
+        match bank {
+            Bank::First => self.keyr.keyr(),
+            #[cfg(feature = "xl")]
+            Bank::Second => &Bank2::regs().keyr,
+        }
+    }
+
+    fn bank_sr(&mut self, bank: Bank) -> &flash::SR {
+        match bank {
+            Bank::First => self.sr.sr(),
+            #[cfg(feature = "xl")]
+            Bank::Second => &Bank2::regs().sr,
+        }
+    }
+
+    fn bank_cr(&mut self, bank: Bank) -> &flash::CR {
+        match bank {
+            Bank::First => self.cr.cr(),
+            #[cfg(feature = "xl")]
+            Bank::Second => &Bank2::regs().cr,
+        }
+    }
+
+    fn bank_ar(&mut self, bank: Bank) -> &flash::AR {
+        match bank {
+            Bank::First => self.ar.ar(),
+            #[cfg(feature = "xl")]
+            Bank::Second => &Bank2::regs().ar,
+        }
+    }
+
+    /// Reads the option bytes that were loaded at the last reset
+    pub fn option_bytes(&mut self) -> OptionBytes {
+        let obr = self.obr.obr().read();
+        OptionBytes {
+            readout_protection: if obr.rdprt().bit_is_set() {
+                RdpLevel::Level1
+            } else {
+                RdpLevel::Level0
+            },
+            write_protection: !self.wrpr.wrpr().read().wrp().bits(),
+            watchdog_sw: obr.wdg_sw().bit_is_set(),
+            reset_on_stop: obr.n_rst_stop().bit_is_clear(),
+            reset_on_standby: obr.n_rst_stdby().bit_is_clear(),
+            data0: obr.data0().bits(),
+            data1: obr.data1().bits(),
+            readout_protection_confirmed: false,
+        }
+    }
+
+    /// Erases and programs the option bytes
+    ///
+    /// Nothing is written if `option_bytes` equal the current option bytes. The new values are
+    /// loaded at the next system reset. Changing the readout protection, and changing anything
+    /// while the readout protection is at `Level1`, has to be confirmed with
+    /// [confirm_readout_protection_change](struct.OptionBytes.html#method.confirm_readout_protection_change),
+    /// otherwise `Error::ReadoutProtectionNotConfirmed` is returned.
+    ///
+    /// Erasing the option bytes while the readout protection is at `Level1` makes the flash
+    /// interface mass erase the whole main flash, including the code calling this function.
+    /// Going back to `Level0` therefore has to be done from code running in RAM, and this
+    /// function does not return when called from flash.
+    pub fn program_option_bytes(&mut self, option_bytes: &OptionBytes) -> Result<()> {
+        let current = self.option_bytes();
+        let unchanged = OptionBytes {
+            readout_protection_confirmed: false,
+            ..*option_bytes
+        } == current;
+        if unchanged {
+            return Ok(());
+        }
+        // At Level1 the erase below mass erases the main flash, whatever is changed
+        let erases_flash = current.readout_protection == RdpLevel::Level1;
+        if (option_bytes.readout_protection != current.readout_protection || erases_flash)
+            && !option_bytes.readout_protection_confirmed
+        {
+            return Err(Error::ReadoutProtectionNotConfirmed);
+        }
+
+        self.unlock(Bank::First)?;
+
+        // NOTE(unsafe) write Keys to the option key register, this only unlocks writing the
+        // option bytes
+        unsafe {
+            self.optkeyr.optkeyr().write(|w| w.optkey().bits(KEY1));
+            self.optkeyr.optkeyr().write(|w| w.optkey().bits(KEY2));
+        }
+        if self.cr.cr().read().optwre().bit_is_clear() {
+            self.lock(Bank::First)?;
+            return Err(Error::UnlockError);
+        }
+
+        // Erasing sets all option bytes to 0xFF, which enables the readout protection until the
+        // RDP byte is programmed below
+        self.cr.cr().modify(|_, w| w.opter().set_bit());
+        self.cr.cr().modify(|_, w| w.strt().set_bit());
+        while self.sr.sr().read().bsy().bit_is_set() {}
+        self.cr.cr().modify(|_, w| w.opter().clear_bit());
+
+        let sr = self.sr.sr().read();
+        let result = if sr.wrprterr().bit_is_set() {
+            self.sr.sr().write(|w| w.wrprterr().set_bit());
+            Err(Error::EraseError)
+        } else if sr.pgerr().bit_is_set() {
+            self.sr.sr().write(|w| w.pgerr().set_bit());
+            Err(Error::EraseError)
+        } else {
+            self.program_option_half_words(option_bytes)
+        };
+
+        self.cr.cr().modify(|_, w| w.optwre().clear_bit());
+        self.lock(Bank::First)?;
+        result
+    }
+
+    fn program_option_half_words(&mut self, option_bytes: &OptionBytes) -> Result<()> {
+        let rdp = match option_bytes.readout_protection {
+            RdpLevel::Level0 => RDPRT_KEY,
+            RdpLevel::Level1 => 0x0000,
+        };
+        let mut user = 0xF8;
+        if option_bytes.watchdog_sw {
+            user |= 1 << 0;
+        }
+        if !option_bytes.reset_on_stop {
+            user |= 1 << 1;
+        }
+        if !option_bytes.reset_on_standby {
+            user |= 1 << 2;
+        }
+        let wrp = !option_bytes.write_protection;
+
+        // Only the low byte of every half-word is written, the complement in the high byte is
+        // computed by the flash interface
+        let half_words = [
+            rdp,
+            user,
+            u16::from(option_bytes.data0),
+            u16::from(option_bytes.data1),
+            wrp as u8 as u16,
+            (wrp >> 8) as u8 as u16,
+            (wrp >> 16) as u8 as u16,
+            (wrp >> 24) as u8 as u16,
+        ];
+
+        self.cr.cr().modify(|_, w| w.optpg().set_bit());
+        let mut result = Ok(());
+        for (idx, half_word) in half_words.iter().enumerate() {
+            // The erased value does not have to be programmed
+            if *half_word & 0xFF == 0xFF {
+                continue;
+            }
+
+            let address = (OPTION_BYTES_START + 2 * idx as u32) as *mut u16;
+            // NOTE(unsafe) write to the option bytes area with no side effects
+            unsafe { core::ptr::write_volatile(address, *half_word) };
+            while self.sr.sr().read().bsy().bit_is_set() {}
+
+            let sr = self.sr.sr().read();
+            if sr.pgerr().bit_is_set() {
+                self.sr.sr().write(|w| w.pgerr().set_bit());
+                result = Err(Error::ProgrammingError);
+                break;
+            } else if sr.wrprterr().bit_is_set() {
+                self.sr.sr().write(|w| w.wrprterr().set_bit());
+                result = Err(Error::WriteError);
+                break;
+            }
+        }
+        self.cr.cr().modify(|_, w| w.optpg().clear_bit());
+        result
+    }
+
+    /// Creates a writer for the flash
+    ///
+    /// `NorFlash::erase` of the writer requires `sector_sz` to be the page size of
+    /// the device selected by the density features, 2 KB for high-density, XL-density and
+    /// connectivity line devices and 1 KB otherwise.
     pub fn writer(&mut self, sector_sz: SectorSize, flash_sz: FlashSize) -> FlashWriter {
         FlashWriter {
             flash: self,
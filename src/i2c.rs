@@ -5,18 +5,21 @@
 // https://www.st.com/content/ccc/resource/technical/document/application_note/5d/ae/a3/6f/08/69/4e/9b/CD00209826.pdf/files/CD00209826.pdf/jcr:content/translations/en.CD00209826.pdf
 
 use crate::afio::MAPR;
+use crate::dma::{dma1, Receive, RxDma, Static, Transfer, TransferPayload, Transmit, TxDma, R, W};
 use crate::gpio::gpiob::{PB10, PB11, PB6, PB7, PB8, PB9};
 use crate::gpio::{Alternate, OpenDrain};
 use crate::hal::blocking::i2c::{Read, Write, WriteRead};
 use crate::pac::{DWT, I2C1, I2C2};
 use crate::rcc::{Clocks, Enable, GetBusFreq, Reset, APB1};
 use crate::time::Hertz;
+use as_slice::{AsMutSlice, AsSlice};
 use core::ops::Deref;
+use core::sync::atomic::{self, Ordering};
 use nb::Error::{Other, WouldBlock};
 use nb::{Error as NbError, Result as NbResult};
 
 /// I2C error
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Error {
     /// Bus error
     Bus,
@@ -26,6 +29,8 @@ pub enum Error {
     Acknowledge,
     /// Overrun/underrun
     Overrun,
+    /// DMA transfer error
+    Dma,
     // Pec, // SMBUS mode only
     // Timeout, // SMBUS mode only
     // Alert, // SMBUS mode only
@@ -100,6 +105,7 @@ pub struct I2c<I2C, PINS> {
 /// embedded-hal compatible blocking I2C implementation
 pub struct BlockingI2c<I2C, PINS> {
     nb: I2c<I2C, PINS>,
+    sysclk_mhz: u32,
     start_timeout: u32,
     start_retries: u8,
     addr_timeout: u32,
@@ -208,6 +214,7 @@ fn blocking_i2c<I2C, PINS>(
     let sysclk_mhz = clocks.sysclk().0 / 1_000_000;
     BlockingI2c {
         nb: i2c,
+        sysclk_mhz,
         start_timeout: start_timeout_us * sysclk_mhz,
         start_retries,
         addr_timeout: addr_timeout_us * sysclk_mhz,
@@ -285,6 +292,15 @@ where
         i2c.init();
         i2c
     }
+
+    /// Recomputes the `I2C_CR2`, `I2C_TRISE` and `I2C_CCR` registers after the clocks have been
+    /// reconfigured
+    ///
+    /// Must not be called while a transfer is in progress.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.pclk1 = I2C::Bus::get_frequency(clocks).0;
+        self.init();
+    }
 }
 
 impl<I2C, PINS> I2c<I2C, PINS>
@@ -408,6 +424,17 @@ where
             data_timeout_us,
         )
     }
+
+    /// Recomputes the bus timing and the timeouts after the clocks have been reconfigured
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.nb.reconfigure(clocks);
+
+        let sysclk_mhz = clocks.sysclk().0 / 1_000_000;
+        self.start_timeout = self.start_timeout / self.sysclk_mhz * sysclk_mhz;
+        self.addr_timeout = self.addr_timeout / self.sysclk_mhz * sysclk_mhz;
+        self.data_timeout = self.data_timeout / self.sysclk_mhz * sysclk_mhz;
+        self.sysclk_mhz = sysclk_mhz;
+    }
 }
 
 impl<I2C, PINS> BlockingI2c<I2C, PINS>
@@ -562,3 +589,1126 @@ where
         Ok(())
     }
 }
+
+/// Progress of an `InterruptI2c` transaction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Phase {
+    Idle,
+    Start,
+    Address,
+    Write,
+    Read,
+    Done(Result<(), Error>),
+}
+
+/// Interrupt driven I2C master
+///
+/// A transaction is started with `write`, `read` or `write_read`. It is then
+/// advanced by calling `handle_event` from the `I2Cx_EV` interrupt and
+/// `handle_error` from the `I2Cx_ER` interrupt, and its result is collected
+/// with `poll`.
+pub struct InterruptI2c<I2C, PINS> {
+    nb: I2c<I2C, PINS>,
+    phase: Phase,
+    addr: u8,
+    read: bool,
+    write_buf: &'static [u8],
+    write_idx: usize,
+    read_buf: Option<&'static mut [u8]>,
+    read_idx: usize,
+}
+
+impl<PINS> InterruptI2c<I2C1, PINS> {
+    /// Creates an interrupt driven I2C1 object on pins PB6 and PB7 or PB8 and PB9 (if remapped)
+    pub fn i2c1(
+        i2c: I2C1,
+        pins: PINS,
+        mapr: &mut MAPR,
+        mode: Mode,
+        clocks: Clocks,
+        apb: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C1>,
+    {
+        InterruptI2c::new(I2c::i2c1(i2c, pins, mapr, mode, clocks, apb))
+    }
+}
+
+impl<PINS> InterruptI2c<I2C2, PINS> {
+    /// Creates an interrupt driven I2C2 object on pins PB10 and PB11
+    pub fn i2c2(i2c: I2C2, pins: PINS, mode: Mode, clocks: Clocks, apb: &mut APB1) -> Self
+    where
+        PINS: Pins<I2C2>,
+    {
+        InterruptI2c::new(I2c::i2c2(i2c, pins, mode, clocks, apb))
+    }
+}
+
+impl<I2C, PINS> InterruptI2c<I2C, PINS>
+where
+    I2C: Deref<Target = I2cRegisterBlock>,
+{
+    fn new(nb: I2c<I2C, PINS>) -> Self {
+        nb.i2c.cr2.modify(|_, w| w.iterren().set_bit());
+        InterruptI2c {
+            nb,
+            phase: Phase::Idle,
+            addr: 0,
+            read: false,
+            write_buf: &[],
+            write_idx: 0,
+            read_buf: None,
+            read_idx: 0,
+        }
+    }
+
+    /// Starts writing `bytes` to the device at `addr`
+    ///
+    /// Returns `WouldBlock` while another transaction is in progress.
+    pub fn write(&mut self, addr: u8, bytes: &'static [u8]) -> NbResult<(), Error> {
+        self.begin(addr, bytes, None)
+    }
+
+    /// Starts reading into `buffer` from the device at `addr`
+    ///
+    /// Returns `WouldBlock` while another transaction is in progress.
+    pub fn read(&mut self, addr: u8, buffer: &'static mut [u8]) -> NbResult<(), Error> {
+        self.begin(addr, &[], Some(buffer))
+    }
+
+    /// Starts writing `bytes` to the device at `addr`, followed by a repeated START
+    /// and a read into `buffer`
+    ///
+    /// Returns `WouldBlock` while another transaction is in progress.
+    pub fn write_read(
+        &mut self,
+        addr: u8,
+        bytes: &'static [u8],
+        buffer: &'static mut [u8],
+    ) -> NbResult<(), Error> {
+        self.begin(addr, bytes, Some(buffer))
+    }
+
+    fn begin(
+        &mut self,
+        addr: u8,
+        bytes: &'static [u8],
+        buffer: Option<&'static mut [u8]>,
+    ) -> NbResult<(), Error> {
+        if self.phase != Phase::Idle {
+            return Err(WouldBlock);
+        }
+        if let Some(ref buffer) = buffer {
+            assert!(!buffer.is_empty());
+        }
+
+        self.addr = addr;
+        self.write_buf = bytes;
+        self.write_idx = 0;
+        self.read_buf = buffer;
+        self.read_idx = 0;
+        // A read without anything to write skips the write phase
+        self.read = bytes.is_empty() && self.read_buf.is_some();
+
+        self.restart();
+        Ok(())
+    }
+
+    /// Generates a (repeated) START condition
+    fn restart(&mut self) {
+        self.nb
+            .i2c
+            .cr1
+            .modify(|_, w| w.pos().clear_bit().ack().set_bit());
+        self.nb
+            .i2c
+            .cr2
+            .modify(|_, w| w.itevten().set_bit().itbufen().set_bit());
+        self.phase = Phase::Start;
+        self.nb.send_start();
+    }
+
+    fn finish(&mut self, result: Result<(), Error>) {
+        self.nb
+            .i2c
+            .cr2
+            .modify(|_, w| w.itevten().clear_bit().itbufen().clear_bit());
+        self.nb
+            .i2c
+            .cr1
+            .modify(|_, w| w.pos().clear_bit().ack().set_bit());
+        self.phase = Phase::Done(result);
+    }
+
+    fn disable_buffer_interrupt(&mut self) {
+        self.nb.i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+    }
+
+    /// Advances the transaction, call this from the `I2Cx_EV` interrupt handler
+    pub fn handle_event(&mut self) {
+        let sr1 = self.nb.i2c.sr1.read();
+
+        match self.phase {
+            Phase::Start => {
+                if sr1.sb().bit_is_set() {
+                    self.nb.send_addr(self.addr, self.read);
+                    self.phase = Phase::Address;
+                }
+            }
+            Phase::Address => {
+                if sr1.addr().bit_is_clear() {
+                    return;
+                }
+                if self.read {
+                    self.address_read();
+                } else if self.write_buf.is_empty() {
+                    self.nb.i2c.sr2.read();
+                    self.end_write();
+                } else {
+                    self.nb.i2c.sr2.read();
+                    self.phase = Phase::Write;
+                }
+            }
+            Phase::Write => {
+                if self.write_idx < self.write_buf.len() {
+                    if sr1.tx_e().bit_is_set() {
+                        let byte = self.write_buf[self.write_idx];
+                        self.nb.i2c.dr.write(|w| w.dr().bits(byte));
+                        self.write_idx += 1;
+                        if self.write_idx == self.write_buf.len() {
+                            // Wait for the last byte to leave the shift register
+                            self.disable_buffer_interrupt();
+                        }
+                    }
+                } else if sr1.btf().bit_is_set() {
+                    self.end_write();
+                }
+            }
+            Phase::Read => self.receive(sr1.rx_ne().bit_is_set(), sr1.btf().bit_is_set()),
+            Phase::Idle | Phase::Done(_) => {}
+        }
+    }
+
+    /// Clears ADDR for a read, following the sequences of the errata sheet (ES096)
+    /// and AN2824 for 1, 2 and N byte receptions
+    fn address_read(&mut self) {
+        let len = self.read_buf.as_ref().map_or(0, |b| b.len());
+        match len {
+            1 => {
+                self.nb.i2c.cr1.modify(|_, w| w.ack().clear_bit());
+                // Clearing ADDR and setting STOP must not be interrupted
+                cortex_m::interrupt::free(|_| {
+                    self.nb.i2c.sr2.read();
+                    self.nb.send_stop();
+                });
+            }
+            2 => {
+                self.nb
+                    .i2c
+                    .cr1
+                    .modify(|_, w| w.pos().set_bit().ack().clear_bit());
+                self.nb.i2c.sr2.read();
+                self.disable_buffer_interrupt();
+            }
+            3 => {
+                self.nb.i2c.sr2.read();
+                self.disable_buffer_interrupt();
+            }
+            _ => {
+                self.nb.i2c.sr2.read();
+            }
+        }
+        self.phase = Phase::Read;
+    }
+
+    fn receive(&mut self, rx_ne: bool, btf: bool) {
+        let i2c = &self.nb.i2c;
+        let buffer = match self.read_buf {
+            Some(ref mut buffer) => buffer,
+            None => return,
+        };
+        let len = buffer.len();
+        let remaining = len - self.read_idx;
+
+        let done = match (len, remaining) {
+            (1, _) if rx_ne => {
+                buffer[0] = i2c.dr.read().dr().bits();
+                true
+            }
+            (2, _) if btf => {
+                i2c.cr1.modify(|_, w| w.stop().set_bit());
+                buffer[0] = i2c.dr.read().dr().bits();
+                buffer[1] = i2c.dr.read().dr().bits();
+                true
+            }
+            (1, _) | (2, _) => false,
+            (_, 3) if btf => {
+                i2c.cr1.modify(|_, w| w.ack().clear_bit());
+                buffer[len - 3] = i2c.dr.read().dr().bits();
+                self.read_idx += 1;
+                false
+            }
+            (_, 2) if btf => {
+                i2c.cr1.modify(|_, w| w.stop().set_bit());
+                buffer[len - 2] = i2c.dr.read().dr().bits();
+                buffer[len - 1] = i2c.dr.read().dr().bits();
+                true
+            }
+            (_, remaining) if remaining > 3 && rx_ne => {
+                buffer[self.read_idx] = i2c.dr.read().dr().bits();
+                self.read_idx += 1;
+                if len - self.read_idx == 3 {
+                    // The last three bytes are read on BTF
+                    i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+                }
+                false
+            }
+            _ => false,
+        };
+
+        if done {
+            self.read_idx = len;
+            self.finish(Ok(()));
+        }
+    }
+
+    fn end_write(&mut self) {
+        if self.read_buf.is_some() {
+            self.read = true;
+            self.restart();
+        } else {
+            self.nb.send_stop();
+            self.finish(Ok(()));
+        }
+    }
+
+    /// Aborts the transaction on a bus error, call this from the `I2Cx_ER`
+    /// interrupt handler
+    pub fn handle_error(&mut self) {
+        let sr1 = self.nb.i2c.sr1.read();
+
+        let error = if sr1.berr().bit_is_set() {
+            Error::Bus
+        } else if sr1.arlo().bit_is_set() {
+            Error::Arbitration
+        } else if sr1.af().bit_is_set() {
+            Error::Acknowledge
+        } else if sr1.ovr().bit_is_set() {
+            Error::Overrun
+        } else {
+            return;
+        };
+
+        self.nb.i2c.sr1.modify(|_, w| {
+            w.berr()
+                .clear_bit()
+                .arlo()
+                .clear_bit()
+                .af()
+                .clear_bit()
+                .ovr()
+                .clear_bit()
+        });
+
+        // After an arbitration loss the peripheral already switched to slave mode
+        if error != Error::Arbitration {
+            self.nb.send_stop();
+        }
+        self.finish(Err(error));
+    }
+
+    /// Returns the result of the transaction once it is finished
+    ///
+    /// The read buffer is handed back with a successful read or write_read. After
+    /// an error it can be taken back with `take_buffer`.
+    pub fn poll(&mut self) -> NbResult<Option<&'static mut [u8]>, Error> {
+        match self.phase {
+            Phase::Idle => Ok(None),
+            Phase::Done(result) => {
+                self.phase = Phase::Idle;
+                result.map_err(Other)?;
+                Ok(self.read_buf.take())
+            }
+            _ => Err(WouldBlock),
+        }
+    }
+
+    /// Takes back the read buffer of a failed transaction
+    pub fn take_buffer(&mut self) -> Option<&'static mut [u8]> {
+        if self.phase == Phase::Idle {
+            self.read_buf.take()
+        } else {
+            None
+        }
+    }
+
+    /// Releases the I2C peripheral and associated pins
+    pub fn free(self) -> (I2C, PINS) {
+        self.nb.i2c.cr2.modify(|_, w| {
+            w.itevten()
+                .clear_bit()
+                .itbufen()
+                .clear_bit()
+                .iterren()
+                .clear_bit()
+        });
+        self.nb.free()
+    }
+}
+
+impl<I2C, PINS> InterruptI2c<I2C, PINS>
+where
+    I2C: Deref<Target = I2cRegisterBlock> + Enable + Reset,
+    I2C::Bus: GetBusFreq,
+{
+    /// Recomputes the bus timing after the clocks have been reconfigured
+    ///
+    /// Returns `WouldBlock` while a transaction is in progress.
+    pub fn reconfigure(&mut self, clocks: &Clocks) -> NbResult<(), Error> {
+        if self.phase != Phase::Idle {
+            return Err(WouldBlock);
+        }
+        self.nb.reconfigure(clocks);
+        self.nb.i2c.cr2.modify(|_, w| w.iterren().set_bit());
+        Ok(())
+    }
+}
+
+// DMA
+
+pub type I2cTxDma<I2C, PINS, CHANNEL> = TxDma<I2c<I2C, PINS>, CHANNEL>;
+
+pub type I2cRxDma<I2C, PINS, CHANNEL> = RxDma<I2c<I2C, PINS>, CHANNEL>;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum DmaPhase {
+    Start,
+    Address,
+    Write,
+    Data,
+    Done(Result<(), Error>),
+}
+
+/// Result of advancing a DMA transaction
+enum DmaStep {
+    Busy,
+    /// The address was acknowledged, the DMA requests have to be started
+    StartDma,
+    Finished,
+}
+
+/// Progress of a DMA transaction
+struct DmaState {
+    phase: DmaPhase,
+    addr: u8,
+    /// The DMA receives, after `bytes` have been written
+    rx: bool,
+    bytes: &'static [u8],
+    idx: usize,
+}
+
+/// DMA transaction of an `I2cTxDma` or `I2cRxDma`
+///
+/// The START condition, the address and the bytes written by `write_read` are
+/// handled by `poll`, the data is transferred by DMA. The event and error
+/// interrupts are enabled while the transaction is in progress, and the buffer
+/// interrupt while the bytes of `write_read` are written, so `poll` can be
+/// called from the `I2Cx_EV` and `I2Cx_ER` interrupt handlers. The end of a DMA
+/// reception raises no I2C event, `poll` also has to be called from the DMA
+/// channel interrupt (`Event::TransferComplete`) then.
+pub struct I2cTransfer<MODE, BUFFER, PAYLOAD> {
+    transfer: Transfer<MODE, BUFFER, PAYLOAD>,
+    state: DmaState,
+}
+
+impl<I2C, PINS> I2c<I2C, PINS>
+where
+    I2C: Deref<Target = I2cRegisterBlock>,
+{
+    /// Generates the START condition of a DMA transaction that writes `bytes`
+    /// before the DMA transfer
+    fn dma_begin(&mut self, addr: u8, bytes: &'static [u8], rx: bool) -> DmaState {
+        // Acknowledge every byte of a read but the last one, see `I2C_CR2.LAST`
+        self.i2c
+            .cr1
+            .modify(|_, w| w.pos().clear_bit().ack().set_bit());
+        self.i2c
+            .cr2
+            .modify(|_, w| w.last().bit(rx).itevten().set_bit().iterren().set_bit());
+        self.send_start();
+        DmaState {
+            phase: DmaPhase::Start,
+            addr,
+            rx,
+            bytes,
+            idx: 0,
+        }
+    }
+
+    /// Advances a DMA transaction, `complete` tells if the DMA channel finished
+    fn dma_step(&mut self, state: &mut DmaState, complete: bool) -> Result<DmaStep, Error> {
+        let sr1 = self.i2c.sr1.read();
+
+        if sr1.berr().bit_is_set() {
+            return Err(Error::Bus);
+        } else if sr1.arlo().bit_is_set() {
+            return Err(Error::Arbitration);
+        } else if sr1.af().bit_is_set() {
+            return Err(Error::Acknowledge);
+        } else if sr1.ovr().bit_is_set() {
+            return Err(Error::Overrun);
+        }
+
+        let writing = state.idx < state.bytes.len();
+        match state.phase {
+            DmaPhase::Start => {
+                if sr1.sb().bit_is_set() {
+                    let read = state.rx && !writing;
+                    self.send_addr(state.addr, read);
+                    state.phase = DmaPhase::Address;
+                }
+            }
+            DmaPhase::Address => {
+                if sr1.addr().bit_is_set() {
+                    if writing {
+                        // Without ITBUFEN, TxE raises no event interrupt
+                        self.i2c.cr2.modify(|_, w| w.itbufen().set_bit());
+                        self.i2c.sr2.read();
+                        state.phase = DmaPhase::Write;
+                    } else {
+                        // ADDR is cleared after the DMA requests are enabled
+                        state.phase = DmaPhase::Data;
+                        return Ok(DmaStep::StartDma);
+                    }
+                }
+            }
+            DmaPhase::Write => {
+                if writing {
+                    if sr1.tx_e().bit_is_set() {
+                        self.i2c.dr.write(|w| w.dr().bits(state.bytes[state.idx]));
+                        state.idx += 1;
+                        if state.idx == state.bytes.len() {
+                            // The repeated START is generated on BTF, which is an event
+                            self.i2c.cr2.modify(|_, w| w.itbufen().clear_bit());
+                        }
+                    }
+                } else if sr1.btf().bit_is_set() {
+                    // Setting START while BTF is set generates the repeated START
+                    self.send_start();
+                    state.phase = DmaPhase::Start;
+                }
+            }
+            DmaPhase::Data => {
+                // The last byte sent by DMA has to leave the shift register
+                if complete && (state.rx || sr1.btf().bit_is_set()) {
+                    self.send_stop();
+                    self.i2c.cr2.modify(|_, w| {
+                        w.itevten()
+                            .clear_bit()
+                            .iterren()
+                            .clear_bit()
+                            .last()
+                            .clear_bit()
+                    });
+                    state.phase = DmaPhase::Done(Ok(()));
+                    return Ok(DmaStep::Finished);
+                }
+            }
+            DmaPhase::Done(_) => {}
+        }
+        Ok(DmaStep::Busy)
+    }
+
+    /// Clears the error flags and disables the DMA requests and interrupts after a
+    /// failed transaction
+    ///
+    /// A STOP condition is generated unless the arbitration was lost, the peripheral
+    /// has then already switched to slave mode.
+    fn dma_abort(&mut self, error: Error) {
+        self.i2c.sr1.modify(|_, w| {
+            w.berr()
+                .clear_bit()
+                .arlo()
+                .clear_bit()
+                .af()
+                .clear_bit()
+                .ovr()
+                .clear_bit()
+        });
+        if error != Error::Arbitration {
+            self.send_stop();
+        }
+        self.i2c.cr2.modify(|_, w| {
+            w.dmaen()
+                .clear_bit()
+                .last()
+                .clear_bit()
+                .itevten()
+                .clear_bit()
+                .itbufen()
+                .clear_bit()
+                .iterren()
+                .clear_bit()
+        });
+    }
+}
+
+macro_rules! i2c_dma_transfer {
+    ($MODE:ty, $PAYLOAD:ty) => {
+        impl<B, PINS> I2cTransfer<$MODE, B, $PAYLOAD> {
+            /// Advances the transaction
+            ///
+            /// Returns `Ok` once the STOP condition has been generated. A NACK, bus
+            /// error, arbitration loss or DMA transfer error aborts the transaction
+            /// and is returned.
+            pub fn poll(&mut self) -> NbResult<(), Error> {
+                if let DmaPhase::Done(result) = self.state.phase {
+                    return result.map_err(Other);
+                }
+
+                let payload = &mut self.transfer.payload;
+                let result = if payload.channel.transfer_error() {
+                    Err(Error::Dma)
+                } else {
+                    let complete = !payload.channel.in_progress();
+                    payload.payload.dma_step(&mut self.state, complete)
+                };
+                match result {
+                    Ok(DmaStep::Busy) => Err(WouldBlock),
+                    Ok(DmaStep::StartDma) => {
+                        payload.start();
+                        // Clearing ADDR starts the DMA requests
+                        payload.payload.i2c.sr2.read();
+                        Err(WouldBlock)
+                    }
+                    Ok(DmaStep::Finished) => {
+                        atomic::compiler_fence(Ordering::Acquire);
+                        payload.stop();
+                        Ok(())
+                    }
+                    Err(error) => {
+                        payload.channel.stop();
+                        payload.payload.dma_abort(error);
+                        self.state.phase = DmaPhase::Done(Err(error));
+                        Err(Other(error))
+                    }
+                }
+            }
+
+            /// Returns `true` once the transaction is finished or aborted
+            pub fn is_done(&mut self) -> bool {
+                self.poll() != Err(WouldBlock)
+            }
+
+            /// Blocks until the transaction is finished and returns the buffer and
+            /// the payload, together with the error that aborted the transaction
+            pub fn wait(mut self) -> Result<(B, $PAYLOAD), (Error, B, $PAYLOAD)> {
+                let result = nb::block!(self.poll());
+                let Transfer {
+                    buffer, payload, ..
+                } = self.transfer;
+                match result {
+                    Ok(()) => Ok((buffer, payload)),
+                    Err(error) => Err((error, buffer, payload)),
+                }
+            }
+        }
+    };
+}
+
+macro_rules! i2c_dma {
+    ($I2Ci:ident, $RCi:ty, $TCi:ty) => {
+        impl<PINS> Transmit for I2cTxDma<$I2Ci, PINS, $TCi> {
+            type TxChannel = $TCi;
+            type ReceivedWord = u8;
+        }
+
+        impl<PINS> Receive for I2cRxDma<$I2Ci, PINS, $RCi> {
+            type RxChannel = $RCi;
+            type TransmittedWord = u8;
+        }
+
+        impl<PINS> I2c<$I2Ci, PINS> {
+            pub fn with_tx_dma(self, channel: $TCi) -> I2cTxDma<$I2Ci, PINS, $TCi> {
+                I2cTxDma {
+                    payload: self,
+                    channel,
+                }
+            }
+
+            pub fn with_rx_dma(self, channel: $RCi) -> I2cRxDma<$I2Ci, PINS, $RCi> {
+                I2cRxDma {
+                    payload: self,
+                    channel,
+                }
+            }
+        }
+
+        impl<PINS> I2cTxDma<$I2Ci, PINS, $TCi> {
+            pub fn release(self) -> (I2c<$I2Ci, PINS>, $TCi) {
+                let I2cTxDma { payload, channel } = self;
+                (payload, channel)
+            }
+
+            /// Starts writing `buffer` to the device at `addr`
+            ///
+            /// Nothing blocks, the transaction is advanced by `I2cTransfer::poll`.
+            pub fn write<A, B>(mut self, addr: u8, buffer: B) -> I2cTransfer<R, B, Self>
+            where
+                A: AsSlice<Element = u8>,
+                B: Static<A>,
+            {
+                {
+                    let buffer = buffer.borrow().as_slice();
+                    assert!(!buffer.is_empty());
+                    self.channel.set_peripheral_address(
+                        unsafe { &(*$I2Ci::ptr()).dr as *const _ as u32 },
+                        false,
+                    );
+                    self.channel
+                        .set_memory_address(buffer.as_ptr() as u32, true);
+                    self.channel.set_transfer_length(buffer.len());
+                }
+                atomic::compiler_fence(Ordering::Release);
+                self.channel.ch().cr.modify(|_, w| {
+                    w.mem2mem()
+                        .clear_bit()
+                        .pl()
+                        .medium()
+                        .msize()
+                        .bits8()
+                        .psize()
+                        .bits8()
+                        .circ()
+                        .clear_bit()
+                        .dir()
+                        .set_bit()
+                });
+
+                let state = self.payload.dma_begin(addr, &[], false);
+                I2cTransfer {
+                    transfer: Transfer::r(buffer, self),
+                    state,
+                }
+            }
+        }
+
+        impl<PINS> I2cRxDma<$I2Ci, PINS, $RCi> {
+            pub fn release(self) -> (I2c<$I2Ci, PINS>, $RCi) {
+                let I2cRxDma { payload, channel } = self;
+                (payload, channel)
+            }
+
+            /// Starts reading into `buffer` from the device at `addr`
+            ///
+            /// Nothing blocks, the transaction is advanced by `I2cTransfer::poll`.
+            /// The last byte is not acknowledged. DMA reception needs at least two
+            /// bytes, use `InterruptI2c` to read single bytes.
+            pub fn read<B>(
+                self,
+                addr: u8,
+                buffer: &'static mut B,
+            ) -> I2cTransfer<W, &'static mut B, Self>
+            where
+                B: AsMutSlice<Element = u8>,
+            {
+                self.write_read(addr, &[], buffer)
+            }
+
+            /// Starts writing `bytes` to the device at `addr` and reading into
+            /// `buffer` after a repeated START condition
+            ///
+            /// `bytes` are sent by `I2cTransfer::poll`, the read is then transferred
+            /// by DMA like `read`. DMA reception needs at least two bytes.
+            pub fn write_read<B>(
+                mut self,
+                addr: u8,
+                bytes: &'static [u8],
+                buffer: &'static mut B,
+            ) -> I2cTransfer<W, &'static mut B, Self>
+            where
+                B: AsMutSlice<Element = u8>,
+            {
+                {
+                    let buffer = buffer.as_mut_slice();
+                    assert!(buffer.len() >= 2);
+                    self.channel.set_peripheral_address(
+                        unsafe { &(*$I2Ci::ptr()).dr as *const _ as u32 },
+                        false,
+                    );
+                    self.channel
+                        .set_memory_address(buffer.as_ptr() as u32, true);
+                    self.channel.set_transfer_length(buffer.len());
+                }
+                atomic::compiler_fence(Ordering::Release);
+                self.channel.ch().cr.modify(|_, w| {
+                    w.mem2mem()
+                        .clear_bit()
+                        .pl()
+                        .medium()
+                        .msize()
+                        .bits8()
+                        .psize()
+                        .bits8()
+                        .circ()
+                        .clear_bit()
+                        .dir()
+                        .clear_bit()
+                });
+
+                let state = self.payload.dma_begin(addr, bytes, true);
+                I2cTransfer {
+                    transfer: Transfer::w(buffer, self),
+                    state,
+                }
+            }
+        }
+
+        impl<PINS> TransferPayload for I2cTxDma<$I2Ci, PINS, $TCi> {
+            fn start(&mut self) {
+                self.payload.i2c.cr2.modify(|_, w| w.dmaen().set_bit());
+                self.channel.start();
+            }
+            fn stop(&mut self) {
+                self.channel.stop();
+                self.payload.i2c.cr2.modify(|_, w| w.dmaen().clear_bit());
+            }
+        }
+
+        impl<PINS> TransferPayload for I2cRxDma<$I2Ci, PINS, $RCi> {
+            fn start(&mut self) {
+                self.payload.i2c.cr2.modify(|_, w| w.dmaen().set_bit());
+                self.channel.start();
+            }
+            fn stop(&mut self) {
+                self.channel.stop();
+                self.payload.i2c.cr2.modify(|_, w| w.dmaen().clear_bit());
+            }
+        }
+
+        i2c_dma_transfer!(R, I2cTxDma<$I2Ci, PINS, $TCi>);
+        i2c_dma_transfer!(W, I2cRxDma<$I2Ci, PINS, $RCi>);
+    };
+}
+
+i2c_dma!(I2C1, dma1::C7, dma1::C6);
+i2c_dma!(I2C2, dma1::C5, dma1::C4);
+
+/// I2C interrupt sources
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Start bit, address, stop, byte transfer finished (ITEVTEN)
+    Event,
+    /// Transmit buffer empty or receive buffer not empty, requires `Event` (ITBUFEN)
+    Buffer,
+    /// Bus error, arbitration loss, acknowledge failure, overrun (ITERREN)
+    Error,
+}
+
+/// Own address configuration for `I2cSlave`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SlaveAddress {
+    address: u16,
+    ten_bit: bool,
+    address2: Option<u8>,
+    general_call: bool,
+    fast_mode: bool,
+}
+
+impl SlaveAddress {
+    /// Responds to the 7-bit `address`
+    pub fn new(address: u8) -> Self {
+        assert!(address < 0x80);
+        SlaveAddress {
+            address: u16::from(address),
+            ten_bit: false,
+            address2: None,
+            general_call: false,
+            fast_mode: false,
+        }
+    }
+
+    /// Responds to the 10-bit `address`
+    ///
+    /// Dual addressing is only available in 7-bit mode.
+    pub fn ten_bit(address: u16) -> Self {
+        assert!(address < 0x400);
+        SlaveAddress {
+            address,
+            ten_bit: true,
+            address2: None,
+            general_call: false,
+            fast_mode: false,
+        }
+    }
+
+    /// Additionally responds to the 7-bit `address2` (OAR2)
+    pub fn dual_address(mut self, address2: u8) -> Self {
+        assert!(!self.ten_bit);
+        assert!(address2 < 0x80);
+        self.address2 = Some(address2);
+        self
+    }
+
+    /// Additionally responds to the general call address 0x00
+    pub fn general_call(mut self, enabled: bool) -> Self {
+        self.general_call = enabled;
+        self
+    }
+
+    /// Allows the master to clock the bus in fast mode (up to 400 kHz)
+    ///
+    /// Fast mode requires a peripheral clock of at least 4 MHz, standard mode only
+    /// requires 2 MHz.
+    pub fn fast_mode(mut self, enabled: bool) -> Self {
+        self.fast_mode = enabled;
+        self
+    }
+
+    fn min_pclk1_mhz(&self) -> u32 {
+        if self.fast_mode {
+            4
+        } else {
+            2
+        }
+    }
+}
+
+/// Address that was matched at the start of a transfer in slave mode
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchedAddress {
+    /// Address configured in OAR1
+    Primary,
+    /// Dual address configured in OAR2
+    Secondary,
+    /// General call address 0x00
+    GeneralCall,
+}
+
+/// Bus events seen by `I2cSlave`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SlaveEvent {
+    /// The master addressed this device. `read` is `true` if the master wants to
+    /// read from this device.
+    AddressMatch { address: MatchedAddress, read: bool },
+    /// The master wants to read a byte, answer with `I2cSlave::send()`.
+    ReadRequest,
+    /// The master wrote a byte.
+    WriteReceived(u8),
+    /// The master ended the transfer, either with a STOP condition or by not
+    /// acknowledging the last byte it read.
+    Stop,
+}
+
+/// I2C peripheral operating in slave mode
+///
+/// Clock stretching stays enabled: the bus is held after an address match until
+/// `poll` reports it, and while a `ReadRequest` is not answered or a received byte
+/// is not read.
+pub struct I2cSlave<I2C, PINS> {
+    i2c: I2C,
+    pins: PINS,
+    min_pclk1_mhz: u32,
+}
+
+impl<PINS> I2cSlave<I2C1, PINS> {
+    /// Creates an I2C1 slave on pins PB6 and PB7 or PB8 and PB9 (if remapped)
+    pub fn i2c1(
+        i2c: I2C1,
+        pins: PINS,
+        mapr: &mut MAPR,
+        address: SlaveAddress,
+        clocks: Clocks,
+        apb: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C1>,
+    {
+        mapr.modify_mapr(|_, w| w.i2c1_remap().bit(PINS::REMAP));
+        I2cSlave::<I2C1, _>::_i2c(i2c, pins, address, clocks, apb)
+    }
+}
+
+impl<PINS> I2cSlave<I2C2, PINS> {
+    /// Creates an I2C2 slave on pins PB10 and PB11
+    pub fn i2c2(
+        i2c: I2C2,
+        pins: PINS,
+        address: SlaveAddress,
+        clocks: Clocks,
+        apb: &mut APB1,
+    ) -> Self
+    where
+        PINS: Pins<I2C2>,
+    {
+        I2cSlave::<I2C2, _>::_i2c(i2c, pins, address, clocks, apb)
+    }
+}
+
+impl<I2C, PINS> I2cSlave<I2C, PINS>
+where
+    I2C: Deref<Target = I2cRegisterBlock> + Enable + Reset,
+    I2C::Bus: GetBusFreq,
+{
+    /// Configures the I2C peripheral to work in slave mode
+    fn _i2c(
+        i2c: I2C,
+        pins: PINS,
+        address: SlaveAddress,
+        clocks: Clocks,
+        apb: &mut I2C::Bus,
+    ) -> Self {
+        I2C::enable(apb);
+        I2C::reset(apb);
+
+        // The peripheral clock must be at least 2 MHz in standard mode and
+        // 4 MHz in fast mode
+        let min_pclk1_mhz = address.min_pclk1_mhz();
+        let pclk1_mhz = I2C::Bus::get_frequency(&clocks).0 / 1_000_000;
+        assert!(pclk1_mhz >= min_pclk1_mhz);
+
+        i2c.cr2.write(|w| unsafe { w.freq().bits(pclk1_mhz as u8) });
+
+        // Bit 14 of OAR1 must be kept at 1 by software
+        if address.ten_bit {
+            i2c.oar1.write(|w| unsafe {
+                w.bits(1 << 14)
+                    .addmode()
+                    .add10()
+                    .add()
+                    .bits(address.address)
+            });
+        } else {
+            i2c.oar1.write(|w| unsafe {
+                w.bits(1 << 14)
+                    .addmode()
+                    .add7()
+                    .add()
+                    .bits(address.address << 1)
+            });
+        }
+
+        match address.address2 {
+            Some(address2) => i2c.oar2.write(|w| w.add2().bits(address2).endual().dual()),
+            None => i2c.oar2.write(|w| w.endual().single()),
+        }
+
+        i2c.cr1.write(|w| {
+            w.engc()
+                .bit(address.general_call)
+                .nostretch()
+                .clear_bit()
+                .pe()
+                .set_bit()
+        });
+        // ACK can only be set once the peripheral is enabled
+        i2c.cr1.modify(|_, w| w.ack().set_bit());
+
+        I2cSlave {
+            i2c,
+            pins,
+            min_pclk1_mhz,
+        }
+    }
+
+    /// Updates the peripheral clock frequency in `I2C_CR2` after the clocks have been
+    /// reconfigured
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        let pclk1_mhz = I2C::Bus::get_frequency(clocks).0 / 1_000_000;
+        assert!(pclk1_mhz >= self.min_pclk1_mhz);
+
+        self.i2c
+            .cr2
+            .modify(|_, w| unsafe { w.freq().bits(pclk1_mhz as u8) });
+    }
+}
+
+impl<I2C, PINS> I2cSlave<I2C, PINS>
+where
+    I2C: Deref<Target = I2cRegisterBlock>,
+{
+    /// Starts listening for an interrupt event
+    pub fn listen(&mut self, event: Event) {
+        match event {
+            Event::Event => self.i2c.cr2.modify(|_, w| w.itevten().set_bit()),
+            Event::Buffer => self.i2c.cr2.modify(|_, w| w.itbufen().set_bit()),
+            Event::Error => self.i2c.cr2.modify(|_, w| w.iterren().set_bit()),
+        }
+    }
+
+    /// Stops listening for an interrupt event
+    pub fn unlisten(&mut self, event: Event) {
+        match event {
+            Event::Event => self.i2c.cr2.modify(|_, w| w.itevten().clear_bit()),
+            Event::Buffer => self.i2c.cr2.modify(|_, w| w.itbufen().clear_bit()),
+            Event::Error => self.i2c.cr2.modify(|_, w| w.iterren().clear_bit()),
+        }
+    }
+
+    /// Returns the next bus event, clearing the flags that caused it
+    ///
+    /// Call this from the I2C event and error interrupt handlers until it returns
+    /// `WouldBlock`, or poll it from the main loop.
+    pub fn poll(&mut self) -> NbResult<SlaveEvent, Error> {
+        let sr1 = self.i2c.sr1.read();
+
+        if sr1.berr().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.berr().clear_bit());
+            return Err(Other(Error::Bus));
+        }
+        if sr1.ovr().bit_is_set() {
+            self.i2c.sr1.modify(|_, w| w.ovr().clear_bit());
+            return Err(Other(Error::Overrun));
+        }
+
+        if sr1.addr().bit_is_set() {
+            // Reading SR2 after SR1 clears ADDR and releases SCL
+            let sr2 = self.i2c.sr2.read();
+            let address = if sr2.gencall().bit_is_set() {
+                MatchedAddress::GeneralCall
+            } else if sr2.dualf().bit_is_set() {
+                MatchedAddress::Secondary
+            } else {
+                MatchedAddress::Primary
+            };
+            return Ok(SlaveEvent::AddressMatch {
+                address,
+                read: sr2.tra().bit_is_set(),
+            });
+        }
+
+        if sr1.rx_ne().bit_is_set() {
+            return Ok(SlaveEvent::WriteReceived(self.i2c.dr.read().dr().bits()));
+        }
+
+        if sr1.stopf().bit_is_set() {
+            // STOPF is cleared by reading SR1 followed by a write to CR1
+            self.i2c.cr1.modify(|_, w| w.pe().set_bit());
+            return Ok(SlaveEvent::Stop);
+        }
+
+        if sr1.af().bit_is_set() {
+            // A slave transmitter ends with a NACK from the master, no STOPF is set
+            self.i2c.sr1.modify(|_, w| w.af().clear_bit());
+            return Ok(SlaveEvent::Stop);
+        }
+
+        if sr1.tx_e().bit_is_set() && self.i2c.sr2.read().tra().bit_is_set() {
+            return Ok(SlaveEvent::ReadRequest);
+        }
+
+        Err(WouldBlock)
+    }
+
+    /// Sends a byte in response to a `ReadRequest`
+    pub fn send(&mut self, byte: u8) {
+        self.i2c.dr.write(|w| w.dr().bits(byte));
+    }
+
+    /// Releases the I2C peripheral and associated pins
+    pub fn free(self) -> (I2C, PINS) {
+        self.i2c.cr1.modify(|_, w| w.pe().clear_bit());
+        (self.i2c, self.pins)
+    }
+}
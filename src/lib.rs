@@ -144,6 +144,8 @@ pub mod afio;
 pub mod backup_domain;
 #[cfg(feature = "device-selected")]
 pub mod bb;
+#[cfg(feature = "device-selected")]
+pub mod bootloader;
 #[cfg(all(feature = "device-selected", feature = "has-can"))]
 pub mod can;
 #[cfg(feature = "device-selected")]
@@ -153,6 +155,8 @@ pub mod delay;
 #[cfg(feature = "device-selected")]
 pub mod dma;
 #[cfg(feature = "device-selected")]
+pub mod eeprom;
+#[cfg(feature = "device-selected")]
 pub mod flash;
 #[cfg(feature = "device-selected")]
 pub mod gpio;
@@ -165,6 +169,8 @@ pub mod pwm;
 #[cfg(feature = "device-selected")]
 pub mod pwm_input;
 #[cfg(feature = "device-selected")]
+pub mod pwr;
+#[cfg(feature = "device-selected")]
 pub mod qei;
 #[cfg(feature = "device-selected")]
 pub mod rcc;
@@ -63,11 +63,12 @@ use crate::pac::TIM1;
 #[cfg(feature = "medium")]
 use crate::pac::TIM4;
 use crate::pac::{TIM2, TIM3};
-use cast::{u16, u32};
+use cast::{u16, u32, u64};
 
 use crate::afio::MAPR;
 use crate::bb;
 use crate::gpio::{self, Alternate, PushPull};
+use crate::rcc::{sealed::RccBus, Clocks, GetBusFreq};
 use crate::time::Hertz;
 use crate::time::U32Ext;
 use crate::timer::Timer;
@@ -307,6 +308,29 @@ macro_rules! hal {
                 }
             }
 
+            impl<REMAP, P, PINS> Pwm<$TIMX, REMAP, P, PINS>
+            where
+                REMAP: Remap<Periph = $TIMX>,
+                PINS: Pins<REMAP, P>,
+            {
+                /// Rescales the prescaler after the clocks have been reconfigured so that the
+                /// period stays the same
+                ///
+                /// The auto-reload value and the duty cycles are kept, so `get_max_duty` does not
+                /// change. The period is exact if the prescaler scaled by the ratio of the timer
+                /// clocks is an integer, otherwise the nearest prescaler is used. The new
+                /// prescaler is loaded at the next update event.
+                pub fn reconfigure(&mut self, clocks: &Clocks) {
+                    let clk = <$TIMX as RccBus>::Bus::get_timer_frequency(clocks);
+                    let tim = unsafe { &*$TIMX::ptr() };
+                    let psc = u64(tim.psc.read().psc().bits()) + 1;
+                    let psc = (psc * u64(clk.0) + u64(self.clk.0) / 2) / u64(self.clk.0);
+                    let psc = psc.max(1).min(1 << 16) - 1;
+                    self.clk = clk;
+                    tim.psc.write(|w| w.psc().bits(u16(psc).unwrap()));
+                }
+            }
+
         /*
         The following implemention of the embedded_hal::Pwm uses Hertz as a time type.  This was choosen
         because of the timescales of operations being on the order of nanoseconds and not being able to
@@ -0,0 +1,275 @@
+//! # Power control
+//!
+//! Entry into the Sleep, Stop and Standby low-power modes, the standby and wakeup flags and the
+//! programmable voltage detector (PVD).
+//!
+//! ```rust
+//! let dp = pac::Peripherals::take().unwrap();
+//! let mut cp = cortex_m::Peripherals::take().unwrap();
+//! let mut rcc = dp.RCC.constrain();
+//! let mut pwr = dp.PWR.constrain(&mut rcc.apb1);
+//!
+//! if pwr.woke_from_standby() {
+//!     pwr.clear_standby_flag();
+//! }
+//!
+//! pwr.enable_wakeup_pin();
+//! pwr.standby(&mut cp.SCB);
+//! ```
+
+use cortex_m::asm::{wfe, wfi};
+use cortex_m::peripheral::SCB;
+
+use crate::gpio::Edge;
+use crate::pac::{EXTI, PWR, RCC};
+use crate::rcc::APB1;
+
+/// Extension trait to constrain the PWR peripheral
+pub trait PwrExt {
+    /// Constrains the PWR peripheral and enables its clock
+    fn constrain(self, apb1: &mut APB1) -> Pwr;
+}
+
+impl PwrExt for PWR {
+    fn constrain(self, apb1: &mut APB1) -> Pwr {
+        apb1.set_pwren();
+        Pwr { pwr: self }
+    }
+}
+
+/// Instruction used to enter a low-power mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SleepEntry {
+    /// Wait for interrupt, any enabled interrupt wakes the core
+    Wfi,
+    /// Wait for event, any event or interrupt with SEVONPEND set wakes the core
+    Wfe,
+}
+
+/// Voltage regulator mode while in Stop mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Regulator {
+    /// The regulator stays on, wakeup is faster
+    Normal,
+    /// The regulator is in low-power mode, the current consumption is lower
+    LowPower,
+}
+
+/// Threshold of the programmable voltage detector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PvdLevel {
+    /// 2.2 V
+    V2_2 = 0b000,
+    /// 2.3 V
+    V2_3 = 0b001,
+    /// 2.4 V
+    V2_4 = 0b010,
+    /// 2.5 V
+    V2_5 = 0b011,
+    /// 2.6 V
+    V2_6 = 0b100,
+    /// 2.7 V
+    V2_7 = 0b101,
+    /// 2.8 V
+    V2_8 = 0b110,
+    /// 2.9 V
+    V2_9 = 0b111,
+}
+
+/// Constrained PWR peripheral
+pub struct Pwr {
+    pwr: PWR,
+}
+
+impl Pwr {
+    /// Enters Sleep mode, only the core clock is stopped
+    pub fn sleep(&mut self, scb: &mut SCB, entry: SleepEntry) {
+        scb.clear_sleepdeep();
+        enter(entry);
+    }
+
+    /// Makes the core enter Sleep mode again when it returns from the last interrupt handler
+    pub fn sleep_on_exit(&mut self, scb: &mut SCB, enable: bool) {
+        if enable {
+            scb.set_sleeponexit();
+        } else {
+            scb.clear_sleeponexit();
+        }
+    }
+
+    /// Enters Stop mode, all clocks in the 1.8 V domain are stopped
+    ///
+    /// The device is woken up by any EXTI line. On wakeup, SYSCLK runs from HSI, so HSE, the PLLs
+    /// and the SYSCLK source that were active before are restored before this function returns.
+    /// The prescalers and the flash wait states keep their settings, so the frozen `Clocks` stay
+    /// valid.
+    pub fn stop(&mut self, scb: &mut SCB, regulator: Regulator, entry: SleepEntry) {
+        let rcc = unsafe { &*RCC::ptr() };
+        let cr = rcc.cr.read();
+        let sws = rcc.cfgr.read().sws().bits();
+
+        self.pwr.cr.modify(|_, w| {
+            w.pdds()
+                .clear_bit()
+                .lpds()
+                .bit(regulator == Regulator::LowPower)
+        });
+        scb.set_sleepdeep();
+        enter(entry);
+        scb.clear_sleepdeep();
+
+        if cr.hseon().bit_is_set() {
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            while rcc.cr.read().hserdy().bit_is_clear() {}
+        }
+
+        #[cfg(feature = "connectivity")]
+        {
+            if cr.pll2on().bit_is_set() {
+                rcc.cr.modify(|_, w| w.pll2on().set_bit());
+                while rcc.cr.read().pll2rdy().bit_is_clear() {}
+            }
+            if cr.pll3on().bit_is_set() {
+                rcc.cr.modify(|_, w| w.pll3on().set_bit());
+                while rcc.cr.read().pll3rdy().bit_is_clear() {}
+            }
+        }
+
+        if cr.pllon().bit_is_set() {
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            while rcc.cr.read().pllrdy().bit_is_clear() {}
+        }
+
+        rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(sws) });
+        while rcc.cfgr.read().sws().bits() != sws {}
+    }
+
+    /// Enters Standby mode, the 1.8 V domain is powered off
+    ///
+    /// The device is woken up by a rising edge on the WKUP pin (if enabled), an RTC alarm, an
+    /// IWDG reset or an external reset on NRST. Wakeup continues like a reset, only the backup
+    /// domain is kept, see [woke_from_standby](#method.woke_from_standby).
+    pub fn standby(&mut self, scb: &mut SCB) -> ! {
+        self.pwr
+            .cr
+            .modify(|_, w| w.pdds().set_bit().cwuf().set_bit());
+        scb.set_sleepdeep();
+        loop {
+            wfi();
+        }
+    }
+
+    /// Enables wakeup from Standby mode by a rising edge on the WKUP pin (PA0)
+    ///
+    /// While enabled, PA0 is forced into input pull-down configuration.
+    pub fn enable_wakeup_pin(&mut self) {
+        self.pwr.csr.modify(|_, w| w.ewup().set_bit());
+    }
+
+    /// Disables the WKUP pin, PA0 can be used as a normal GPIO again
+    pub fn disable_wakeup_pin(&mut self) {
+        self.pwr.csr.modify(|_, w| w.ewup().clear_bit());
+    }
+
+    /// Returns `true` if the device was in Standby mode before the last reset (SBF)
+    pub fn woke_from_standby(&self) -> bool {
+        self.pwr.csr.read().sbf().bit_is_set()
+    }
+
+    /// Clears the standby flag
+    pub fn clear_standby_flag(&mut self) {
+        self.pwr.cr.modify(|_, w| w.csbf().set_bit());
+    }
+
+    /// Returns `true` if a wakeup event was received from the WKUP pin or the RTC alarm (WUF)
+    pub fn is_wakeup_flag_set(&self) -> bool {
+        self.pwr.csr.read().wuf().bit_is_set()
+    }
+
+    /// Clears the wakeup flag
+    pub fn clear_wakeup_flag(&mut self) {
+        self.pwr.cr.modify(|_, w| w.cwuf().set_bit());
+    }
+
+    /// Enables the programmable voltage detector with the given threshold
+    ///
+    /// The detector output is connected to EXTI line 16, see [Pvd](struct.Pvd.html).
+    pub fn enable_pvd(&mut self, level: PvdLevel) -> Pvd {
+        self.pwr
+            .cr
+            .modify(|_, w| unsafe { w.pls().bits(level as u8).pvde().set_bit() });
+        Pvd { _0: () }
+    }
+
+    /// Disables the programmable voltage detector
+    pub fn disable_pvd(&mut self, _pvd: Pvd) {
+        let exti = unsafe { &*EXTI::ptr() };
+        exti.imr.modify(|_, w| w.mr16().clear_bit());
+        self.pwr.cr.modify(|_, w| w.pvde().clear_bit());
+    }
+
+    /// Releases the PWR peripheral
+    pub fn release(self) -> PWR {
+        self.pwr
+    }
+}
+
+/// Enabled programmable voltage detector
+///
+/// Owns EXTI line 16. The line is set when VDD drops below the threshold and cleared when it
+/// rises above it again, so `Edge::RISING` reports a falling supply voltage.
+pub struct Pvd {
+    _0: (),
+}
+
+impl Pvd {
+    /// Returns `true` while VDD is below the threshold (PVDO)
+    pub fn is_below_threshold(&self) -> bool {
+        unsafe { (*PWR::ptr()).csr.read().pvdo().bit_is_set() }
+    }
+
+    /// Generates interrupts and events on the given edge of the detector output
+    pub fn trigger_on_edge(&mut self, exti: &EXTI, edge: Edge) {
+        match edge {
+            Edge::RISING => {
+                exti.rtsr.modify(|_, w| w.tr16().set_bit());
+                exti.ftsr.modify(|_, w| w.tr16().clear_bit());
+            }
+            Edge::FALLING => {
+                exti.ftsr.modify(|_, w| w.tr16().set_bit());
+                exti.rtsr.modify(|_, w| w.tr16().clear_bit());
+            }
+            Edge::RISING_FALLING => {
+                exti.rtsr.modify(|_, w| w.tr16().set_bit());
+                exti.ftsr.modify(|_, w| w.tr16().set_bit());
+            }
+        }
+    }
+
+    /// Enables the PVD interrupt on EXTI line 16
+    pub fn enable_interrupt(&mut self, exti: &EXTI) {
+        exti.imr.modify(|_, w| w.mr16().set_bit());
+    }
+
+    /// Disables the PVD interrupt on EXTI line 16
+    pub fn disable_interrupt(&mut self, exti: &EXTI) {
+        exti.imr.modify(|_, w| w.mr16().clear_bit());
+    }
+
+    /// Clears the interrupt pending bit of EXTI line 16
+    pub fn clear_interrupt_pending_bit(&mut self) {
+        unsafe { (*EXTI::ptr()).pr.write(|w| w.pr16().set_bit()) };
+    }
+
+    /// Reads the interrupt pending bit of EXTI line 16
+    pub fn check_interrupt(&mut self) -> bool {
+        unsafe { (*EXTI::ptr()).pr.read().pr16().bit_is_set() }
+    }
+}
+
+fn enter(entry: SleepEntry) {
+    match entry {
+        SleepEntry::Wfi => wfi(),
+        SleepEntry::Wfe => wfe(),
+    }
+}
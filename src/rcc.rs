@@ -6,6 +6,8 @@ use crate::pac::{rcc, PWR, RCC};
 use cast::u32;
 
 use crate::flash::ACR;
+use crate::gpio::gpioa::PA8;
+use crate::gpio::{Alternate, PushPull};
 use crate::time::Hertz;
 
 use crate::backup_domain::BackupDomain;
@@ -22,14 +24,7 @@ impl RccExt for RCC {
             ahb: AHB { _0: () },
             apb1: APB1 { _0: () },
             apb2: APB2 { _0: () },
-            cfgr: CFGR {
-                hse: None,
-                hclk: None,
-                pclk1: None,
-                pclk2: None,
-                sysclk: None,
-                adcclk: None,
-            },
+            cfgr: CFGR::new(),
             bkp: BKP { _0: () },
         }
     }
@@ -152,9 +147,56 @@ pub struct CFGR {
     pclk2: Option<u32>,
     sysclk: Option<u32>,
     adcclk: Option<u32>,
+    usbclk: bool,
+    css: bool,
+    hpre: Option<u32>,
+    ppre1: Option<u32>,
+    ppre2: Option<u32>,
+    adcpre: Option<u32>,
+    #[cfg(feature = "connectivity")]
+    pll2clk: Option<u32>,
+    #[cfg(feature = "connectivity")]
+    pll3clk: Option<u32>,
+    #[cfg(feature = "connectivity")]
+    prediv2: Option<u8>,
+    #[cfg(feature = "connectivity")]
+    prediv1src_pll2: bool,
+    #[cfg(feature = "connectivity")]
+    i2s2src_pll3: bool,
+    #[cfg(feature = "connectivity")]
+    i2s3src_pll3: bool,
 }
 
 impl CFGR {
+    fn new() -> Self {
+        CFGR {
+            hse: None,
+            hclk: None,
+            pclk1: None,
+            pclk2: None,
+            sysclk: None,
+            adcclk: None,
+            usbclk: false,
+            css: false,
+            hpre: None,
+            ppre1: None,
+            ppre2: None,
+            adcpre: None,
+            #[cfg(feature = "connectivity")]
+            pll2clk: None,
+            #[cfg(feature = "connectivity")]
+            pll3clk: None,
+            #[cfg(feature = "connectivity")]
+            prediv2: None,
+            #[cfg(feature = "connectivity")]
+            prediv1src_pll2: false,
+            #[cfg(feature = "connectivity")]
+            i2s2src_pll3: false,
+            #[cfg(feature = "connectivity")]
+            i2s3src_pll3: false,
+        }
+    }
+
     /// Uses HSE (external oscillator) instead of HSI (internal RC oscillator) as the clock source.
     /// Will result in a hang if an external oscillator is not connected or it fails to start.
     /// The frequency specified must be the frequency of the external oscillator
@@ -211,9 +253,74 @@ impl CFGR {
         self
     }
 
+    /// Enables the clock security system (CSS), which switches SYSCLK to HSI if HSE fails
+    ///
+    /// The failure raises an NMI, see [handle_css_interrupt](fn.handle_css_interrupt.html).
+    /// Has no effect if HSE is not used.
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Requires a valid 48 MHz USB clock. [try_freeze](#method.try_freeze) fails if the
+    /// clock tree can not provide one.
+    pub fn require_usbclk(mut self) -> Self {
+        self.usbclk = true;
+        self
+    }
+
+    /// Sets the desired frequency for the PLL2 clock, generated from HSE / PREDIV2
+    #[cfg(feature = "connectivity")]
+    pub fn pll2clk<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.pll2clk = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the desired frequency for the PLL3 clock, generated from HSE / PREDIV2
+    #[cfg(feature = "connectivity")]
+    pub fn pll3clk<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.pll3clk = Some(freq.into().0);
+        self
+    }
+
+    /// Sets the PREDIV2 divider (1 to 16) shared by PLL2 and PLL3. If it is not set, a
+    /// divider that generates both PLL frequencies is searched.
+    #[cfg(feature = "connectivity")]
+    pub fn prediv2(mut self, div: u8) -> Self {
+        self.prediv2 = Some(div);
+        self
+    }
+
+    /// Feeds PREDIV1, and with it the main PLL, from PLL2 instead of HSE (PREDIV1SRC)
+    #[cfg(feature = "connectivity")]
+    pub fn prediv1src_pll2(mut self) -> Self {
+        self.prediv1src_pll2 = true;
+        self
+    }
+
+    /// Clocks I2S2 from the PLL3 VCO (2 x PLL3CLK) instead of SYSCLK
+    #[cfg(feature = "connectivity")]
+    pub fn i2s2src_pll3(mut self) -> Self {
+        self.i2s2src_pll3 = true;
+        self
+    }
+
+    /// Clocks I2S3 from the PLL3 VCO (2 x PLL3CLK) instead of SYSCLK
+    #[cfg(feature = "connectivity")]
+    pub fn i2s3src_pll3(mut self) -> Self {
+        self.i2s3src_pll3 = true;
+        self
+    }
+
     /// Applies the clock configuration and returns a `Clocks` struct that signifies that the
-    /// clocks are frozen, and contains the frequencies used. After this function is called,
-    /// the clocks can not change
+    /// clocks are frozen, and contains the frequencies used. To change the clocks afterwards, see
+    /// [Clocks::reconfigure](struct.Clocks.html#method.reconfigure)
     ///
     /// Usage:
     ///
@@ -225,12 +332,271 @@ impl CFGR {
     /// ```
 
     pub fn freeze(self, acr: &mut ACR) -> Clocks {
+        let config = self.get_round_config();
+        Self::freeze_with_config(config, acr)
+    }
+
+    /// Applies the clock configuration like [freeze](#method.freeze), but only if all
+    /// requested frequencies can be generated exactly
+    ///
+    /// Unlike `freeze`, this explores all clock sources, PLL prescalers and multipliers and
+    /// fails instead of rounding. See [get_config](#method.get_config) for details.
+    ///
+    /// ```rust
+    /// let clocks = rcc
+    ///     .cfgr
+    ///     .use_hse(8.mhz())
+    ///     .sysclk(48.mhz())
+    ///     .require_usbclk()
+    ///     .try_freeze(&mut flash.acr)
+    ///     .unwrap();
+    /// ```
+    pub fn try_freeze(self, acr: &mut ACR) -> Result<Clocks, Error> {
+        let config = self.get_config()?;
+        Ok(Self::freeze_with_config(config, acr))
+    }
+
+    /// Calculates the register settings for the requested frequencies
+    ///
+    /// Frequencies that were not requested are chosen as high as the device allows, SYSCLK
+    /// defaults to the oscillator frequency. If a frequency can not be generated exactly, the
+    /// clock that can not be generated is returned as `Err`.
+    ///
+    /// This does not access any registers.
+    pub fn get_config(&self) -> Result<RawConfig, Error> {
+        if let Some(hse) = self.hse {
+            if !(limits::HSE_MIN..=limits::HSE_MAX).contains(&hse) {
+                return Err(Error::HseOutOfRange);
+            }
+        }
+        let src = self.hse.unwrap_or(HSI);
+
+        #[cfg(feature = "connectivity")]
+        let pll23 = self.pll23_config()?;
+        #[cfg(feature = "connectivity")]
+        let prediv1_in = if self.prediv1src_pll2 {
+            pll23.pll2clk
+        } else {
+            self.hse
+        };
+        #[cfg(not(feature = "connectivity"))]
+        let prediv1_in = self.hse;
+
+        let (prediv1, pllmul, sysclk) = if self.usbclk {
+            if self.hse.is_none() || !cfg!(any(feature = "stm32f103", feature = "connectivity")) {
+                return Err(Error::Usbclk);
+            }
+            // The USB prescaler divides the PLL output by 1 or 1.5
+            let pllclk = match self.sysclk {
+                Some(sysclk) => [sysclk, sysclk],
+                None => [72_000_000, 48_000_000],
+            };
+            pllclk
+                .iter()
+                .filter(|&&pllclk| pllclk == 72_000_000 || pllclk == 48_000_000)
+                .find_map(|&pllclk| {
+                    self.pll_config(pllclk, prediv1_in)
+                        .map(|(prediv1, pllmul)| (prediv1, Some(pllmul), pllclk))
+                })
+                .ok_or(Error::Usbclk)?
+        } else {
+            match self.sysclk {
+                None => (1, None, src),
+                Some(sysclk) if sysclk == src => (1, None, src),
+                Some(sysclk) => self
+                    .pll_config(sysclk, prediv1_in)
+                    .map(|(prediv1, pllmul)| (prediv1, Some(pllmul), sysclk))
+                    .ok_or(Error::Sysclk)?,
+            }
+        };
+        if sysclk > limits::SYSCLK_MAX {
+            return Err(Error::Sysclk);
+        }
+
+        let (hdiv, hpre) =
+            divider(sysclk, self.hclk, self.hpre, limits::SYSCLK_MAX, &HPRE).ok_or(Error::Hclk)?;
+        let hclk = sysclk / hdiv;
+        let (ppre1, ppre1_bits) =
+            divider(hclk, self.pclk1, self.ppre1, limits::PCLK1_MAX, &PPRE).ok_or(Error::Pclk1)?;
+        let (ppre2, ppre2_bits) =
+            divider(hclk, self.pclk2, self.ppre2, limits::SYSCLK_MAX, &PPRE).ok_or(Error::Pclk2)?;
+        let pclk2 = hclk / ppre2;
+        let (adcdiv, adcpre) =
+            divider(pclk2, self.adcclk, self.adcpre, limits::ADCCLK_MAX, &ADCPRE)
+                .ok_or(Error::Adcclk)?;
+
+        let (usbpre, usbclk_valid) = usb_prescaler(self.hse, pllmul, sysclk);
+
+        Ok(RawConfig {
+            hse: self.hse,
+            prediv1,
+            pllmul,
+            hpre,
+            ppre1: ppre1_bits,
+            ppre2: ppre2_bits,
+            adcpre,
+            usbpre,
+            css: self.css,
+            #[cfg(feature = "connectivity")]
+            pll23,
+            clocks: Clocks {
+                hclk: Hertz(hclk),
+                pclk1: Hertz(hclk / ppre1),
+                pclk2: Hertz(pclk2),
+                ppre1: ppre1 as u8,
+                ppre2: ppre2 as u8,
+                sysclk: Hertz(sysclk),
+                adcclk: Hertz(pclk2 / adcdiv),
+                usbclk_valid,
+                hse: self.hse.map(Hertz),
+                pllclk: pllmul.map(|_| Hertz(sysclk)),
+                #[cfg(feature = "connectivity")]
+                pll2clk: pll23.pll2clk.map(Hertz),
+                #[cfg(feature = "connectivity")]
+                pll3clk: pll23.pll3clk.map(Hertz),
+                #[cfg(feature = "connectivity")]
+                i2s2clk: Hertz(pll23.i2sclk(pll23.i2s2src, sysclk)),
+                #[cfg(feature = "connectivity")]
+                i2s3clk: Hertz(pll23.i2sclk(pll23.i2s3src, sysclk)),
+            },
+        })
+    }
+
+    /// Searches the PREDIV2 divider and the PLL2 and PLL3 multiplication factors that generate
+    /// the requested frequencies exactly
+    #[cfg(feature = "connectivity")]
+    fn pll23_config(&self) -> Result<Pll23Config, Error> {
+        let mut config = Pll23Config {
+            prediv2: 1,
+            pll2mul: None,
+            pll3mul: None,
+            pll2clk: None,
+            pll3clk: None,
+            prediv1src: self.prediv1src_pll2,
+            i2s2src: self.i2s2src_pll3,
+            i2s3src: self.i2s3src_pll3,
+        };
+        if self.prediv1src_pll2 && self.pll2clk.is_none() {
+            return Err(Error::Pll2);
+        }
+        if (self.i2s2src_pll3 || self.i2s3src_pll3) && self.pll3clk.is_none() {
+            return Err(Error::Pll3);
+        }
+        if self.pll2clk.is_none() && self.pll3clk.is_none() {
+            return Ok(config);
+        }
+        let hse = match self.hse {
+            Some(hse) => hse,
+            None if self.pll2clk.is_some() => return Err(Error::Pll2),
+            None => return Err(Error::Pll3),
+        };
+
+        // Finds the multiplication factor for `pllclk`, `Some(None)` if the PLL is not used
+        let pll = |pllin: u32, pllclk: Option<u32>| match pllclk {
+            None => Some(None),
+            Some(pllclk) => PLL23MUL
+                .iter()
+                .find(|&&(mul, _)| {
+                    pllin * mul == pllclk
+                        && (limits::PLL23OUT_MIN..=limits::PLL23OUT_MAX).contains(&pllclk)
+                })
+                .map(|&(_, bits)| Some(bits)),
+        };
+
+        let (first, last) = match self.prediv2 {
+            Some(prediv2) => (prediv2, prediv2),
+            None => (1, 16),
+        };
+        let mut pll2_found = false;
+        for prediv2 in first..=last {
+            if !(1..=16).contains(&prediv2) || hse % u32(prediv2) != 0 {
+                continue;
+            }
+            let pllin = hse / u32(prediv2);
+            if !(limits::PLL23IN_MIN..=limits::PLL23IN_MAX).contains(&pllin) {
+                continue;
+            }
+            if let Some(pll2mul) = pll(pllin, self.pll2clk) {
+                pll2_found = true;
+                if let Some(pll3mul) = pll(pllin, self.pll3clk) {
+                    config.prediv2 = prediv2;
+                    config.pll2mul = pll2mul;
+                    config.pll3mul = pll3mul;
+                    config.pll2clk = self.pll2clk;
+                    config.pll3clk = self.pll3clk;
+                    return Ok(config);
+                }
+            }
+        }
+        Err(if pll2_found { Error::Pll3 } else { Error::Pll2 })
+    }
+
+    /// Searches the PLL input divider and multiplication factor that generate `pllclk`
+    /// exactly. Returns the divider and the PLLMUL bits.
+    ///
+    /// `prediv1_in` is the PREDIV1 input clock, without one the PLL is fed by HSI / 2.
+    fn pll_config(&self, pllclk: u32, prediv1_in: Option<u32>) -> Option<(u8, u8)> {
+        let (src, prediv_max) = match prediv1_in {
+            Some(src) => (src, limits::PREDIV1_MAX),
+            None => (HSI / 2, 1),
+        };
+        for prediv1 in 1..=prediv_max {
+            if src % u32(prediv1) != 0 {
+                continue;
+            }
+            let pllin = src / u32(prediv1);
+            if !(limits::PLLIN_MIN..=limits::PLLIN_MAX).contains(&pllin) {
+                continue;
+            }
+            for &(mul_x2, bits) in PLLMUL.iter() {
+                if pllin * mul_x2 == pllclk * 2
+                    && (limits::PLLOUT_MIN..=limits::PLLOUT_MAX).contains(&pllclk)
+                {
+                    return Some((prediv1, bits));
+                }
+            }
+        }
+        None
+    }
+
+    /// Calculates the register settings for the requested frequencies, rounding them to
+    /// frequencies close to the requested ones. This is the configuration applied by
+    /// [freeze](#method.freeze).
+    ///
+    /// PLL2 and PLL3 are not rounded, this panics if their frequencies can not be
+    /// generated exactly. Use [get_config](#method.get_config) to get an `Err` instead.
+    pub fn get_round_config(&self) -> RawConfig {
+        #[cfg(feature = "connectivity")]
+        let pll23 = self
+            .pll23_config()
+            .expect("PLL2 or PLL3 frequency can not be generated, use `try_freeze`");
+        #[cfg(feature = "connectivity")]
+        let pll2_src = if self.prediv1src_pll2 {
+            pll23.pll2clk
+        } else {
+            None
+        };
+        #[cfg(not(feature = "connectivity"))]
+        let pll2_src: Option<u32> = None;
+
         let pllsrcclk = self.hse.unwrap_or(HSI / 2);
 
         let pllmul = self.sysclk.unwrap_or(pllsrcclk) / pllsrcclk;
 
-        let (pllmul_bits, sysclk) = if pllmul == 1 {
-            (None, self.hse.unwrap_or(HSI))
+        let (prediv1, pllmul_bits, sysclk) = if let Some(pll2clk) = pll2_src {
+            // PREDIV1 has to bring PLL2 down to the PLL input range, so round to the exact
+            // configuration
+            match self.sysclk {
+                Some(sysclk) => {
+                    let (prediv1, pllmul_bits) = self
+                        .pll_config(sysclk, Some(pll2clk))
+                        .expect("SYSCLK can not be generated from PLL2");
+                    (prediv1, Some(pllmul_bits), sysclk)
+                }
+                None => (1, None, pllsrcclk),
+            }
+        } else if pllmul == 1 {
+            (1, None, self.hse.unwrap_or(HSI))
         } else {
             #[cfg(not(feature = "connectivity"))]
             let pllmul = cmp::min(cmp::max(pllmul, 1), 16);
@@ -238,7 +604,7 @@ impl CFGR {
             #[cfg(feature = "connectivity")]
             let pllmul = cmp::min(cmp::max(pllmul, 4), 9);
 
-            (Some(pllmul as u8 - 2), pllsrcclk * pllmul)
+            (1, Some(pllmul as u8 - 2), pllsrcclk * pllmul)
         };
 
         assert!(sysclk <= 72_000_000);
@@ -257,6 +623,12 @@ impl CFGR {
                 192..=383 => 0b1110,
                 _ => 0b1111,
             })
+            // Kept prescalers are lower bounds, see `Clocks::reconfigure`
+            .or_else(|| {
+                self.hpre
+                    .and_then(|div| divider(sysclk, None, Some(div), limits::SYSCLK_MAX, &HPRE))
+                    .map(|(_, bits)| bits)
+            })
             .unwrap_or(0b0111);
 
         let hclk = if hpre_bits >= 0b1100 {
@@ -277,6 +649,11 @@ impl CFGR {
                 6..=11 => 0b110,
                 _ => 0b111,
             })
+            .or_else(|| {
+                self.ppre1
+                    .and_then(|div| divider(hclk, None, Some(div), limits::PCLK1_MAX, &PPRE))
+                    .map(|(_, bits)| bits)
+            })
             .unwrap_or(0b011);
 
         let ppre1 = 1 << (ppre1_bits - 0b011);
@@ -294,6 +671,11 @@ impl CFGR {
                 6..=11 => 0b110,
                 _ => 0b111,
             })
+            .or_else(|| {
+                self.ppre2
+                    .and_then(|div| divider(hclk, None, Some(div), limits::SYSCLK_MAX, &PPRE))
+                    .map(|(_, bits)| bits)
+            })
             .unwrap_or(0b011);
 
         let ppre2 = 1 << (ppre2_bits - 0b011);
@@ -301,28 +683,7 @@ impl CFGR {
 
         assert!(pclk2 <= 72_000_000);
 
-        // adjust flash wait states
-        #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
-        unsafe {
-            acr.acr().write(|w| {
-                w.latency().bits(if sysclk <= 24_000_000 {
-                    0b000
-                } else if sysclk <= 48_000_000 {
-                    0b001
-                } else {
-                    0b010
-                })
-            })
-        }
-
-        // the USB clock is only valid if an external crystal is used, the PLL is enabled, and the
-        // PLL output frequency is a supported one.
-        // usbpre == false: divide clock by 1.5, otherwise no division
-        let (usbpre, usbclk_valid) = match (self.hse, pllmul_bits, sysclk) {
-            (Some(_), Some(_), 72_000_000) => (false, true),
-            (Some(_), Some(_), 48_000_000) => (true, true),
-            _ => (true, false),
-        };
+        let (usbpre, usbclk_valid) = usb_prescaler(self.hse, pllmul_bits, sysclk);
 
         let apre_bits: u8 = self
             .adcclk
@@ -332,6 +693,11 @@ impl CFGR {
                 5..=7 => 0b10,
                 _ => 0b11,
             })
+            .or_else(|| {
+                self.adcpre
+                    .and_then(|div| divider(pclk2, None, Some(div), limits::ADCCLK_MAX, &ADCPRE))
+                    .map(|(_, bits)| bits)
+            })
             .unwrap_or(0b11);
 
         let apre = (apre_bits + 1) << 1;
@@ -339,25 +705,143 @@ impl CFGR {
 
         assert!(adcclk <= 14_000_000);
 
+        RawConfig {
+            hse: self.hse,
+            prediv1,
+            pllmul: pllmul_bits,
+            hpre: hpre_bits,
+            ppre1: ppre1_bits,
+            ppre2: ppre2_bits,
+            adcpre: apre_bits,
+            usbpre,
+            css: self.css,
+            #[cfg(feature = "connectivity")]
+            pll23,
+            clocks: Clocks {
+                hclk: Hertz(hclk),
+                pclk1: Hertz(pclk1),
+                pclk2: Hertz(pclk2),
+                ppre1,
+                ppre2,
+                sysclk: Hertz(sysclk),
+                adcclk: Hertz(adcclk),
+                usbclk_valid,
+                hse: self.hse.map(Hertz),
+                pllclk: pllmul_bits.map(|_| Hertz(sysclk)),
+                #[cfg(feature = "connectivity")]
+                pll2clk: pll23.pll2clk.map(Hertz),
+                #[cfg(feature = "connectivity")]
+                pll3clk: pll23.pll3clk.map(Hertz),
+                #[cfg(feature = "connectivity")]
+                i2s2clk: Hertz(pll23.i2sclk(pll23.i2s2src, sysclk)),
+                #[cfg(feature = "connectivity")]
+                i2s3clk: Hertz(pll23.i2sclk(pll23.i2s3src, sysclk)),
+            },
+        }
+    }
+
+    fn freeze_with_config(config: RawConfig, acr: &mut ACR) -> Clocks {
         let rcc = unsafe { &*RCC::ptr() };
 
-        if self.hse.is_some() {
+        // run from HSI while the PLLs and the flash wait states are changed, after reset
+        // this is already the case
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+        rcc.cfgr.modify(|_, w| w.sw().hsi());
+        while !rcc.cfgr.read().sws().is_hsi() {}
+
+        // the PLLs can only be configured while they are disabled
+        rcc.cr.modify(|_, w| w.pllon().clear_bit());
+        while rcc.cr.read().pllrdy().bit_is_set() {}
+
+        #[cfg(feature = "connectivity")]
+        {
+            rcc.cr
+                .modify(|_, w| w.pll2on().clear_bit().pll3on().clear_bit());
+            while rcc.cr.read().pll2rdy().bit_is_set() || rcc.cr.read().pll3rdy().bit_is_set() {}
+        }
+
+        if !config.css {
+            rcc.cr.modify(|_, w| w.csson().clear_bit());
+        }
+        if config.hse.is_none() {
+            rcc.cr.modify(|_, w| w.hseon().clear_bit());
+        }
+
+        // adjust flash wait states
+        #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+        unsafe {
+            let sysclk = config.clocks.sysclk.0;
+            acr.acr().write(|w| {
+                w.latency().bits(if sysclk <= 24_000_000 {
+                    0b000
+                } else if sysclk <= 48_000_000 {
+                    0b001
+                } else {
+                    0b010
+                })
+            })
+        }
+
+        if config.hse.is_some() {
             // enable HSE and wait for it to be ready
 
             rcc.cr.modify(|_, w| w.hseon().set_bit());
 
             while rcc.cr.read().hserdy().bit_is_clear() {}
+
+            if config.css {
+                rcc.cr.modify(|_, w| w.csson().set_bit());
+            }
+        }
+
+        #[cfg(feature = "connectivity")]
+        {
+            let pll23 = config.pll23;
+            rcc.cfgr2.modify(|_, w| unsafe {
+                if let Some(pll2mul) = pll23.pll2mul {
+                    w.pll2mul().bits(pll2mul);
+                }
+                if let Some(pll3mul) = pll23.pll3mul {
+                    w.pll3mul().bits(pll3mul);
+                }
+                w.prediv2()
+                    .bits(pll23.prediv2 - 1)
+                    .prediv1src()
+                    .bit(pll23.prediv1src)
+                    .i2s2src()
+                    .bit(pll23.i2s2src)
+                    .i2s3src()
+                    .bit(pll23.i2s3src)
+            });
+
+            // enable PLL2 and PLL3 and wait for them to be ready
+            if pll23.pll2mul.is_some() {
+                rcc.cr.modify(|_, w| w.pll2on().set_bit());
+                while rcc.cr.read().pll2rdy().bit_is_clear() {}
+            }
+            if pll23.pll3mul.is_some() {
+                rcc.cr.modify(|_, w| w.pll3on().set_bit());
+                while rcc.cr.read().pll3rdy().bit_is_clear() {}
+            }
         }
 
-        if let Some(pllmul_bits) = pllmul_bits {
+        if let Some(pllmul_bits) = config.pllmul {
             // enable PLL and wait for it to be ready
 
+            #[cfg(any(feature = "stm32f100", feature = "connectivity"))]
+            rcc.cfgr2
+                .modify(|_, w| w.prediv1().bits(config.prediv1 - 1));
+
             #[allow(unused_unsafe)]
             rcc.cfgr.modify(|_, w| unsafe {
+                #[cfg(any(feature = "stm32f101", feature = "stm32f103"))]
+                w.pllxtpre().bit(config.prediv1 == 2);
                 w.pllmul()
                     .bits(pllmul_bits)
                     .pllsrc()
-                    .bit(self.hse.is_some())
+                    .bit(config.hse.is_some())
             });
 
             rcc.cr.modify(|_, w| w.pllon().set_bit());
@@ -365,90 +849,306 @@ impl CFGR {
             while rcc.cr.read().pllrdy().bit_is_clear() {}
         }
 
+        let sw_bits = if config.pllmul.is_some() {
+            // PLL
+            0b10
+        } else if config.hse.is_some() {
+            // HSE
+            0b1
+        } else {
+            // HSI
+            0b0
+        };
+
         // set prescalers and clock source
         #[cfg(feature = "connectivity")]
         rcc.cfgr.modify(|_, w| unsafe {
-            w.adcpre().bits(apre_bits);
+            w.adcpre().bits(config.adcpre);
             w.ppre2()
-                .bits(ppre2_bits)
+                .bits(config.ppre2)
                 .ppre1()
-                .bits(ppre1_bits)
+                .bits(config.ppre1)
                 .hpre()
-                .bits(hpre_bits)
+                .bits(config.hpre)
                 .otgfspre()
-                .bit(usbpre)
+                .bit(config.usbpre)
                 .sw()
-                .bits(if pllmul_bits.is_some() {
-                    // PLL
-                    0b10
-                } else if self.hse.is_some() {
-                    // HSE
-                    0b1
-                } else {
-                    // HSI
-                    0b0
-                })
+                .bits(sw_bits)
         });
 
         #[cfg(feature = "stm32f103")]
         rcc.cfgr.modify(|_, w| unsafe {
-            w.adcpre().bits(apre_bits);
+            w.adcpre().bits(config.adcpre);
             w.ppre2()
-                .bits(ppre2_bits)
+                .bits(config.ppre2)
                 .ppre1()
-                .bits(ppre1_bits)
+                .bits(config.ppre1)
                 .hpre()
-                .bits(hpre_bits)
+                .bits(config.hpre)
                 .usbpre()
-                .bit(usbpre)
+                .bit(config.usbpre)
                 .sw()
-                .bits(if pllmul_bits.is_some() {
-                    // PLL
-                    0b10
-                } else if self.hse.is_some() {
-                    // HSE
-                    0b1
-                } else {
-                    // HSI
-                    0b0
-                })
+                .bits(sw_bits)
         });
 
         #[cfg(any(feature = "stm32f100", feature = "stm32f101"))]
         rcc.cfgr.modify(|_, w| unsafe {
-            w.adcpre().bits(apre_bits);
+            w.adcpre().bits(config.adcpre);
             w.ppre2()
-                .bits(ppre2_bits)
+                .bits(config.ppre2)
                 .ppre1()
-                .bits(ppre1_bits)
+                .bits(config.ppre1)
                 .hpre()
-                .bits(hpre_bits)
+                .bits(config.hpre)
                 .sw()
-                .bits(if pllmul_bits.is_some() {
-                    // PLL
-                    0b10
-                } else if self.hse.is_some() {
-                    // HSE
-                    0b1
-                } else {
-                    // HSI
-                    0b0
-                })
+                .bits(sw_bits)
         });
 
-        Clocks {
-            hclk: Hertz(hclk),
-            pclk1: Hertz(pclk1),
-            pclk2: Hertz(pclk2),
-            ppre1,
-            ppre2,
-            sysclk: Hertz(sysclk),
-            adcclk: Hertz(adcclk),
-            usbclk_valid,
+        config.clocks
+    }
+}
+
+/// Register settings of a clock configuration
+///
+/// Calculated by [CFGR::get_config](struct.CFGR.html#method.get_config) or
+/// [CFGR::get_round_config](struct.CFGR.html#method.get_round_config).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawConfig {
+    hse: Option<u32>,
+    prediv1: u8,
+    pllmul: Option<u8>,
+    hpre: u8,
+    ppre1: u8,
+    ppre2: u8,
+    adcpre: u8,
+    usbpre: bool,
+    css: bool,
+    #[cfg(feature = "connectivity")]
+    pll23: Pll23Config,
+    clocks: Clocks,
+}
+
+impl RawConfig {
+    /// Returns the clock frequencies resulting from this configuration
+    pub fn clocks(&self) -> Clocks {
+        self.clocks
+    }
+}
+
+/// PLL2, PLL3 and I2S clock settings of connectivity line devices
+#[cfg(feature = "connectivity")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Pll23Config {
+    prediv2: u8,
+    pll2mul: Option<u8>,
+    pll3mul: Option<u8>,
+    pll2clk: Option<u32>,
+    pll3clk: Option<u32>,
+    prediv1src: bool,
+    i2s2src: bool,
+    i2s3src: bool,
+}
+
+#[cfg(feature = "connectivity")]
+impl Pll23Config {
+    /// I2S clock, either SYSCLK or the PLL3 VCO
+    fn i2sclk(&self, pll3: bool, sysclk: u32) -> u32 {
+        match self.pll3clk {
+            Some(pll3clk) if pll3 => 2 * pll3clk,
+            _ => sysclk,
         }
     }
 }
 
+/// Clock configuration error, names the clock that can not be generated
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The HSE frequency is outside of the oscillator range
+    HseOutOfRange,
+    /// SYSCLK can not be generated exactly or exceeds the maximum frequency
+    Sysclk,
+    /// HCLK can not be divided exactly from SYSCLK
+    Hclk,
+    /// PCLK1 can not be divided exactly from HCLK or exceeds the maximum frequency
+    Pclk1,
+    /// PCLK2 can not be divided exactly from HCLK or exceeds the maximum frequency
+    Pclk2,
+    /// ADCCLK can not be divided exactly from PCLK2 or exceeds the maximum frequency
+    Adcclk,
+    /// A 48 MHz USB clock requires USB support, HSE and a 48 MHz or 72 MHz PLL output
+    Usbclk,
+    /// PLL2 is required but not set, or can not be generated exactly from HSE
+    #[cfg(feature = "connectivity")]
+    Pll2,
+    /// PLL3 is required but not set, or can not be generated exactly from HSE
+    #[cfg(feature = "connectivity")]
+    Pll3,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match self {
+            Error::HseOutOfRange => "HSE frequency out of range",
+            Error::Sysclk => "SYSCLK frequency can not be generated",
+            Error::Hclk => "HCLK frequency can not be generated",
+            Error::Pclk1 => "PCLK1 frequency can not be generated",
+            Error::Pclk2 => "PCLK2 frequency can not be generated",
+            Error::Adcclk => "ADCCLK frequency can not be generated",
+            Error::Usbclk => "48 MHz USB clock can not be generated",
+            #[cfg(feature = "connectivity")]
+            Error::Pll2 => "PLL2 frequency can not be generated",
+            #[cfg(feature = "connectivity")]
+            Error::Pll3 => "PLL3 frequency can not be generated",
+        })
+    }
+}
+
+/// Frequency limits of the device, see the datasheet
+#[cfg(feature = "stm32f100")]
+mod limits {
+    pub const HSE_MIN: u32 = 4_000_000;
+    pub const HSE_MAX: u32 = 24_000_000;
+    pub const PREDIV1_MAX: u8 = 16;
+    pub const PLLIN_MIN: u32 = 1_000_000;
+    pub const PLLIN_MAX: u32 = 24_000_000;
+    pub const PLLOUT_MIN: u32 = 16_000_000;
+    pub const PLLOUT_MAX: u32 = 24_000_000;
+    pub const SYSCLK_MAX: u32 = 24_000_000;
+    pub const PCLK1_MAX: u32 = 24_000_000;
+    pub const ADCCLK_MAX: u32 = 12_000_000;
+}
+
+/// Frequency limits of the device, see the datasheet
+#[cfg(any(feature = "stm32f101", feature = "stm32f103"))]
+mod limits {
+    pub const HSE_MIN: u32 = 4_000_000;
+    pub const HSE_MAX: u32 = 16_000_000;
+    pub const PREDIV1_MAX: u8 = 2;
+    pub const PLLIN_MIN: u32 = 1_000_000;
+    pub const PLLIN_MAX: u32 = 25_000_000;
+    pub const PLLOUT_MIN: u32 = 16_000_000;
+    #[cfg(feature = "stm32f101")]
+    pub const PLLOUT_MAX: u32 = 36_000_000;
+    #[cfg(feature = "stm32f103")]
+    pub const PLLOUT_MAX: u32 = 72_000_000;
+    pub const SYSCLK_MAX: u32 = PLLOUT_MAX;
+    pub const PCLK1_MAX: u32 = 36_000_000;
+    pub const ADCCLK_MAX: u32 = 14_000_000;
+}
+
+/// Frequency limits of the device, see the datasheet
+#[cfg(feature = "connectivity")]
+mod limits {
+    pub const HSE_MIN: u32 = 3_000_000;
+    pub const HSE_MAX: u32 = 25_000_000;
+    pub const PREDIV1_MAX: u8 = 16;
+    pub const PLLIN_MIN: u32 = 3_000_000;
+    pub const PLLIN_MAX: u32 = 12_000_000;
+    pub const PLLOUT_MIN: u32 = 18_000_000;
+    pub const PLLOUT_MAX: u32 = 72_000_000;
+    pub const PLL23IN_MIN: u32 = 3_000_000;
+    pub const PLL23IN_MAX: u32 = 5_000_000;
+    pub const PLL23OUT_MIN: u32 = 40_000_000;
+    pub const PLL23OUT_MAX: u32 = 74_000_000;
+    pub const SYSCLK_MAX: u32 = 72_000_000;
+    pub const PCLK1_MAX: u32 = 36_000_000;
+    pub const ADCCLK_MAX: u32 = 14_000_000;
+}
+
+/// PLL multiplication factors times two and their PLLMUL bits
+#[cfg(not(feature = "connectivity"))]
+const PLLMUL: [(u32, u8); 15] = [
+    (4, 0b0000),
+    (6, 0b0001),
+    (8, 0b0010),
+    (10, 0b0011),
+    (12, 0b0100),
+    (14, 0b0101),
+    (16, 0b0110),
+    (18, 0b0111),
+    (20, 0b1000),
+    (22, 0b1001),
+    (24, 0b1010),
+    (26, 0b1011),
+    (28, 0b1100),
+    (30, 0b1101),
+    (32, 0b1110),
+];
+
+/// PLL multiplication factors times two and their PLLMUL bits
+#[cfg(feature = "connectivity")]
+const PLLMUL: [(u32, u8); 7] = [
+    (8, 0b0010),
+    (10, 0b0011),
+    (12, 0b0100),
+    (13, 0b1101),
+    (14, 0b0101),
+    (16, 0b0110),
+    (18, 0b0111),
+];
+
+/// PLL2 and PLL3 multiplication factors and their PLL2MUL / PLL3MUL bits
+#[cfg(feature = "connectivity")]
+const PLL23MUL: [(u32, u8); 9] = [
+    (8, 0b0110),
+    (9, 0b0111),
+    (10, 0b1000),
+    (11, 0b1001),
+    (12, 0b1010),
+    (13, 0b1011),
+    (14, 0b1100),
+    (16, 0b1110),
+    (20, 0b1111),
+];
+
+/// AHB prescaler divisors and their HPRE bits
+const HPRE: [(u32, u8); 9] = [
+    (1, 0b0111),
+    (2, 0b1000),
+    (4, 0b1001),
+    (8, 0b1010),
+    (16, 0b1011),
+    (64, 0b1100),
+    (128, 0b1101),
+    (256, 0b1110),
+    (512, 0b1111),
+];
+
+/// APB prescaler divisors and their PPRE bits
+const PPRE: [(u32, u8); 5] = [(1, 0b011), (2, 0b100), (4, 0b101), (8, 0b110), (16, 0b111)];
+
+/// ADC prescaler divisors and their ADCPRE bits
+const ADCPRE: [(u32, u8); 4] = [(2, 0b00), (4, 0b01), (6, 0b10), (8, 0b11)];
+
+/// Finds the divisor that divides `clk` exactly to `target`, or the smallest divisor of at
+/// least `min` that keeps `clk` below `max` if no target is given
+fn divider(
+    clk: u32,
+    target: Option<u32>,
+    min: Option<u32>,
+    max: u32,
+    divisors: &[(u32, u8)],
+) -> Option<(u32, u8)> {
+    divisors
+        .iter()
+        .find(|&&(div, _)| match target {
+            Some(target) => target.checked_mul(div) == Some(clk) && target <= max,
+            None => div >= min.unwrap_or(1) && clk / div <= max,
+        })
+        .copied()
+}
+
+/// The USB clock is only valid if an external crystal is used, the PLL is enabled, and the
+/// PLL output frequency is a supported one.
+/// usbpre == false: divide clock by 1.5, otherwise no division
+fn usb_prescaler(hse: Option<u32>, pllmul: Option<u8>, sysclk: u32) -> (bool, bool) {
+    match (hse, pllmul, sysclk) {
+        (Some(_), Some(_), 72_000_000) => (false, true),
+        (Some(_), Some(_), 48_000_000) => (true, true),
+        _ => (true, false),
+    }
+}
+
 pub struct BKP {
     _0: (),
 }
@@ -469,7 +1169,9 @@ impl BKP {
 
 /// Frozen clock frequencies
 ///
-/// The existence of this value indicates that the clock configuration can no longer be changed
+/// The existence of this value indicates that the clocks are configured. They can only be
+/// changed at runtime through [reconfigure](#method.reconfigure), which produces new `Clocks`
+/// that replace this value.
 ///
 /// To acquire it, use the freeze function on the `rcc.cfgr` register. If desired, you can adjust
 /// the frequencies using the methods on [cfgr](struct.CFGR.html) before calling freeze.
@@ -481,7 +1183,7 @@ impl BKP {
 ///
 /// let clocks = rcc.cfgr.freeze(&mut flash.acr);
 /// ```
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Clocks {
     hclk: Hertz,
     pclk1: Hertz,
@@ -491,6 +1193,16 @@ pub struct Clocks {
     sysclk: Hertz,
     adcclk: Hertz,
     usbclk_valid: bool,
+    hse: Option<Hertz>,
+    pllclk: Option<Hertz>,
+    #[cfg(feature = "connectivity")]
+    pll2clk: Option<Hertz>,
+    #[cfg(feature = "connectivity")]
+    pll3clk: Option<Hertz>,
+    #[cfg(feature = "connectivity")]
+    i2s2clk: Hertz,
+    #[cfg(feature = "connectivity")]
+    i2s3clk: Hertz,
 }
 
 impl Clocks {
@@ -543,6 +1255,236 @@ impl Clocks {
     pub fn usbclk_valid(&self) -> bool {
         self.usbclk_valid
     }
+
+    /// Starts a new clock configuration to replace these clocks at runtime
+    ///
+    /// The returned builder keeps HSE, SYSCLK and the AHB, APB and ADC prescalers of these
+    /// clocks. Prescalers are only increased if a bus would exceed its maximum frequency, bus
+    /// frequencies that are requested explicitly replace them. The USB clock requirement,
+    /// the clock security system and on connectivity line devices PLL2 and PLL3 have to be
+    /// requested again.
+    ///
+    /// Applying it with `freeze` or `try_freeze` switches SYSCLK to HSI, stops the PLLs and
+    /// then sets up the new configuration. Afterwards the new `Clocks` have to be passed to the
+    /// `reconfigure` method of every driver that depends on the bus frequencies.
+    ///
+    /// ```rust
+    /// // drop to 8 MHz to save power
+    /// let clocks = clocks.reconfigure().sysclk(8.mhz()).freeze(&mut flash.acr);
+    /// serial.reconfigure(&clocks);
+    /// ```
+    pub fn reconfigure(&self) -> CFGR {
+        CFGR {
+            hse: self.hse.map(|hse| hse.0),
+            sysclk: Some(self.sysclk.0),
+            hpre: Some(self.sysclk.0 / self.hclk.0),
+            ppre1: Some(u32(self.ppre1)),
+            ppre2: Some(u32(self.ppre2)),
+            adcpre: Some(self.pclk2.0 / self.adcclk.0),
+            ..CFGR::new()
+        }
+    }
+
+    /// Returns the clock frequencies after the clock security system switched SYSCLK to HSI
+    ///
+    /// The AHB, APB and ADC prescalers keep their settings, HSE and all PLLs are stopped.
+    pub fn hsi_fallback(&self) -> Clocks {
+        let hclk = HSI / (self.sysclk.0 / self.hclk.0);
+        let pclk2 = hclk / u32(self.ppre2);
+        Clocks {
+            hclk: Hertz(hclk),
+            pclk1: Hertz(hclk / u32(self.ppre1)),
+            pclk2: Hertz(pclk2),
+            sysclk: Hertz(HSI),
+            adcclk: Hertz(pclk2 / (self.pclk2.0 / self.adcclk.0)),
+            usbclk_valid: false,
+            hse: None,
+            pllclk: None,
+            #[cfg(feature = "connectivity")]
+            pll2clk: None,
+            #[cfg(feature = "connectivity")]
+            pll3clk: None,
+            #[cfg(feature = "connectivity")]
+            i2s2clk: Hertz(HSI),
+            #[cfg(feature = "connectivity")]
+            i2s3clk: Hertz(HSI),
+            ..*self
+        }
+    }
+
+    /// Returns the HSE frequency, `None` if HSE is not used
+    pub fn hse(&self) -> Option<Hertz> {
+        self.hse
+    }
+
+    /// Returns the PLL output frequency, `None` if the PLL is disabled
+    pub fn pllclk(&self) -> Option<Hertz> {
+        self.pllclk
+    }
+
+    /// Returns the PLL2 frequency, `None` if PLL2 is disabled
+    #[cfg(feature = "connectivity")]
+    pub fn pll2clk(&self) -> Option<Hertz> {
+        self.pll2clk
+    }
+
+    /// Returns the PLL3 frequency, `None` if PLL3 is disabled
+    #[cfg(feature = "connectivity")]
+    pub fn pll3clk(&self) -> Option<Hertz> {
+        self.pll3clk
+    }
+
+    /// Returns the I2S2 clock frequency
+    #[cfg(feature = "connectivity")]
+    pub fn i2s2clk(&self) -> Hertz {
+        self.i2s2clk
+    }
+
+    /// Returns the I2S3 clock frequency
+    #[cfg(feature = "connectivity")]
+    pub fn i2s3clk(&self) -> Hertz {
+        self.i2s3clk
+    }
+}
+
+/// Handles the clock security system interrupt, to be called from the NMI handler
+///
+/// Clears the CSSF flag and returns `true` if HSE failed and the system has fallen back to HSI.
+/// The new frequencies are given by [Clocks::hsi_fallback](struct.Clocks.html#method.hsi_fallback).
+///
+/// ```rust
+/// #[exception]
+/// fn NonMaskableInt() {
+///     if rcc::handle_css_interrupt() {
+///         // signal the application to reconfigure its peripherals
+///     }
+/// }
+/// ```
+pub fn handle_css_interrupt() -> bool {
+    let rcc = unsafe { &*RCC::ptr() };
+    if rcc.cir.read().cssf().bit_is_set() {
+        rcc.cir.modify(|_, w| w.cssc().set_bit());
+        // SWS reads 0b00 once SYSCLK runs from HSI
+        rcc.cfgr.read().sws().bits() == 0b00
+    } else {
+        false
+    }
+}
+
+/// Clock source of the microcontroller clock output (MCO)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoSource {
+    /// System clock
+    Sysclk,
+    /// HSI oscillator
+    Hsi,
+    /// HSE oscillator
+    Hse,
+    /// PLL clock divided by 2
+    PllDiv2,
+    /// PLL2 clock
+    #[cfg(feature = "connectivity")]
+    Pll2,
+    /// PLL3 clock divided by 2
+    #[cfg(feature = "connectivity")]
+    Pll3Div2,
+    /// XT1 external oscillator, for Ethernet
+    #[cfg(feature = "connectivity")]
+    Xt1,
+    /// PLL3 clock, for Ethernet
+    #[cfg(feature = "connectivity")]
+    Pll3,
+}
+
+/// Microcontroller clock output on PA8
+///
+/// ```rust
+/// let pa8 = gpioa.pa8.into_alternate_push_pull(&mut gpioa.crh);
+/// let mco = Mco::new(pa8, McoSource::Hse, &clocks);
+/// assert_eq!(mco.frequency(), clocks.hse().unwrap());
+/// ```
+pub struct Mco {
+    pin: PA8<Alternate<PushPull>>,
+    source: McoSource,
+    frequency: Hertz,
+}
+
+impl Mco {
+    /// Outputs the clock `source` on PA8
+    ///
+    /// Panics if the source is not running or faster than the 50 MHz the pin supports.
+    pub fn new(pin: PA8<Alternate<PushPull>>, source: McoSource, clocks: &Clocks) -> Self {
+        let frequency = Self::source_frequency(source, clocks);
+
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|_, w| {
+            let mco = w.mco();
+            match source {
+                McoSource::Sysclk => mco.sysclk(),
+                McoSource::Hsi => mco.hsi(),
+                McoSource::Hse => mco.hse(),
+                McoSource::PllDiv2 => mco.pll(),
+                #[cfg(feature = "connectivity")]
+                McoSource::Pll2 => mco.pll2(),
+                #[cfg(feature = "connectivity")]
+                McoSource::Pll3Div2 => mco.pll3(),
+                #[cfg(feature = "connectivity")]
+                McoSource::Xt1 => mco.xt1(),
+                #[cfg(feature = "connectivity")]
+                McoSource::Pll3 => mco.pll3ethernet(),
+            }
+        });
+
+        Mco {
+            pin,
+            source,
+            frequency,
+        }
+    }
+
+    /// Frequency of `source` with the `clocks` configuration
+    fn source_frequency(source: McoSource, clocks: &Clocks) -> Hertz {
+        let frequency = match source {
+            McoSource::Sysclk => Some(clocks.sysclk),
+            McoSource::Hsi => Some(Hertz(HSI)),
+            McoSource::Hse => clocks.hse,
+            McoSource::PllDiv2 => clocks.pllclk.map(|pllclk| Hertz(pllclk.0 / 2)),
+            #[cfg(feature = "connectivity")]
+            McoSource::Pll2 => clocks.pll2clk,
+            #[cfg(feature = "connectivity")]
+            McoSource::Pll3Div2 => clocks.pll3clk.map(|pll3clk| Hertz(pll3clk.0 / 2)),
+            #[cfg(feature = "connectivity")]
+            McoSource::Xt1 => clocks.hse,
+            #[cfg(feature = "connectivity")]
+            McoSource::Pll3 => clocks.pll3clk,
+        };
+        let frequency = frequency.expect("MCO source clock is not running");
+        assert!(frequency.0 <= 50_000_000);
+        frequency
+    }
+
+    /// Updates the output frequency after the clocks have been reconfigured
+    ///
+    /// Panics like [new](#method.new) if the source is no longer running or faster than the pin
+    /// supports.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.frequency = Self::source_frequency(self.source, clocks);
+    }
+
+    /// Returns the frequency of the output clock
+    ///
+    /// Call [reconfigure](#method.reconfigure) after the clocks have been reconfigured to
+    /// update it.
+    pub fn frequency(&self) -> Hertz {
+        self.frequency
+    }
+
+    /// Stops the clock output and returns the pin
+    pub fn release(self) -> PA8<Alternate<PushPull>> {
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|_, w| w.mco().no_mco());
+        self.pin
+    }
 }
 
 pub trait GetBusFreq {
@@ -755,3 +1697,358 @@ bus! {
 bus! {
     USB => (APB1, usben, usbrst),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::U32Ext;
+
+    #[test]
+    fn hsi_without_pll() {
+        let config = CFGR::new().get_config().unwrap();
+        assert_eq!(config.pllmul, None);
+        let clocks = config.clocks();
+        assert_eq!(clocks.sysclk().0, HSI);
+        assert_eq!(clocks.hclk().0, HSI);
+        assert_eq!(clocks.pclk1().0, HSI);
+        assert!(clocks.adcclk().0 <= limits::ADCCLK_MAX);
+        assert!(!clocks.usbclk_valid());
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn hse_72mhz() {
+        let config = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(72.mhz())
+            .get_config()
+            .unwrap();
+        let clocks = config.clocks();
+        assert_eq!(clocks.sysclk().0, 72_000_000);
+        assert_eq!(clocks.hclk().0, 72_000_000);
+        assert_eq!(clocks.pclk1().0, 36_000_000);
+        assert_eq!(clocks.pclk2().0, 72_000_000);
+        assert_eq!(clocks.adcclk().0, 12_000_000);
+        assert_eq!(clocks.pllclk().map(|f| f.0), Some(72_000_000));
+        assert_eq!(config.ppre1, 0b100);
+        assert_eq!(config.adcpre, 0b10);
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn largest_hclk_divider() {
+        let config = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(72.mhz())
+            .hclk(140_625.hz())
+            .get_config()
+            .unwrap();
+        assert_eq!(config.hpre, 0b1111);
+        assert_eq!(config.clocks().hclk().0, 140_625);
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn inexact_dividers() {
+        let cfgr = || CFGR::new().use_hse(8.mhz()).sysclk(72.mhz());
+        // 512 * 10 MHz does not fit into u32
+        assert_eq!(cfgr().hclk(10.mhz()).get_config().err(), Some(Error::Hclk));
+        assert_eq!(
+            cfgr().pclk1(10.mhz()).get_config().err(),
+            Some(Error::Pclk1)
+        );
+        assert_eq!(
+            cfgr().pclk2(10.mhz()).get_config().err(),
+            Some(Error::Pclk2)
+        );
+        assert_eq!(
+            cfgr().adcclk(14.mhz()).get_config().err(),
+            Some(Error::Adcclk)
+        );
+        assert_eq!(
+            cfgr()
+                .adcclk(9.mhz())
+                .get_config()
+                .unwrap()
+                .clocks()
+                .adcclk()
+                .0,
+            9_000_000
+        );
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn frequencies_above_limits() {
+        let cfgr = || CFGR::new().use_hse(8.mhz()).sysclk(72.mhz());
+        assert_eq!(
+            cfgr().pclk1(72.mhz()).get_config().err(),
+            Some(Error::Pclk1)
+        );
+        assert_eq!(
+            cfgr().adcclk(18.mhz()).get_config().err(),
+            Some(Error::Adcclk)
+        );
+        assert_eq!(
+            CFGR::new()
+                .use_hse(8.mhz())
+                .sysclk(80.mhz())
+                .get_config()
+                .err(),
+            Some(Error::Sysclk)
+        );
+    }
+
+    #[test]
+    fn invalid_sources() {
+        assert_eq!(
+            CFGR::new().use_hse(1.mhz()).get_config().err(),
+            Some(Error::HseOutOfRange)
+        );
+        assert_eq!(
+            CFGR::new().use_hse(30.mhz()).get_config().err(),
+            Some(Error::HseOutOfRange)
+        );
+        assert_eq!(
+            CFGR::new()
+                .use_hse(8.mhz())
+                .sysclk(23_500.khz())
+                .get_config()
+                .err(),
+            Some(Error::Sysclk)
+        );
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn reconfigure_keeps_prescalers() {
+        let clocks = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(72.mhz())
+            .hclk(36.mhz())
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(clocks.reconfigure().get_config().unwrap().clocks(), clocks);
+        assert_eq!(clocks.reconfigure().get_round_config().clocks(), clocks);
+
+        let slow = clocks
+            .reconfigure()
+            .sysclk(8.mhz())
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(slow.hse().map(|f| f.0), Some(8_000_000));
+        assert_eq!(slow.pllclk(), None);
+        assert_eq!(slow.hclk().0, 4_000_000);
+        assert_eq!(slow.pclk1().0, 4_000_000);
+        assert_eq!(slow.adcclk().0, 1_000_000);
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn reconfigure_respects_limits() {
+        let clocks = CFGR::new().use_hse(8.mhz()).get_config().unwrap().clocks();
+        assert_eq!(clocks.ppre1, 1);
+        let fast = clocks
+            .reconfigure()
+            .sysclk(72.mhz())
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(fast.pclk1().0, 36_000_000);
+        assert_eq!(fast.pclk2().0, 72_000_000);
+        assert!(fast.adcclk().0 <= limits::ADCCLK_MAX);
+
+        // `freeze` applies the rounded configuration
+        let round = clocks
+            .reconfigure()
+            .sysclk(72.mhz())
+            .get_round_config()
+            .clocks();
+        assert_eq!(round, fast);
+    }
+
+    #[test]
+    fn usb_clock() {
+        assert_eq!(
+            CFGR::new().require_usbclk().get_config().err(),
+            Some(Error::Usbclk)
+        );
+        assert_eq!(
+            CFGR::new()
+                .use_hse(8.mhz())
+                .sysclk(36.mhz())
+                .require_usbclk()
+                .get_config()
+                .err(),
+            Some(Error::Usbclk)
+        );
+    }
+
+    #[cfg(feature = "stm32f103")]
+    #[test]
+    fn usb_clock_from_pll() {
+        let clocks = CFGR::new()
+            .use_hse(8.mhz())
+            .require_usbclk()
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(clocks.sysclk().0, 72_000_000);
+        assert!(clocks.usbclk_valid());
+
+        let config = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(48.mhz())
+            .require_usbclk()
+            .get_config()
+            .unwrap();
+        assert!(config.usbpre);
+        assert!(config.clocks().usbclk_valid());
+    }
+
+    #[cfg(feature = "connectivity")]
+    #[test]
+    fn pll2_feeds_pll_and_pll3_feeds_mco() {
+        let cfgr = || {
+            CFGR::new()
+                .use_hse(25.mhz())
+                .pll2clk(40.mhz())
+                .prediv1src_pll2()
+                .sysclk(72.mhz())
+                .pll3clk(50.mhz())
+        };
+        let config = cfgr().get_config().unwrap();
+        assert_eq!(config.pll23.prediv2, 5);
+        assert_eq!(config.pll23.pll2mul, Some(0b0110));
+        assert_eq!(config.pll23.pll3mul, Some(0b1000));
+        assert!(config.pll23.prediv1src);
+        assert_eq!(config.prediv1, 5);
+        assert_eq!(config.pllmul, Some(0b0111));
+        let clocks = config.clocks();
+        assert_eq!(clocks.sysclk().0, 72_000_000);
+        assert_eq!(clocks.pll2clk().map(|f| f.0), Some(40_000_000));
+        assert_eq!(clocks.pll3clk().map(|f| f.0), Some(50_000_000));
+        let round = cfgr().pclk1(36.mhz()).get_round_config();
+        assert_eq!(round.pll23, config.pll23);
+        assert_eq!(round.clocks().sysclk().0, 72_000_000);
+    }
+
+    #[cfg(feature = "connectivity")]
+    #[test]
+    fn fixed_prediv2() {
+        let config = CFGR::new()
+            .use_hse(8.mhz())
+            .prediv2(2)
+            .pll3clk(64.mhz())
+            .get_config()
+            .unwrap();
+        assert_eq!(config.pll23.prediv2, 2);
+        assert_eq!(config.pll23.pll2mul, None);
+        assert_eq!(config.pll23.pll3mul, Some(0b1110));
+        assert_eq!(config.clocks().pll2clk(), None);
+        assert_eq!(config.clocks().pll3clk().map(|f| f.0), Some(64_000_000));
+    }
+
+    #[cfg(feature = "connectivity")]
+    #[test]
+    fn pll23_out_of_limits() {
+        let cfgr = || CFGR::new().use_hse(25.mhz());
+        // 5 MHz * 16 is above the PLL2 output limit
+        assert_eq!(
+            cfgr().pll2clk(80.mhz()).get_config().err(),
+            Some(Error::Pll2)
+        );
+        // 30 MHz is below the PLL3 output limit
+        assert_eq!(
+            cfgr().pll3clk(30.mhz()).get_config().err(),
+            Some(Error::Pll3)
+        );
+        // PLL2 and PLL3 share the 5 MHz PLL input, 44 MHz is no multiple of it
+        assert_eq!(
+            cfgr()
+                .pll2clk(45.mhz())
+                .pll3clk(44.mhz())
+                .get_config()
+                .err(),
+            Some(Error::Pll3)
+        );
+        // 25 MHz is above the PLL2 and PLL3 input limit
+        assert_eq!(
+            cfgr().prediv2(1).pll2clk(50.mhz()).get_config().err(),
+            Some(Error::Pll2)
+        );
+        assert_eq!(
+            cfgr().prediv2(17).pll2clk(40.mhz()).get_config().err(),
+            Some(Error::Pll2)
+        );
+        assert_eq!(
+            CFGR::new()
+                .use_hse(26.mhz())
+                .pll2clk(52.mhz())
+                .get_config()
+                .err(),
+            Some(Error::HseOutOfRange)
+        );
+    }
+
+    #[cfg(feature = "connectivity")]
+    #[test]
+    fn pll23_missing_sources() {
+        assert_eq!(
+            CFGR::new().pll2clk(40.mhz()).get_config().err(),
+            Some(Error::Pll2)
+        );
+        assert_eq!(
+            CFGR::new().pll3clk(40.mhz()).get_config().err(),
+            Some(Error::Pll3)
+        );
+        let cfgr = || CFGR::new().use_hse(25.mhz());
+        assert_eq!(
+            cfgr().prediv1src_pll2().get_config().err(),
+            Some(Error::Pll2)
+        );
+        assert_eq!(cfgr().i2s2src_pll3().get_config().err(), Some(Error::Pll3));
+        assert_eq!(cfgr().i2s3src_pll3().get_config().err(), Some(Error::Pll3));
+    }
+
+    #[cfg(any(feature = "stm32f103", feature = "connectivity"))]
+    #[test]
+    fn mco_source_frequencies() {
+        let clocks = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(72.mhz())
+            .pclk1(36.mhz())
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(Mco::source_frequency(McoSource::Hse, &clocks).0, 8_000_000);
+        assert_eq!(
+            Mco::source_frequency(McoSource::PllDiv2, &clocks).0,
+            36_000_000
+        );
+
+        let slow = CFGR::new()
+            .use_hse(8.mhz())
+            .sysclk(48.mhz())
+            .get_config()
+            .unwrap()
+            .clocks();
+        assert_eq!(
+            Mco::source_frequency(McoSource::Sysclk, &slow).0,
+            48_000_000
+        );
+        assert_eq!(
+            Mco::source_frequency(McoSource::PllDiv2, &slow).0,
+            24_000_000
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "MCO source clock is not running")]
+    fn mco_source_stopped() {
+        let clocks = CFGR::new().get_config().unwrap().clocks();
+        Mco::source_frequency(McoSource::Hse, &clocks);
+    }
+}
@@ -1,16 +1,23 @@
 /*!
   Real time clock
 */
-use crate::pac::{RCC, RTC};
+use crate::pac::{rcc::bdcr::RTCSEL_A, RCC, RTC};
 
 use crate::backup_domain::BackupDomain;
-use crate::time::Hertz;
+use crate::time::{Hertz, MonoTimer};
 
+use core::cmp;
 use core::convert::Infallible;
 
 // The LSE runs at at 32 768 hertz unless an external clock is provided
 const LSE_HERTZ: u32 = 32_768;
 
+// Index of the backup data register holding the low half of the calendar epoch offset, the high
+// half is stored in the following register
+const EPOCH_OFFSET_REGISTER: usize = 0;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
 /**
   Real time clock
 
@@ -30,6 +37,44 @@ const LSE_HERTZ: u32 = 32_768;
 
 pub struct Rtc {
     regs: RTC,
+    clock: Hertz,
+    /// Value written to the write-only PRL register
+    prescaler: u32,
+    was_running: bool,
+}
+
+/// Clock source of the RTC
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcClkSource {
+    /// 32.768 kHz crystal on the LSE oscillator
+    Lse,
+    /// 32.768 kHz external clock on OSC32_IN, the LSE oscillator is bypassed
+    LseBypass,
+    /// Internal RC oscillator, the argument is its frequency. The LSI is nominally 40 kHz but
+    /// varies between 30 and 60 kHz from device to device, so for accurate timekeeping it
+    /// should be measured against HSE.
+    Lsi(Hertz),
+    /// HSE divided by 128, the argument is the HSE frequency. The RTC stops when the 1.8 V domain
+    /// is powered off, e.g. in Standby mode.
+    Hse(Hertz),
+}
+
+impl RtcClkSource {
+    fn rtcsel(self) -> RTCSEL_A {
+        match self {
+            RtcClkSource::Lse | RtcClkSource::LseBypass => RTCSEL_A::LSE,
+            RtcClkSource::Lsi(_) => RTCSEL_A::LSI,
+            RtcClkSource::Hse(_) => RTCSEL_A::HSE,
+        }
+    }
+
+    fn frequency(self) -> Hertz {
+        match self {
+            RtcClkSource::Lse | RtcClkSource::LseBypass => Hertz(LSE_HERTZ),
+            RtcClkSource::Lsi(lsi) => lsi,
+            RtcClkSource::Hse(hse) => Hertz(hse.0 / 128),
+        }
+    }
 }
 
 impl Rtc {
@@ -44,13 +89,33 @@ impl Rtc {
       reset the counter.
     */
     pub fn rtc(regs: RTC, bkp: &mut BackupDomain) -> Self {
-        let mut result = Rtc { regs };
-
-        Rtc::enable_rtc(bkp);
+        Rtc::with_source(regs, bkp, RtcClkSource::Lse)
+    }
 
-        // Set the prescaler to make it count up once every second.
-        let prl = LSE_HERTZ - 1;
+    /**
+      Initialises the RTC with the given clock source. The frequency is set to 1 Hz.
+
+      If the RTC is already running from `source`, e.g. after a system reset, only the oscillator
+      is enabled and the counter keeps its value, see [was_running](#method.was_running). A
+      frequency selected with [select_frequency](#method.select_frequency) before the reset is
+      replaced by 1 Hz, the prescaler register cannot be read back. If another source is
+      selected, the backup domain has to be reset to change it, which clears the counter and the
+      backup data registers.
+    */
+    pub fn with_source(regs: RTC, bkp: &mut BackupDomain, source: RtcClkSource) -> Self {
+        let was_running = Rtc::enable_rtc(bkp, source);
+        let clock = source.frequency();
+        let prl = clock.0.wrapping_sub(1);
         assert!(prl < 1 << 20);
+        let mut result = Rtc {
+            regs,
+            clock,
+            prescaler: prl,
+            was_running,
+        };
+
+        // Set the prescaler to make it count up once every second. The divider is reloaded from
+        // PRL when it reaches zero, so this does not disturb a running counter.
         result.perform_write(|s| {
             s.regs.prlh.write(|w| unsafe { w.bits(prl >> 16) });
             s.regs.prll.write(|w| unsafe { w.bits(prl as u16 as u32) });
@@ -59,35 +124,76 @@ impl Rtc {
         result
     }
 
-    /// Enables the RTC device with the lse as the clock
-    fn enable_rtc(_bkp: &mut BackupDomain) {
-        // NOTE: Safe RCC access because we are only accessing bdcr
+    /// Enables the RTC device with the given clock source, returns `true` if it was already
+    /// running from that source
+    fn enable_rtc(_bkp: &mut BackupDomain, source: RtcClkSource) -> bool {
+        // NOTE: Safe RCC access because we are only accessing bdcr and the LSI bits in csr,
         // and we have a &mut on BackupDomain
         let rcc = unsafe { &*RCC::ptr() };
+
+        // The LSI is not part of the backup domain and stopped by every reset
+        if let RtcClkSource::Lsi(_) = source {
+            rcc.csr.modify(|_, w| w.lsion().set_bit());
+            while rcc.csr.read().lsirdy().bit_is_clear() {}
+        }
+
+        let bdcr = rcc.bdcr.read();
+        if bdcr.rtcen().bit_is_set() && bdcr.rtcsel().variant() == source.rtcsel() {
+            return true;
+        }
+
+        // RTCSEL can only be changed by a backup domain reset
+        if !bdcr.rtcsel().is_no_clock() {
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+        }
+
+        match source {
+            RtcClkSource::Lse | RtcClkSource::LseBypass => {
+                // LSEBYP can only be written while the LSE is off
+                rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+                while rcc.bdcr.read().lserdy().bit_is_set() {}
+                rcc.bdcr
+                    .modify(|_, w| w.lsebyp().bit(source == RtcClkSource::LseBypass));
+                // start the LSE oscillator
+                rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+                while rcc.bdcr.read().lserdy().bit_is_clear() {}
+            }
+            RtcClkSource::Hse(_) => {
+                assert!(rcc.cr.read().hserdy().bit_is_set(), "HSE is not running");
+            }
+            RtcClkSource::Lsi(_) => {}
+        }
+
         rcc.bdcr.modify(|_, w| {
             w
-                // start the LSE oscillator
-                .lseon()
-                .set_bit()
                 // Enable the RTC
                 .rtcen()
                 .set_bit()
-                // Set the source of the RTC to LSE
+                // Set the source of the RTC
                 .rtcsel()
-                .lse()
-        })
+                .variant(source.rtcsel())
+        });
+        false
+    }
+
+    /// Returns `true` if the RTC was already running from the same clock source when it was
+    /// initialised, so the counter continues from before the last reset
+    pub fn was_running(&self) -> bool {
+        self.was_running
     }
 
     /// Selects the frequency of the RTC Timer
-    /// NOTE: Maximum frequency of 16384 Hz using the internal LSE
+    /// NOTE: Maximum frequency of half the RTC clock, 16384 Hz using the LSE
     pub fn select_frequency(&mut self, timeout: impl Into<Hertz>) {
         let frequency = timeout.into().0;
 
         // The manual says that the zero value for the prescaler is not recommended, thus the
         // minimum division factor is 2 (prescaler + 1)
-        assert!(frequency <= LSE_HERTZ / 2);
+        assert!(frequency <= self.clock.0 / 2);
 
-        let prescaler = LSE_HERTZ / frequency - 1;
+        let prescaler = self.clock.0 / frequency - 1;
+        self.prescaler = prescaler;
         self.perform_write(|s| {
             s.regs.prlh.write(|w| unsafe { w.bits(prescaler >> 16) });
             s.regs
@@ -198,6 +304,99 @@ impl Rtc {
         }
     }
 
+    /**
+      Sets the calendar date and time
+
+      The counter is reset to zero and the Unix timestamp of `datetime` is stored as the epoch
+      offset in the backup data registers DR1 and DR2, so the calendar survives resets and does
+      not overflow in 2106. The calendar requires the default counter frequency of 1 Hz,
+      `Error::Frequency` is returned after [select_frequency](#method.select_frequency) chose
+      another one.
+    */
+    pub fn set_datetime(
+        &mut self,
+        bkp: &mut BackupDomain,
+        datetime: &DateTime,
+    ) -> Result<(), Error> {
+        self.check_one_hz()?;
+        let timestamp = datetime.timestamp().ok_or(Error::InvalidDateTime)?;
+        let offset = cast::u32(timestamp).map_err(|_| Error::OutOfRange)?;
+
+        bkp.write_data_register_low(EPOCH_OFFSET_REGISTER, offset as u16);
+        bkp.write_data_register_low(EPOCH_OFFSET_REGISTER + 1, (offset >> 16) as u16);
+        self.set_time(0);
+        Ok(())
+    }
+
+    /// Returns the current calendar date and time, `Error::Frequency` if the counter does not
+    /// run at 1 Hz
+    pub fn now(&self, bkp: &BackupDomain) -> Result<DateTime, Error> {
+        self.check_one_hz()?;
+        let timestamp = u64::from(epoch_offset(bkp)) + u64::from(self.current_time());
+        // The timestamp is at most 2^33 seconds, far from the end of year 65535
+        Ok(DateTime::from_timestamp(timestamp).unwrap())
+    }
+
+    /**
+      Sets the alarm to the given calendar date and time
+
+      This also clears the alarm flag if it is set. Returns `Error::OutOfRange` if `datetime` is
+      not after the current time, see [now](#method.now), or more than 2^32 seconds after the
+      last call to [set_datetime](#method.set_datetime), and `Error::Frequency` if the counter
+      does not run at 1 Hz.
+    */
+    pub fn set_alarm_at(&mut self, bkp: &BackupDomain, datetime: &DateTime) -> Result<(), Error> {
+        self.check_one_hz()?;
+        let timestamp = datetime.timestamp().ok_or(Error::InvalidDateTime)?;
+        let counter = alarm_counter(timestamp, epoch_offset(bkp), self.current_time())
+            .ok_or(Error::OutOfRange)?;
+
+        self.set_alarm(counter);
+        Ok(())
+    }
+
+    /**
+      Measures how many ppm the RTC runs faster than `timer`
+
+      Waits for the next counter increment and then counts the timer cycles during the following
+      `seconds` increments, so the counter has to run at the default frequency of 1 Hz, otherwise
+      `Error::Frequency` is returned. A negative result means that the RTC runs slow. When SYSCLK
+      is generated from HSE, this measures the drift of the LSE crystal against the HSE crystal.
+
+      The DWT cycle counter overflows after 2^32 cycles, which limits `seconds` to 59 at 72 MHz.
+    */
+    pub fn measure_drift_ppm(&self, timer: MonoTimer, seconds: u32) -> Result<i32, Error> {
+        self.check_one_hz()?;
+        let expected = u64::from(timer.frequency().0) * u64::from(seconds);
+        assert!(seconds > 0 && expected <= u64::from(u32::MAX));
+
+        let start = self.current_time();
+        while self.current_time() == start {}
+        let instant = timer.now();
+        while self.current_time().wrapping_sub(start) <= seconds {}
+        let elapsed = i64::from(instant.elapsed());
+
+        Ok(((expected as i64 - elapsed) * 1_000_000 / elapsed) as i32)
+    }
+
+    /**
+      Slows the RTC down by `ppm` using the calibration value in the backup domain
+
+      Only a slow down is possible, the correction is limited to about 121 ppm.
+    */
+    pub fn set_calibration_ppm(&mut self, bkp: &mut BackupDomain, ppm: u32) {
+        bkp.set_rtc_calibration(calibration_from_ppm(ppm));
+    }
+
+    /// The calendar and the drift measurement count the counter increments as seconds
+    fn check_one_hz(&self) -> Result<(), Error> {
+        if self.prescaler == self.clock.0 - 1 {
+            Ok(())
+        } else {
+            Err(Error::Frequency)
+        }
+    }
+
     /**
       The RTC registers can not be written to at any time as documented on page
       485 of the manual. Performing writes using this function ensures that
@@ -218,3 +417,281 @@ impl Rtc {
         while !self.regs.crl.read().rtoff().bit() {}
     }
 }
+
+/// Converts a correction in ppm to the number of RTC clock pulses skipped every 2^20 pulses
+fn calibration_from_ppm(ppm: u32) -> u8 {
+    let cal = (u64::from(ppm) * (1 << 20) + 500_000) / 1_000_000;
+    cmp::min(cal, 127) as u8
+}
+
+fn epoch_offset(bkp: &BackupDomain) -> u32 {
+    u32::from(bkp.read_data_register_low(EPOCH_OFFSET_REGISTER))
+        | u32::from(bkp.read_data_register_low(EPOCH_OFFSET_REGISTER + 1)) << 16
+}
+
+/// Counter value of the alarm at `timestamp`, `None` if it is not after the `counter` value now
+fn alarm_counter(timestamp: u64, epoch_offset: u32, counter: u32) -> Option<u32> {
+    timestamp
+        .checked_sub(u64::from(epoch_offset))
+        .and_then(|alarm| cast::u32(alarm).ok())
+        .filter(|&alarm| alarm > counter)
+}
+
+/// Calendar errors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The date or time is not valid, or before 1970
+    InvalidDateTime,
+    /// The date and time can not be represented by the RTC counter
+    OutOfRange,
+    /// The counter does not run at 1 Hz, see `Rtc::select_frequency`
+    Frequency,
+}
+
+/// Day of the week
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Weekday {
+    Monday = 1,
+    Tuesday = 2,
+    Wednesday = 3,
+    Thursday = 4,
+    Friday = 5,
+    Saturday = 6,
+    Sunday = 7,
+}
+
+/**
+  Calendar date and time in UTC
+
+  Conversion to and from Unix timestamps follows the proleptic Gregorian calendar without leap
+  seconds. `month` and `day` start at 1.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    /// Ignored when converting to a timestamp
+    pub weekday: Weekday,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Creates a date and time from its components and computes the weekday
+    ///
+    /// Returns `None` if the date or time is not valid or before 1970.
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Option<Self> {
+        let datetime = DateTime {
+            year,
+            month,
+            day,
+            weekday: Weekday::Thursday,
+            hour,
+            minute,
+            second,
+        };
+        datetime.timestamp().and_then(DateTime::from_timestamp)
+    }
+
+    /// Converts seconds since 1970-01-01 00:00:00 to a date and time
+    ///
+    /// Returns `None` if the year does not fit into `u16`.
+    pub fn from_timestamp(timestamp: u64) -> Option<Self> {
+        let days = timestamp / SECONDS_PER_DAY;
+        let seconds = timestamp % SECONDS_PER_DAY;
+
+        // Shift the epoch to 0000-03-01, so that the leap day is the last day of a year, and
+        // split into 400 year eras of 146097 days
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = era * 400 + yoe + if month <= 2 { 1 } else { 0 };
+
+        let weekday = match (days + 3) % 7 {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        };
+
+        Some(DateTime {
+            year: cast::u16(year).ok()?,
+            month: month as u8,
+            day: day as u8,
+            weekday,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        })
+    }
+
+    /// Converts the date and time to seconds since 1970-01-01 00:00:00
+    ///
+    /// Returns `None` if the date or time is not valid or before 1970.
+    pub fn timestamp(&self) -> Option<u64> {
+        if self.year < 1970
+            || !(1..=12).contains(&self.month)
+            || self.day < 1
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+
+        let (month, day) = (u64::from(self.month), u64::from(self.day));
+        let year = u64::from(self.year) - if month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let yoe = year - era * 400;
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146_097 + doe - 719_468;
+
+        Some(
+            days * SECONDS_PER_DAY
+                + u64::from(self.hour) * 3600
+                + u64::from(self.minute) * 60
+                + u64::from(self.second),
+        )
+    }
+}
+
+/// Returns `true` for leap years of the Gregorian calendar
+pub fn is_leap_year(year: u16) -> bool {
+    let divisible_by = |divisor| year / divisor * divisor == year;
+    (divisible_by(4) && !divisible_by(100)) || divisible_by(400)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime::new(year, month, day, hour, minute, second).unwrap()
+    }
+
+    #[test]
+    fn known_timestamps() {
+        let epoch = datetime(1970, 1, 1, 0, 0, 0);
+        assert_eq!(epoch.timestamp(), Some(0));
+        assert_eq!(epoch.weekday, Weekday::Thursday);
+
+        let y2k38 = datetime(2038, 1, 19, 3, 14, 7);
+        assert_eq!(y2k38.timestamp(), Some(0x7fff_ffff));
+        assert_eq!(y2k38.weekday, Weekday::Tuesday);
+
+        let end_of_u32 = datetime(2106, 2, 7, 6, 28, 15);
+        assert_eq!(end_of_u32.timestamp(), Some(0xffff_ffff));
+
+        assert_eq!(
+            DateTime::from_timestamp(951_782_400),
+            Some(datetime(2000, 2, 29, 0, 0, 0))
+        );
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(is_leap_year(1972));
+        assert!(is_leap_year(2000));
+        assert!(is_leap_year(2024));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2023));
+        assert!(!is_leap_year(2100));
+        assert!(is_leap_year(2400));
+
+        assert!(DateTime::new(2024, 2, 29, 0, 0, 0).is_some());
+        assert!(DateTime::new(2023, 2, 29, 0, 0, 0).is_none());
+        assert!(DateTime::new(2000, 2, 29, 0, 0, 0).is_some());
+        assert!(DateTime::new(2100, 2, 29, 0, 0, 0).is_none());
+
+        // 2100 is not a leap year, March 1st follows February 28th
+        let feb28 = datetime(2100, 2, 28, 23, 59, 59).timestamp().unwrap();
+        assert_eq!(
+            DateTime::from_timestamp(feb28 + 1),
+            Some(datetime(2100, 3, 1, 0, 0, 0))
+        );
+        assert_eq!(datetime(2100, 3, 1, 0, 0, 0).weekday, Weekday::Monday);
+    }
+
+    #[test]
+    fn invalid_dates() {
+        assert!(DateTime::new(1969, 12, 31, 23, 59, 59).is_none());
+        assert!(DateTime::new(2020, 0, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2020, 13, 1, 0, 0, 0).is_none());
+        assert!(DateTime::new(2020, 4, 31, 0, 0, 0).is_none());
+        assert!(DateTime::new(2020, 1, 0, 0, 0, 0).is_none());
+        assert!(DateTime::new(2020, 1, 1, 24, 0, 0).is_none());
+        assert!(DateTime::new(2020, 1, 1, 0, 60, 0).is_none());
+        assert!(DateTime::new(2020, 1, 1, 0, 0, 60).is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        // Every day from 1970 until after 2100, at a varying time of day
+        let mut day = 0;
+        while day < 50_000 {
+            let timestamp = day * SECONDS_PER_DAY + (day * 7919) % SECONDS_PER_DAY;
+            let datetime = DateTime::from_timestamp(timestamp).unwrap();
+            assert_eq!(datetime.timestamp(), Some(timestamp));
+            assert_eq!(
+                DateTime::new(
+                    datetime.year,
+                    datetime.month,
+                    datetime.day,
+                    datetime.hour,
+                    datetime.minute,
+                    datetime.second
+                ),
+                Some(datetime)
+            );
+            day += 1;
+        }
+    }
+
+    #[test]
+    fn epoch_offset_beyond_2106() {
+        // `now` adds the 32 bit counter to the 32 bit epoch offset
+        let timestamp = u64::from(u32::MAX) + u64::from(u32::MAX);
+        let later = DateTime::from_timestamp(timestamp).unwrap();
+        assert_eq!(later, datetime(2242, 3, 16, 12, 56, 30));
+        assert_eq!(later.timestamp(), Some(timestamp));
+    }
+
+    #[test]
+    fn alarm_in_the_future() {
+        let offset = datetime(2020, 1, 1, 0, 0, 0).timestamp().unwrap();
+        let alarm = |datetime: DateTime, counter| {
+            alarm_counter(datetime.timestamp().unwrap(), offset as u32, counter)
+        };
+        assert_eq!(alarm(datetime(2020, 1, 1, 0, 1, 0), 0), Some(60));
+        assert_eq!(alarm(datetime(2020, 1, 1, 0, 1, 0), 59), Some(60));
+        // now or in the past
+        assert_eq!(alarm(datetime(2020, 1, 1, 0, 1, 0), 60), None);
+        assert_eq!(alarm(datetime(2020, 1, 1, 0, 1, 0), 3600), None);
+        assert_eq!(alarm(datetime(2020, 1, 1, 0, 0, 0), 0), None);
+        assert_eq!(alarm(datetime(2019, 12, 31, 0, 0, 0), 0), None);
+        // beyond the 32 bit counter
+        assert_eq!(alarm(datetime(2157, 1, 1, 0, 0, 0), 0), None);
+    }
+}
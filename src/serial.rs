@@ -44,17 +44,20 @@ use core::sync::atomic::{self, Ordering};
 
 use crate::pac::{USART1, USART2, USART3};
 use core::convert::Infallible;
+use embedded_hal::digital::v2::OutputPin;
 use embedded_hal::serial::Write;
 
 use crate::afio::MAPR;
-use crate::dma::{dma1, CircBuffer, RxDma, Static, Transfer, TxDma, R, W};
-use crate::gpio::gpioa::{PA10, PA2, PA3, PA9};
-use crate::gpio::gpiob::{PB10, PB11, PB6, PB7};
-use crate::gpio::gpioc::{PC10, PC11};
-use crate::gpio::gpiod::{PD5, PD6, PD8, PD9};
-use crate::gpio::{Alternate, Floating, Input, PushPull};
-use crate::rcc::{sealed::RccBus, Clocks, Enable, GetBusFreq, Reset, APB1, APB2};
-use crate::time::{Bps, U32Ext};
+use crate::dma::{dma1, CircBuffer, RingBuffer, RxDma, Static, Transfer, TxDma, R, W};
+use crate::gpio::gpioa::{PA0, PA1, PA10, PA11, PA12, PA2, PA3, PA4, PA8, PA9};
+use crate::gpio::gpiob::{PB10, PB11, PB12, PB13, PB14, PB6, PB7};
+use crate::gpio::gpioc::{PC10, PC11, PC12};
+use crate::gpio::gpiod::{PD10, PD11, PD12, PD3, PD4, PD5, PD6, PD7, PD8, PD9};
+use crate::gpio::{Alternate, Floating, Input, OpenDrain, PushPull};
+use crate::rcc::{Clocks, GetBusFreq, APB1, APB2};
+use crate::time::{Bps, Hertz, U32Ext};
+
+pub use crate::hal::spi::{Mode, Phase, Polarity};
 
 /// Interrupt event
 pub enum Event {
@@ -62,6 +65,12 @@ pub enum Event {
     Rxne,
     /// New data can be sent
     Txe,
+    /// Idle line state detected
+    Idle,
+    /// LIN break detected
+    LinBreak,
+    /// The CTS input changed
+    Cts,
 }
 
 /// Serial error
@@ -83,6 +92,8 @@ pub enum Error {
 // Section 9.3.8
 pub trait Pins<USART> {
     const REMAP: u8;
+    /// The pins include CTS and RTS, so hardware flow control can be enabled
+    const FLOWCONTROL: bool = false;
 }
 
 impl Pins<USART1> for (PA9<Alternate<PushPull>>, PA10<Input<Floating>>) {
@@ -98,7 +109,7 @@ impl Pins<USART2> for (PA2<Alternate<PushPull>>, PA3<Input<Floating>>) {
 }
 
 impl Pins<USART2> for (PD5<Alternate<PushPull>>, PD6<Input<Floating>>) {
-    const REMAP: u8 = 0;
+    const REMAP: u8 = 1;
 }
 
 impl Pins<USART3> for (PB10<Alternate<PushPull>>, PB11<Input<Floating>>) {
@@ -113,12 +124,266 @@ impl Pins<USART3> for (PD8<Alternate<PushPull>>, PD9<Input<Floating>>) {
     const REMAP: u8 = 0b11;
 }
 
+// TX, RX, CTS and RTS pins for hardware flow control, see `Config::flowcontrol`
+
+impl Pins<USART1>
+    for (
+        PA9<Alternate<PushPull>>,
+        PA10<Input<Floating>>,
+        PA11<Input<Floating>>,
+        PA12<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART1>
+    for (
+        PB6<Alternate<PushPull>>,
+        PB7<Input<Floating>>,
+        PA11<Input<Floating>>,
+        PA12<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART2>
+    for (
+        PA2<Alternate<PushPull>>,
+        PA3<Input<Floating>>,
+        PA0<Input<Floating>>,
+        PA1<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART2>
+    for (
+        PD5<Alternate<PushPull>>,
+        PD6<Input<Floating>>,
+        PD3<Input<Floating>>,
+        PD4<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART3>
+    for (
+        PB10<Alternate<PushPull>>,
+        PB11<Input<Floating>>,
+        PB13<Input<Floating>>,
+        PB14<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART3>
+    for (
+        PC10<Alternate<PushPull>>,
+        PC11<Input<Floating>>,
+        PB13<Input<Floating>>,
+        PB14<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+    const FLOWCONTROL: bool = true;
+}
+
+impl Pins<USART3>
+    for (
+        PD8<Alternate<PushPull>>,
+        PD9<Input<Floating>>,
+        PD11<Input<Floating>>,
+        PD12<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0b11;
+    const FLOWCONTROL: bool = true;
+}
+
+/// TX pin used as the only pin in single-wire half-duplex mode
+///
+/// The pin has to be configured as open-drain alternate function output and the line needs a
+/// pull-up, either the external one of the bus or the internal one of the other node.
+pub trait HalfDuplexPin<USART> {
+    const REMAP: u8;
+}
+
+impl HalfDuplexPin<USART1> for PA9<Alternate<OpenDrain>> {
+    const REMAP: u8 = 0;
+}
+
+impl HalfDuplexPin<USART1> for PB6<Alternate<OpenDrain>> {
+    const REMAP: u8 = 1;
+}
+
+impl HalfDuplexPin<USART2> for PA2<Alternate<OpenDrain>> {
+    const REMAP: u8 = 0;
+}
+
+impl HalfDuplexPin<USART2> for PD5<Alternate<OpenDrain>> {
+    const REMAP: u8 = 1;
+}
+
+impl HalfDuplexPin<USART3> for PB10<Alternate<OpenDrain>> {
+    const REMAP: u8 = 0;
+}
+
+impl HalfDuplexPin<USART3> for PC10<Alternate<OpenDrain>> {
+    const REMAP: u8 = 1;
+}
+
+impl HalfDuplexPin<USART3> for PD8<Alternate<OpenDrain>> {
+    const REMAP: u8 = 0b11;
+}
+
+/// TX, RX and CK pins for synchronous mode
+pub trait SyncPins<USART> {
+    const REMAP: u8;
+}
+
+impl SyncPins<USART1>
+    for (
+        PA9<Alternate<PushPull>>,
+        PA10<Input<Floating>>,
+        PA8<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+}
+
+impl SyncPins<USART1>
+    for (
+        PB6<Alternate<PushPull>>,
+        PB7<Input<Floating>>,
+        PA8<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+}
+
+impl SyncPins<USART2>
+    for (
+        PA2<Alternate<PushPull>>,
+        PA3<Input<Floating>>,
+        PA4<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+}
+
+impl SyncPins<USART2>
+    for (
+        PD5<Alternate<PushPull>>,
+        PD6<Input<Floating>>,
+        PD7<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+}
+
+impl SyncPins<USART3>
+    for (
+        PB10<Alternate<PushPull>>,
+        PB11<Input<Floating>>,
+        PB12<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0;
+}
+
+impl SyncPins<USART3>
+    for (
+        PC10<Alternate<PushPull>>,
+        PC11<Input<Floating>>,
+        PC12<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 1;
+}
+
+impl SyncPins<USART3>
+    for (
+        PD8<Alternate<PushPull>>,
+        PD9<Input<Floating>>,
+        PD10<Alternate<PushPull>>,
+    )
+{
+    const REMAP: u8 = 0b11;
+}
+
+/// I/O (TX) and clock (CK) pins for smartcard mode
+///
+/// The I/O line is bidirectional and needs the TX pin as open-drain alternate function output
+/// with a pull-up.
+pub trait SmartcardPins<USART> {
+    const REMAP: u8;
+}
+
+impl SmartcardPins<USART1> for (PA9<Alternate<OpenDrain>>, PA8<Alternate<PushPull>>) {
+    const REMAP: u8 = 0;
+}
+
+impl SmartcardPins<USART1> for (PB6<Alternate<OpenDrain>>, PA8<Alternate<PushPull>>) {
+    const REMAP: u8 = 1;
+}
+
+impl SmartcardPins<USART2> for (PA2<Alternate<OpenDrain>>, PA4<Alternate<PushPull>>) {
+    const REMAP: u8 = 0;
+}
+
+impl SmartcardPins<USART2> for (PD5<Alternate<OpenDrain>>, PD7<Alternate<PushPull>>) {
+    const REMAP: u8 = 1;
+}
+
+impl SmartcardPins<USART3> for (PB10<Alternate<OpenDrain>>, PB12<Alternate<PushPull>>) {
+    const REMAP: u8 = 0;
+}
+
+impl SmartcardPins<USART3> for (PC10<Alternate<OpenDrain>>, PC12<Alternate<PushPull>>) {
+    const REMAP: u8 = 1;
+}
+
+impl SmartcardPins<USART3> for (PD8<Alternate<OpenDrain>>, PD10<Alternate<PushPull>>) {
+    const REMAP: u8 = 0b11;
+}
+
 pub enum Parity {
     ParityNone,
     ParityEven,
     ParityOdd,
 }
 
+/// Wakeup method of the receiver in mute mode
+pub enum WakeUp {
+    /// Wake up when the line goes idle
+    IdleLine,
+    /// Wake up on an address frame, the MSB of the frame marks it as address. Only the four
+    /// lowest bits are compared against the node `address`.
+    AddressMark { address: u8 },
+}
+
+/// Hardware flow control
+pub enum FlowControl {
+    /// No flow control
+    None,
+    /// Only receive while RTS is asserted
+    Rts,
+    /// Only transmit while CTS is asserted
+    Cts,
+    /// Both RTS and CTS flow control
+    RtsCts,
+}
+
 pub enum StopBits {
     #[doc = "1 stop bit"]
     STOP1,
@@ -134,6 +399,8 @@ pub struct Config {
     pub baudrate: Bps,
     pub parity: Parity,
     pub stopbits: StopBits,
+    pub flowcontrol: FlowControl,
+    pub wakeup: WakeUp,
 }
 
 impl Config {
@@ -161,6 +428,21 @@ impl Config {
         self.stopbits = stopbits;
         self
     }
+
+    /// Enables RTS and/or CTS flow control
+    ///
+    /// Only used if the CTS and RTS pins are passed to the constructor together with TX and RX,
+    /// flow control stays disabled with any other pins.
+    pub fn flowcontrol(mut self, flowcontrol: FlowControl) -> Self {
+        self.flowcontrol = flowcontrol;
+        self
+    }
+
+    /// Selects how the receiver leaves mute mode
+    pub fn wakeup(mut self, wakeup: WakeUp) -> Self {
+        self.wakeup = wakeup;
+        self
+    }
 }
 
 impl Default for Config {
@@ -170,29 +452,126 @@ impl Default for Config {
             baudrate,
             parity: Parity::ParityNone,
             stopbits: StopBits::STOP1,
+            flowcontrol: FlowControl::None,
+            wakeup: WakeUp::IdleLine,
+        }
+    }
+}
+
+/// Clock output settings for synchronous mode
+pub struct SyncConfig {
+    pub mode: Mode,
+    pub last_bit_clock: bool,
+}
+
+impl SyncConfig {
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Outputs the clock pulse of the last data bit on CK (LBCL)
+    pub fn last_bit_clock(mut self, last_bit_clock: bool) -> Self {
+        self.last_bit_clock = last_bit_clock;
+        self
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> SyncConfig {
+        SyncConfig {
+            mode: Mode {
+                polarity: Polarity::IdleLow,
+                phase: Phase::CaptureOnFirstTransition,
+            },
+            last_bit_clock: false,
+        }
+    }
+}
+
+/// LIN break detection length
+pub enum LinBreakLength {
+    #[doc = "10 bit break detection"]
+    Bits10,
+    #[doc = "11 bit break detection"]
+    Bits11,
+}
+
+/// IrDA SIR power mode
+pub enum IrdaMode {
+    /// Pulses are 3/16 of the bit period
+    Normal,
+    /// Pulses are 3 periods of a 1.8432 MHz clock derived from the bus clock
+    LowPower,
+}
+
+/// Smartcard mode settings
+pub struct SmartcardConfig {
+    pub clock: Hertz,
+    pub guard_time: u8,
+    pub nack: bool,
+}
+
+impl SmartcardConfig {
+    /// Frequency of the card clock on CK, rounded down to the next achievable one
+    pub fn clock(mut self, clock: impl Into<Hertz>) -> Self {
+        self.clock = clock.into();
+        self
+    }
+
+    /// Guard time in baud clocks inserted after each transmitted character
+    pub fn guard_time(mut self, guard_time: u8) -> Self {
+        self.guard_time = guard_time;
+        self
+    }
+
+    /// Sends a NACK on parity errors
+    pub fn nack(mut self, nack: bool) -> Self {
+        self.nack = nack;
+        self
+    }
+}
+
+impl Default for SmartcardConfig {
+    fn default() -> SmartcardConfig {
+        SmartcardConfig {
+            clock: 4.mhz().into(),
+            guard_time: 0,
+            nack: true,
         }
     }
 }
 
 /// Serial abstraction
-pub struct Serial<USART, PINS> {
+///
+/// `WORD` is `u8` for frames with 8 data bits or `u16` for frames with 9 data bits, see
+/// [`into_9bit_data`](#method.into_9bit_data).
+pub struct Serial<USART, PINS, WORD = u8> {
     usart: USART,
     pins: PINS,
+    baudrate: Bps,
+    _word: PhantomData<WORD>,
 }
 
 /// Serial receiver
-pub struct Rx<USART> {
+pub struct Rx<USART, WORD = u8> {
     _usart: PhantomData<USART>,
+    baudrate: Bps,
+    _word: PhantomData<WORD>,
 }
 
 /// Serial transmitter
-pub struct Tx<USART> {
+pub struct Tx<USART, WORD = u8> {
     _usart: PhantomData<USART>,
+    baudrate: Bps,
+    _word: PhantomData<WORD>,
 }
 
 /// Internal trait for the serial read / write logic.
+///
+/// `WORD` is `u8` for 8 bit frames or `u16` for 9 bit frames.
 trait UsartReadWrite: Deref<Target = crate::pac::usart1::RegisterBlock> {
-    fn read(&self) -> nb::Result<u8, Error> {
+    fn read<WORD>(&self) -> nb::Result<WORD, Error> {
         let sr = self.sr.read();
 
         // Check for any errors
@@ -230,13 +609,13 @@ trait UsartReadWrite: Deref<Target = crate::pac::usart1::RegisterBlock> {
         }
     }
 
-    fn write(&self, byte: u8) -> nb::Result<(), Infallible> {
+    fn write<WORD>(&self, word: WORD) -> nb::Result<(), Infallible> {
         let sr = self.sr.read();
 
         if sr.txe().bit_is_set() {
             // NOTE(unsafe) atomic write to stateless register
             // NOTE(write_volatile) 8-bit write that's not possible through the svd2rust API
-            unsafe { ptr::write_volatile(&self.dr as *const _ as *mut _, byte) }
+            unsafe { ptr::write_volatile(&self.dr as *const _ as *mut _, word) }
             Ok(())
         } else {
             Err(nb::Error::WouldBlock)
@@ -255,6 +634,472 @@ trait UsartReadWrite: Deref<Target = crate::pac::usart1::RegisterBlock> {
 }
 impl UsartReadWrite for &crate::pac::usart1::RegisterBlock {}
 
+mod sealed {
+    use crate::afio::MAPR;
+    use crate::rcc::{Enable, Reset};
+
+    pub trait Instance:
+        core::ops::Deref<Target = crate::pac::usart1::RegisterBlock> + Enable + Reset
+    {
+        fn remap(mapr: &mut MAPR, remap: u8);
+
+        /// Returns the registers of the USART owned by a split `Tx` or `Rx`
+        fn registers() -> &'static crate::pac::usart1::RegisterBlock;
+    }
+}
+use sealed::Instance;
+
+impl<USART, PINS> Serial<USART, PINS>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Enables, resets and remaps the USART and configures the frame format. The USART is
+    /// left disabled so that the caller can select the mode of operation first.
+    fn _usart(
+        usart: USART,
+        pins: PINS,
+        remap: u8,
+        mapr: &mut MAPR,
+        config: &Config,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self {
+        // enable and reset USART
+        USART::enable(apb);
+        USART::reset(apb);
+
+        USART::remap(mapr, remap);
+
+        // enable DMA transfers
+        usart.cr3.write(|w| w.dmat().set_bit().dmar().set_bit());
+
+        // Configure baud rate
+        let brr = USART::Bus::get_frequency(&clocks).0 / config.baudrate.0;
+        assert!(brr >= 16, "impossible baud rate");
+        usart.brr.write(|w| unsafe { w.bits(brr) });
+
+        // Configure parity and word length
+        // Unlike most uart devices, the "word length" of this usart device refers to
+        // the size of the data plus the parity bit. I.e. "word length"=8, parity=even
+        // results in 7 bits of data. Therefore, in order to get 8 bits and one parity
+        // bit, we need to set the "word" length to 9 when using parity bits.
+        let (word_length, parity_control_enable, parity) = match config.parity {
+            Parity::ParityNone => (false, false, false),
+            Parity::ParityEven => (true, true, false),
+            Parity::ParityOdd => (true, true, true),
+        };
+        let (wake, address) = match config.wakeup {
+            WakeUp::IdleLine => (false, 0),
+            WakeUp::AddressMark { address } => {
+                assert!(address < 16, "node address has to fit into 4 bits");
+                (true, address)
+            }
+        };
+        usart.cr1.modify(|_r, w| {
+            w.m()
+                .bit(word_length)
+                .ps()
+                .bit(parity)
+                .pce()
+                .bit(parity_control_enable)
+                .wake()
+                .bit(wake)
+        });
+        usart.cr2.modify(|_r, w| w.add().bits(address));
+
+        // Configure stop bits
+        let stop_bits = match config.stopbits {
+            StopBits::STOP1 => 0b00,
+            StopBits::STOP0P5 => 0b01,
+            StopBits::STOP2 => 0b10,
+            StopBits::STOP1P5 => 0b11,
+        };
+        usart.cr2.modify(|_r, w| w.stop().bits(stop_bits));
+
+        Serial {
+            usart,
+            pins,
+            baudrate: config.baudrate,
+            _word: PhantomData,
+        }
+    }
+
+    /// Enables the flow control selected in `config`, only if `PINS` include the CTS and RTS
+    /// pins, an unconnected CTS input could stop the transmitter forever
+    fn enable_flowcontrol(&self, config: &Config)
+    where
+        PINS: Pins<USART>,
+    {
+        let (rtse, ctse) = match config.flowcontrol {
+            _ if !PINS::FLOWCONTROL => (false, false),
+            FlowControl::None => (false, false),
+            FlowControl::Rts => (true, false),
+            FlowControl::Cts => (false, true),
+            FlowControl::RtsCts => (true, true),
+        };
+        self.usart
+            .cr3
+            .modify(|_, w| w.rtse().bit(rtse).ctse().bit(ctse));
+    }
+
+    fn enable(&self) {
+        // UE: enable USART
+        // RE: enable receiver
+        // TE: enable transceiver
+        self.usart
+            .cr1
+            .modify(|_r, w| w.ue().set_bit().re().set_bit().te().set_bit());
+    }
+
+    /// Configures the USART for single-wire half-duplex communication (HDSEL)
+    ///
+    /// TX and RX are internally connected and only the TX pin is used. The pin is released
+    /// while nothing is transmitted, so the other node can drive the line. Note that the
+    /// receiver stays enabled while transmitting, every byte written is also read back.
+    ///
+    /// The remaining arguments are the same as for the regular constructors, e.g.
+    /// [`Serial::usart1`](struct.Serial.html#method.usart1).
+    pub fn half_duplex(
+        usart: USART,
+        pin: PINS,
+        mapr: &mut MAPR,
+        config: Config,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self
+    where
+        PINS: HalfDuplexPin<USART>,
+    {
+        let serial = Serial::_usart(usart, pin, PINS::REMAP, mapr, &config, clocks, apb);
+        serial.usart.cr3.modify(|_, w| w.hdsel().set_bit());
+        serial.enable();
+        serial
+    }
+
+    /// Configures the USART as synchronous master, the clock is output on CK
+    ///
+    /// Data is only clocked while transmitting, so every byte received has to be clocked in by
+    /// writing a byte, like the SPI master mode.
+    pub fn synchronous(
+        usart: USART,
+        pins: PINS,
+        mapr: &mut MAPR,
+        config: Config,
+        sync: SyncConfig,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self
+    where
+        PINS: SyncPins<USART>,
+    {
+        let serial = Serial::_usart(usart, pins, PINS::REMAP, mapr, &config, clocks, apb);
+        serial.usart.cr2.modify(|_, w| {
+            w.clken()
+                .set_bit()
+                .cpol()
+                .bit(sync.mode.polarity == Polarity::IdleHigh)
+                .cpha()
+                .bit(sync.mode.phase == Phase::CaptureOnSecondTransition)
+                .lbcl()
+                .bit(sync.last_bit_clock)
+        });
+        serial.enable();
+        serial
+    }
+
+    /// Configures the USART for LIN
+    ///
+    /// Frames are forced to 8 data bits without parity and 1 stop bit as required by the LIN
+    /// standard, the parity in `config` is ignored. Breaks are sent with
+    /// [`send_break`](#method.send_break) and detected with `Event::LinBreak`.
+    pub fn lin(
+        usart: USART,
+        pins: PINS,
+        mapr: &mut MAPR,
+        config: Config,
+        break_length: LinBreakLength,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self
+    where
+        PINS: Pins<USART>,
+    {
+        let serial = Serial::_usart(usart, pins, PINS::REMAP, mapr, &config, clocks, apb);
+        serial.enable_flowcontrol(&config);
+        serial
+            .usart
+            .cr1
+            .modify(|_, w| w.m().clear_bit().pce().clear_bit());
+        // RM0008: CLKEN and STOP must be cleared in CR2, SCEN, HDSEL and IREN in CR3
+        serial.usart.cr2.modify(|_, w| {
+            w.stop()
+                .bits(0b00)
+                .clken()
+                .clear_bit()
+                .linen()
+                .set_bit()
+                .lbdl()
+                .bit(match break_length {
+                    LinBreakLength::Bits10 => false,
+                    LinBreakLength::Bits11 => true,
+                })
+        });
+        serial
+            .usart
+            .cr3
+            .modify(|_, w| w.scen().clear_bit().hdsel().clear_bit().iren().clear_bit());
+        serial.enable();
+        serial
+    }
+
+    /// Configures the USART for IrDA SIR with the pins connected to an infrared transceiver
+    ///
+    /// The baud rate must not exceed 115200 bps.
+    pub fn irda(
+        usart: USART,
+        pins: PINS,
+        mapr: &mut MAPR,
+        config: Config,
+        mode: IrdaMode,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self
+    where
+        PINS: Pins<USART>,
+    {
+        // In low power mode the pulse width is derived from the bus clock divided down to
+        // 1.42 MHz ... 2.12 MHz, nominally 1.8432 MHz
+        let psc = match mode {
+            IrdaMode::Normal => 1,
+            IrdaMode::LowPower => {
+                let pclk = USART::Bus::get_frequency(&clocks).0;
+                let psc = (pclk + 921_600) / 1_843_200;
+                assert!(psc <= 0xff, "bus clock too high for IrDA low power mode");
+                psc.max(1)
+            }
+        };
+        let serial = Serial::_usart(usart, pins, PINS::REMAP, mapr, &config, clocks, apb);
+        serial.enable_flowcontrol(&config);
+        serial
+            .usart
+            .gtpr
+            .modify(|_, w| unsafe { w.psc().bits(psc as u8) });
+        // RM0008: LINEN, STOP and CLKEN must be cleared in CR2, SCEN and HDSEL in CR3
+        serial
+            .usart
+            .cr2
+            .modify(|_, w| w.stop().bits(0b00).linen().clear_bit().clken().clear_bit());
+        serial.usart.cr3.modify(|_, w| {
+            w.scen()
+                .clear_bit()
+                .hdsel()
+                .clear_bit()
+                .iren()
+                .set_bit()
+                .irlp()
+                .bit(matches!(mode, IrdaMode::LowPower))
+        });
+        serial.enable();
+        serial
+    }
+
+    /// Configures the USART for ISO 7816-3 smartcard communication
+    ///
+    /// The card expects 8 data bits with even parity, which has to be selected in `config`. The
+    /// stop bits are forced to 1.5.
+    pub fn smartcard(
+        usart: USART,
+        pins: PINS,
+        mapr: &mut MAPR,
+        config: Config,
+        smartcard: SmartcardConfig,
+        clocks: Clocks,
+        apb: &mut USART::Bus,
+    ) -> Self
+    where
+        PINS: SmartcardPins<USART>,
+    {
+        // The card clock is the bus clock divided by 2 * PSC
+        assert!(smartcard.clock.0 > 0, "impossible smartcard clock");
+        let pclk = USART::Bus::get_frequency(&clocks).0;
+        let div = 2 * smartcard.clock.0;
+        let psc = (pclk - 1) / div + 1;
+        assert!((1..=0x1f).contains(&psc), "impossible smartcard clock");
+
+        let serial = Serial::_usart(usart, pins, PINS::REMAP, mapr, &config, clocks, apb);
+        serial
+            .usart
+            .gtpr
+            .write(|w| unsafe { w.gt().bits(smartcard.guard_time).psc().bits(psc as u8) });
+        // RM0008: LINEN must be cleared in CR2, HDSEL and IREN in CR3
+        serial
+            .usart
+            .cr2
+            .modify(|_, w| w.stop().bits(0b11).clken().set_bit().linen().clear_bit());
+        serial.usart.cr3.modify(|_, w| {
+            w.hdsel()
+                .clear_bit()
+                .iren()
+                .clear_bit()
+                .scen()
+                .set_bit()
+                .nack()
+                .bit(smartcard.nack)
+        });
+        serial.enable();
+        serial
+    }
+}
+
+impl<USART, PINS, WORD> Serial<USART, PINS, WORD>
+where
+    USART: Instance,
+{
+    /// Switches to frames with 9 data bits, read and written as `u16`
+    ///
+    /// Parity must be disabled, the 9 bits of a frame already include the parity bit.
+    pub fn into_9bit_data(self) -> Serial<USART, PINS, u16> {
+        assert!(
+            self.usart.cr1.read().pce().bit_is_clear(),
+            "9 data bits with parity are not supported"
+        );
+        self.usart.cr1.modify(|_, w| w.m().set_bit());
+        Serial {
+            usart: self.usart,
+            pins: self.pins,
+            baudrate: self.baudrate,
+            _word: PhantomData,
+        }
+    }
+
+    /// Switches back to frames with 8 data bits, read and written as `u8`
+    pub fn into_8bit_data(self) -> Serial<USART, PINS, u8> {
+        if self.usart.cr1.read().pce().bit_is_clear() {
+            self.usart.cr1.modify(|_, w| w.m().clear_bit());
+        }
+        Serial {
+            usart: self.usart,
+            pins: self.pins,
+            baudrate: self.baudrate,
+            _word: PhantomData,
+        }
+    }
+
+    /// Puts the receiver into mute mode until the wakeup condition selected in
+    /// `Config::wakeup` occurs
+    pub fn mute(&mut self) {
+        self.usart.cr1.modify(|_, w| w.rwu().set_bit());
+    }
+
+    /// Returns `true` while the receiver is in mute mode
+    pub fn is_muted(&self) -> bool {
+        self.usart.cr1.read().rwu().bit_is_set()
+    }
+
+    /// Returns `true` if the CTS input changed
+    pub fn is_cts_changed(&self) -> bool {
+        self.usart.sr.read().cts().bit_is_set()
+    }
+
+    /// Clears the CTS change flag
+    pub fn clear_cts_changed(&mut self) {
+        self.usart.sr.modify(|_, w| w.cts().clear_bit());
+    }
+
+    /// Sends a LIN break after the current byte
+    pub fn send_break(&mut self) {
+        self.usart.cr1.modify(|_, w| w.sbk().set_bit());
+    }
+
+    /// Returns `true` if a LIN break was detected
+    pub fn is_break_detected(&self) -> bool {
+        self.usart.sr.read().lbd().bit_is_set()
+    }
+
+    /// Clears the LIN break detection flag
+    pub fn clear_break_detected(&mut self) {
+        self.usart.sr.modify(|_, w| w.lbd().clear_bit());
+    }
+}
+
+impl<USART, PINS, WORD> Serial<USART, PINS, WORD>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate register after the clocks have been reconfigured
+    ///
+    /// Waits for the current transmission to complete. The prescaler of the IrDA low-power and
+    /// smartcard modes is not changed.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        reconfigure_brr(&self.usart, USART::Bus::get_frequency(clocks), self.baudrate);
+    }
+}
+
+impl<USART, WORD> Tx<USART, WORD>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate register after the clocks have been reconfigured
+    ///
+    /// Waits for the current transmission to complete. Both halves share the baud rate
+    /// register, so this re-times the `Rx` half as well.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        reconfigure_brr(USART::registers(), USART::Bus::get_frequency(clocks), self.baudrate);
+    }
+}
+
+impl<USART, WORD> Rx<USART, WORD>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate register after the clocks have been reconfigured
+    ///
+    /// Waits for the current transmission to complete. Both halves share the baud rate
+    /// register, so this re-times the `Tx` half as well.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        reconfigure_brr(USART::registers(), USART::Bus::get_frequency(clocks), self.baudrate);
+    }
+}
+
+impl<USART, CHANNEL> TxDma<Tx<USART>, CHANNEL>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate register after the clocks have been reconfigured, see
+    /// [`Tx::reconfigure`](struct.Tx.html#method.reconfigure)
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.payload.reconfigure(clocks);
+    }
+}
+
+impl<USART, CHANNEL> RxDma<Rx<USART>, CHANNEL>
+where
+    USART: Instance,
+    USART::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate register after the clocks have been reconfigured, see
+    /// [`Rx::reconfigure`](struct.Rx.html#method.reconfigure)
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.payload.reconfigure(clocks);
+    }
+}
+
+/// Writes the baud rate register for `baudrate` at the bus clock `pclk`
+fn reconfigure_brr(usart: &crate::pac::usart1::RegisterBlock, pclk: Hertz, baudrate: Bps) {
+    let brr = pclk.0 / baudrate.0;
+    assert!(brr >= 16, "impossible baud rate");
+
+    while usart.sr.read().tc().bit_is_clear() {}
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    usart.brr.write(|w| unsafe { w.bits(brr) });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+}
+
 macro_rules! hal {
     ($(
         $(#[$meta:meta])*
@@ -267,6 +1112,19 @@ macro_rules! hal {
         ),
     )+) => {
         $(
+            impl Instance for $USARTX {
+                fn remap(mapr: &mut MAPR, remap: u8) {
+                    #[allow(unused_unsafe)]
+                    mapr.modify_mapr(|_, w| unsafe{
+                            w.$usartX_remap().$bit(($closure)(remap))
+                        });
+                }
+
+                fn registers() -> &'static crate::pac::usart1::RegisterBlock {
+                    unsafe { &*$USARTX::ptr() }
+                }
+            }
+
             $(#[$meta])*
             /// The behaviour of the functions is equal for all three USARTs.
             /// Except that they are using the corresponding USART hardware and pins.
@@ -298,78 +1156,40 @@ macro_rules! hal {
                 where
                     PINS: Pins<$USARTX>,
                 {
-                    // enable and reset $USARTX
-                    $USARTX::enable(apb);
-                    $USARTX::reset(apb);
-
-                    #[allow(unused_unsafe)]
-                    mapr.modify_mapr(|_, w| unsafe{
-                            w.$usartX_remap().$bit(($closure)(PINS::REMAP))
-                        });
-
-                    // enable DMA transfers
-                    usart.cr3.write(|w| w.dmat().set_bit().dmar().set_bit());
-
-                    // Configure baud rate
-                    let brr = <$USARTX as RccBus>::Bus::get_frequency(&clocks).0 / config.baudrate.0;
-                    assert!(brr >= 16, "impossible baud rate");
-                    usart.brr.write(|w| unsafe { w.bits(brr) });
-
-                    // Configure parity and word length
-                    // Unlike most uart devices, the "word length" of this usart device refers to
-                    // the size of the data plus the parity bit. I.e. "word length"=8, parity=even
-                    // results in 7 bits of data. Therefore, in order to get 8 bits and one parity
-                    // bit, we need to set the "word" length to 9 when using parity bits.
-                    let (word_length, parity_control_enable, parity) = match config.parity {
-                        Parity::ParityNone => (false, false, false),
-                        Parity::ParityEven => (true, true, false),
-                        Parity::ParityOdd => (true, true, true),
-                    };
-                    usart.cr1.modify(|_r, w| {
-                        w
-                            .m().bit(word_length)
-                            .ps().bit(parity)
-                            .pce().bit(parity_control_enable)
-                    });
-
-                    // Configure stop bits
-                    let stop_bits = match config.stopbits {
-                        StopBits::STOP1 => 0b00,
-                        StopBits::STOP0P5 => 0b01,
-                        StopBits::STOP2 => 0b10,
-                        StopBits::STOP1P5 => 0b11,
-                    };
-                    usart.cr2.modify(|_r, w| {
-                        w.stop().bits(stop_bits)
-                    });
-
-                    // UE: enable USART
-                    // RE: enable receiver
-                    // TE: enable transceiver
-                    usart
-                        .cr1
-                        .modify(|_r, w| w.ue().set_bit().re().set_bit().te().set_bit());
-
-                    Serial { usart, pins }
+                    let serial = Serial::_usart(usart, pins, PINS::REMAP, mapr, &config, clocks, apb);
+                    serial.enable_flowcontrol(&config);
+                    serial.enable();
+                    serial
                 }
+            }
+
+            impl<PINS, WORD> Serial<$USARTX, PINS, WORD> {
 
                 /// Starts listening to the USART by enabling the _Received data
-                /// ready to be read (RXNE)_ interrupt and _Transmit data
-                /// register empty (TXE)_ interrupt
+                /// ready to be read (RXNE)_ interrupt, _Transmit data
+                /// register empty (TXE)_ interrupt or _Idle line detected (IDLE)_
+                /// interrupt
                 pub fn listen(&mut self, event: Event) {
                     match event {
                         Event::Rxne => self.usart.cr1.modify(|_, w| w.rxneie().set_bit()),
                         Event::Txe => self.usart.cr1.modify(|_, w| w.txeie().set_bit()),
+                        Event::Idle => self.usart.cr1.modify(|_, w| w.idleie().set_bit()),
+                        Event::LinBreak => self.usart.cr2.modify(|_, w| w.lbdie().set_bit()),
+                        Event::Cts => self.usart.cr3.modify(|_, w| w.ctsie().set_bit()),
                     }
                 }
 
                 /// Stops listening to the USART by disabling the _Received data
-                /// ready to be read (RXNE)_ interrupt and _Transmit data
-                /// register empty (TXE)_ interrupt
+                /// ready to be read (RXNE)_ interrupt, _Transmit data
+                /// register empty (TXE)_ interrupt or _Idle line detected (IDLE)_
+                /// interrupt
                 pub fn unlisten(&mut self, event: Event) {
                     match event {
                         Event::Rxne => self.usart.cr1.modify(|_, w| w.rxneie().clear_bit()),
                         Event::Txe => self.usart.cr1.modify(|_, w| w.txeie().clear_bit()),
+                        Event::Idle => self.usart.cr1.modify(|_, w| w.idleie().clear_bit()),
+                        Event::LinBreak => self.usart.cr2.modify(|_, w| w.lbdie().clear_bit()),
+                        Event::Cts => self.usart.cr3.modify(|_, w| w.ctsie().clear_bit()),
                     }
                 }
 
@@ -380,19 +1200,23 @@ macro_rules! hal {
 
                 /// Separates the serial struct into separate channel objects for sending (Tx) and
                 /// receiving (Rx)
-                pub fn split(self) -> (Tx<$USARTX>, Rx<$USARTX>) {
+                pub fn split(self) -> (Tx<$USARTX, WORD>, Rx<$USARTX, WORD>) {
                     (
                         Tx {
                             _usart: PhantomData,
+                            baudrate: self.baudrate,
+                            _word: PhantomData,
                         },
                         Rx {
                             _usart: PhantomData,
+                            baudrate: self.baudrate,
+                            _word: PhantomData,
                         },
                     )
                 }
             }
 
-            impl Tx<$USARTX> {
+            impl<WORD> Tx<$USARTX, WORD> {
                 pub fn listen(&mut self) {
                     unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.txeie().set_bit()) };
                 }
@@ -402,7 +1226,7 @@ macro_rules! hal {
                 }
             }
 
-            impl Rx<$USARTX> {
+            impl<WORD> Rx<$USARTX, WORD> {
                 pub fn listen(&mut self) {
                     unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.rxneie().set_bit()) };
                 }
@@ -410,6 +1234,39 @@ macro_rules! hal {
                 pub fn unlisten(&mut self) {
                     unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.rxneie().clear_bit()) };
                 }
+
+                /// Enables the _Idle line detected (IDLE)_ interrupt
+                pub fn listen_idle(&mut self) {
+                    unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.idleie().set_bit()) };
+                }
+
+                pub fn unlisten_idle(&mut self) {
+                    unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.idleie().clear_bit()) };
+                }
+
+                /// Returns `true` if the line went idle after receiving data
+                pub fn is_idle(&self) -> bool {
+                    unsafe { (*$USARTX::ptr()).sr.read().idle().bit_is_set() }
+                }
+
+                /// Clears the IDLE flag by reading SR followed by DR
+                pub fn clear_idle_interrupt(&self) {
+                    unsafe {
+                        let _ = (*$USARTX::ptr()).sr.read();
+                        let _ = (*$USARTX::ptr()).dr.read();
+                    }
+                }
+
+                /// Puts the receiver into mute mode until the wakeup condition
+                /// selected in `Config::wakeup` occurs
+                pub fn mute(&mut self) {
+                    unsafe { (*$USARTX::ptr()).cr1.modify(|_, w| w.rwu().set_bit()) };
+                }
+
+                /// Returns `true` while the receiver is in mute mode
+                pub fn is_muted(&self) -> bool {
+                    unsafe { (*$USARTX::ptr()).cr1.read().rwu().bit_is_set() }
+                }
             }
 
             impl crate::hal::serial::Read<u8> for Rx<$USARTX> {
@@ -420,6 +1277,14 @@ macro_rules! hal {
                 }
             }
 
+            impl crate::hal::serial::Read<u16> for Rx<$USARTX, u16> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    unsafe { &*$USARTX::ptr() }.read()
+                }
+            }
+
             impl crate::hal::serial::Write<u8> for Tx<$USARTX> {
                 type Error = Infallible;
 
@@ -431,6 +1296,17 @@ macro_rules! hal {
                 }
             }
 
+            impl crate::hal::serial::Write<u16> for Tx<$USARTX, u16> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    unsafe { &*$USARTX::ptr() }.flush()
+                }
+                fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+                    unsafe { &*$USARTX::ptr() }.write(word)
+                }
+            }
+
             impl<PINS> crate::hal::serial::Read<u8> for Serial<$USARTX, PINS> {
                 type Error = Error;
 
@@ -439,6 +1315,14 @@ macro_rules! hal {
                 }
             }
 
+            impl<PINS> crate::hal::serial::Read<u16> for Serial<$USARTX, PINS, u16> {
+                type Error = Error;
+
+                fn read(&mut self) -> nb::Result<u16, Error> {
+                    self.usart.deref().read()
+                }
+            }
+
             impl<PINS> crate::hal::serial::Write<u8> for Serial<$USARTX, PINS> {
                 type Error = Infallible;
 
@@ -451,6 +1335,18 @@ macro_rules! hal {
                 }
             }
 
+            impl<PINS> crate::hal::serial::Write<u16> for Serial<$USARTX, PINS, u16> {
+                type Error = Infallible;
+
+                fn flush(&mut self) -> nb::Result<(), Self::Error> {
+                    self.usart.deref().flush()
+                }
+
+                fn write(&mut self, word: u16) -> nb::Result<(), Self::Error> {
+                    self.usart.deref().write(word)
+                }
+            }
+
         )+
     }
 }
@@ -494,6 +1390,83 @@ hal! {
     ),
 }
 
+/// Serial transmitter that drives the driver enable (DE) input of an RS-485 transceiver
+///
+/// DE is asserted before a byte is written and released in `flush` once the TC
+/// (transmission complete) flag is set, so the bus is freed only after the last stop bit.
+/// `bwrite_all` waits for TC after the last byte and therefore always releases the bus.
+pub struct Rs485Tx<USART, DE> {
+    tx: Tx<USART>,
+    de: DE,
+}
+
+impl<USART> Tx<USART> {
+    /// Uses `de` as driver enable pin of an RS-485 transceiver
+    pub fn with_driver_enable<DE>(self, mut de: DE) -> Rs485Tx<USART, DE>
+    where
+        DE: OutputPin<Error = Infallible>,
+    {
+        de.set_low().ok();
+        Rs485Tx { tx: self, de }
+    }
+}
+
+impl<USART, DE> Rs485Tx<USART, DE> {
+    /// Returns the transmitter and the driver enable pin
+    pub fn release(self) -> (Tx<USART>, DE) {
+        (self.tx, self.de)
+    }
+
+    /// Recomputes the baud rate register after the clocks have been reconfigured, see
+    /// [`Tx::reconfigure`](struct.Tx.html#method.reconfigure)
+    pub fn reconfigure(&mut self, clocks: &Clocks)
+    where
+        USART: Instance,
+        USART::Bus: GetBusFreq,
+    {
+        self.tx.reconfigure(clocks);
+    }
+}
+
+impl<USART, DE> crate::hal::serial::Write<u8> for Rs485Tx<USART, DE>
+where
+    Tx<USART>: crate::hal::serial::Write<u8, Error = Infallible>,
+    DE: OutputPin<Error = Infallible>,
+{
+    type Error = Infallible;
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.tx.flush()?;
+        self.de.set_low().ok();
+        Ok(())
+    }
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.de.set_high().ok();
+        self.tx.write(byte)
+    }
+}
+
+impl<USART, DE> crate::hal::blocking::serial::Write<u8> for Rs485Tx<USART, DE>
+where
+    Tx<USART>: crate::hal::serial::Write<u8, Error = Infallible>,
+    DE: OutputPin<Error = Infallible>,
+{
+    type Error = Infallible;
+
+    fn bwrite_all(&mut self, buffer: &[u8]) -> Result<(), Self::Error> {
+        for &byte in buffer {
+            nb::block!(crate::hal::serial::Write::write(self, byte))?;
+        }
+        // Release the bus only once the last stop bit has been sent
+        self.bflush()
+    }
+
+    fn bflush(&mut self) -> Result<(), Self::Error> {
+        nb::block!(crate::hal::serial::Write::flush(self))
+    }
+}
+
 pub type Rx1 = Rx<USART1>;
 pub type Tx1 = Tx<USART1>;
 pub type Rx2 = Rx<USART2>;
@@ -612,6 +1585,51 @@ macro_rules! serialdma {
                 }
             }
 
+            impl<B> crate::dma::RingReadDma<B, u8> for $rxdma where B: as_slice::AsMutSlice<Element=u8> {
+                fn ring_read(mut self, buffer: &'static mut B,
+                ) -> RingBuffer<B, Self>
+                {
+                    let capacity = {
+                        let buffer = buffer.as_mut_slice();
+                        assert!(!buffer.is_empty());
+                        self.channel.set_peripheral_address(unsafe{ &(*$USARTX::ptr()).dr as *const _ as u32 }, false);
+                        self.channel.set_memory_address(buffer.as_ptr() as u32, true);
+                        self.channel.set_transfer_length(buffer.len());
+                        buffer.len()
+                    };
+
+                    atomic::compiler_fence(Ordering::Release);
+
+                    self.channel.ch().cr.modify(|_, w| { w
+                        .mem2mem() .clear_bit()
+                        .pl()      .medium()
+                        .msize()   .bits8()
+                        .psize()   .bits8()
+                        .circ()    .set_bit()
+                        .dir()     .clear_bit()
+                    });
+
+                    self.start();
+
+                    RingBuffer::new(buffer, capacity, self)
+                }
+            }
+
+            impl<B> RingBuffer<B, $rxdma> {
+                /// Returns `true` if the line went idle after receiving data
+                pub fn is_idle(&self) -> bool {
+                    unsafe { (*$USARTX::ptr()).sr.read().idle().bit_is_set() }
+                }
+
+                /// Clears the IDLE flag by reading SR followed by DR
+                pub fn clear_idle_interrupt(&self) {
+                    unsafe {
+                        let _ = (*$USARTX::ptr()).sr.read();
+                        let _ = (*$USARTX::ptr()).dr.read();
+                    }
+                }
+            }
+
             impl<B> crate::dma::ReadDma<B, u8> for $rxdma where B: as_slice::AsMutSlice<Element=u8> {
                 fn read(mut self, buffer: &'static mut B,
                 ) -> Transfer<W, &'static mut B, Self>
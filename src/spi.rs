@@ -40,10 +40,12 @@ use crate::pac::SPI3;
 use crate::pac::{SPI1, SPI2};
 
 use crate::afio::MAPR;
-use crate::dma::dma1::{C3, C5};
+use crate::dma::dma1;
 #[cfg(feature = "connectivity")]
-use crate::dma::dma2::C2;
-use crate::dma::{Static, Transfer, TransferPayload, Transmit, TxDma, R};
+use crate::dma::dma2;
+use crate::dma::{
+    Receive, RxDma, RxTxDma, Static, Transfer, TransferPayload, Transmit, TxDma, R, W,
+};
 use crate::gpio::gpioa::{PA5, PA6, PA7};
 use crate::gpio::gpiob::{PB13, PB14, PB15, PB3, PB4, PB5};
 #[cfg(feature = "connectivity")]
@@ -54,7 +56,7 @@ use crate::time::Hertz;
 
 use core::sync::atomic::{self, Ordering};
 
-use as_slice::AsSlice;
+use as_slice::{AsMutSlice, AsSlice};
 
 /// SPI error
 #[derive(Debug)]
@@ -114,6 +116,7 @@ pins_impl!(
 pub struct Spi<SPI, REMAP, PINS, FRAMESIZE> {
     spi: SPI,
     pins: PINS,
+    freq: Hertz,
     _remap: PhantomData<REMAP>,
     _framesize: PhantomData<FRAMESIZE>,
 }
@@ -311,6 +314,39 @@ where
     }
 }
 
+/// Returns the BR bits for the highest SCK frequency not above `freq`
+fn baud_rate_bits(pclk: Hertz, freq: Hertz) -> u8 {
+    match pclk.0 / freq.0 {
+        0 => unreachable!(),
+        1..=2 => 0b000,
+        3..=5 => 0b001,
+        6..=11 => 0b010,
+        12..=23 => 0b011,
+        24..=47 => 0b100,
+        48..=95 => 0b101,
+        96..=191 => 0b110,
+        _ => 0b111,
+    }
+}
+
+impl<SPI, REMAP, PINS, FrameSize> Spi<SPI, REMAP, PINS, FrameSize>
+where
+    SPI: Deref<Target = SpiRegisterBlock> + Enable,
+    SPI::Bus: GetBusFreq,
+{
+    /// Recomputes the baud rate prescaler after the clocks have been reconfigured
+    ///
+    /// Waits for the current transfer to complete.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        let br = baud_rate_bits(SPI::Bus::get_frequency(clocks), self.freq);
+
+        while self.spi.sr.read().bsy().bit_is_set() {}
+        self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+        self.spi.cr1.modify(|_, w| w.br().bits(br));
+        self.spi.cr1.modify(|_, w| w.spe().set_bit());
+    }
+}
+
 impl<SPI, REMAP, PINS> Spi<SPI, REMAP, PINS, u8>
 where
     SPI: Deref<Target = SpiRegisterBlock> + Enable + Reset,
@@ -331,17 +367,7 @@ where
         // disable SS output
         spi.cr2.write(|w| w.ssoe().clear_bit());
 
-        let br = match SPI::Bus::get_frequency(&clocks).0 / freq.0 {
-            0 => unreachable!(),
-            1..=2 => 0b000,
-            3..=5 => 0b001,
-            6..=11 => 0b010,
-            12..=23 => 0b011,
-            24..=47 => 0b100,
-            48..=95 => 0b101,
-            96..=191 => 0b110,
-            _ => 0b111,
-        };
+        let br = baud_rate_bits(SPI::Bus::get_frequency(&clocks), freq);
 
         spi.cr1.write(|w| {
             w
@@ -383,6 +409,7 @@ where
         Spi {
             spi,
             pins,
+            freq,
             _remap: PhantomData,
             _framesize: PhantomData,
         }
@@ -395,6 +422,7 @@ where
         Spi {
             spi: self.spi,
             pins: self.pins,
+            freq: self.freq,
             _remap: PhantomData,
             _framesize: PhantomData,
         }
@@ -413,6 +441,7 @@ where
         Spi {
             spi: self.spi,
             pins: self.pins,
+            freq: self.freq,
             _remap: PhantomData,
             _framesize: PhantomData,
         }
@@ -506,18 +535,100 @@ pub struct SpiPayload<SPI, REMAP, PINS> {
 
 pub type SpiTxDma<SPI, REMAP, PINS, CHANNEL> = TxDma<SpiPayload<SPI, REMAP, PINS>, CHANNEL>;
 
+pub type SpiRxDma<SPI, REMAP, PINS, CHANNEL> = RxDma<SpiPayload<SPI, REMAP, PINS>, CHANNEL>;
+
+pub type SpiRxTxDma<SPI, REMAP, PINS, RXCHANNEL, TXCHANNEL> =
+    RxTxDma<SpiPayload<SPI, REMAP, PINS>, RXCHANNEL, TXCHANNEL>;
+
 macro_rules! spi_dma {
-    ($SPIi:ident, $TCi:ident) => {
+    ($SPIi:ident, $RCi:ty, $TCi:ty) => {
         impl<REMAP, PINS> Transmit for SpiTxDma<$SPIi, REMAP, PINS, $TCi> {
             type TxChannel = $TCi;
             type ReceivedWord = u8;
         }
 
+        impl<REMAP, PINS> Receive for SpiRxDma<$SPIi, REMAP, PINS, $RCi> {
+            type RxChannel = $RCi;
+            type TransmittedWord = u8;
+        }
+
+        impl<REMAP, PINS> Transmit for SpiRxTxDma<$SPIi, REMAP, PINS, $RCi, $TCi> {
+            type TxChannel = $TCi;
+            type ReceivedWord = u8;
+        }
+
+        impl<REMAP, PINS> Receive for SpiRxTxDma<$SPIi, REMAP, PINS, $RCi, $TCi> {
+            type RxChannel = $RCi;
+            type TransmittedWord = u8;
+        }
+
         impl<REMAP, PINS> Spi<$SPIi, REMAP, PINS, u8> {
             pub fn with_tx_dma(self, channel: $TCi) -> SpiTxDma<$SPIi, REMAP, PINS, $TCi> {
                 let payload = SpiPayload { spi: self };
                 SpiTxDma { payload, channel }
             }
+
+            /// Receives using DMA only, nothing is transmitted
+            ///
+            /// The SPI is switched to receive-only mode (RXONLY). In master mode
+            /// the clock runs from the start of a transfer until it is stopped,
+            /// so a few more frames may be clocked in and discarded after the
+            /// buffer is full. Use `with_rx_tx_dma` if the slave must not see
+            /// extra clock cycles.
+            pub fn with_rx_dma(self, channel: $RCi) -> SpiRxDma<$SPIi, REMAP, PINS, $RCi> {
+                // RXONLY may only be changed while the SPI is disabled, it is
+                // enabled again when a transfer starts
+                self.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                self.spi.cr1.modify(|_, w| w.rxonly().set_bit());
+                let payload = SpiPayload { spi: self };
+                SpiRxDma { payload, channel }
+            }
+
+            /// Transmits and receives using DMA, see `ReadWriteDma`
+            ///
+            /// The clock only runs while frames are transmitted, so exactly as
+            /// many frames are received as are transmitted.
+            pub fn with_rx_tx_dma(
+                self,
+                rxchannel: $RCi,
+                txchannel: $TCi,
+            ) -> SpiRxTxDma<$SPIi, REMAP, PINS, $RCi, $TCi> {
+                let payload = SpiPayload { spi: self };
+                SpiRxTxDma {
+                    payload,
+                    rxchannel,
+                    txchannel,
+                }
+            }
+        }
+
+        impl<REMAP, PINS> SpiTxDma<$SPIi, REMAP, PINS, $TCi> {
+            pub fn release(self) -> (Spi<$SPIi, REMAP, PINS, u8>, $TCi) {
+                let SpiTxDma { payload, channel } = self;
+                (payload.spi, channel)
+            }
+        }
+
+        impl<REMAP, PINS> SpiRxDma<$SPIi, REMAP, PINS, $RCi> {
+            pub fn release(self) -> (Spi<$SPIi, REMAP, PINS, u8>, $RCi) {
+                let SpiRxDma { payload, channel } = self;
+                let spi = &payload.spi.spi;
+                spi.cr1.modify(|_, w| w.spe().clear_bit());
+                spi.cr1.modify(|_, w| w.rxonly().clear_bit());
+                spi.cr1.modify(|_, w| w.spe().set_bit());
+                (payload.spi, channel)
+            }
+        }
+
+        impl<REMAP, PINS> SpiRxTxDma<$SPIi, REMAP, PINS, $RCi, $TCi> {
+            pub fn release(self) -> (Spi<$SPIi, REMAP, PINS, u8>, $RCi, $TCi) {
+                let SpiRxTxDma {
+                    payload,
+                    rxchannel,
+                    txchannel,
+                } = self;
+                (payload.spi, rxchannel, txchannel)
+            }
         }
 
         impl<REMAP, PINS> TransferPayload for SpiTxDma<$SPIi, REMAP, PINS, $TCi> {
@@ -539,6 +650,60 @@ macro_rules! spi_dma {
             }
         }
 
+        impl<REMAP, PINS> TransferPayload for SpiRxDma<$SPIi, REMAP, PINS, $RCi> {
+            fn start(&mut self) {
+                self.payload
+                    .spi
+                    .spi
+                    .cr2
+                    .modify(|_, w| w.rxdmaen().set_bit());
+                self.channel.start();
+                // in receive-only master mode enabling the SPI starts the clock
+                self.payload.spi.spi.cr1.modify(|_, w| w.spe().set_bit());
+            }
+            fn stop(&mut self) {
+                self.payload.spi.spi.cr1.modify(|_, w| w.spe().clear_bit());
+                self.channel.stop();
+                self.payload
+                    .spi
+                    .spi
+                    .cr2
+                    .modify(|_, w| w.rxdmaen().clear_bit());
+                // discard the frames received after the buffer was full and the
+                // overrun, so the next transfer starts with an empty DR
+                self.payload.spi.spi.dr.read();
+                self.payload.spi.spi.sr.read();
+            }
+        }
+
+        impl<REMAP, PINS> TransferPayload for SpiRxTxDma<$SPIi, REMAP, PINS, $RCi, $TCi> {
+            fn start(&mut self) {
+                // RM0008: enable the receive request before the transmit request
+                // so that no received frame is missed
+                self.payload
+                    .spi
+                    .spi
+                    .cr2
+                    .modify(|_, w| w.rxdmaen().set_bit());
+                self.rxchannel.start();
+                self.txchannel.start();
+                self.payload
+                    .spi
+                    .spi
+                    .cr2
+                    .modify(|_, w| w.txdmaen().set_bit());
+            }
+            fn stop(&mut self) {
+                self.payload
+                    .spi
+                    .spi
+                    .cr2
+                    .modify(|_, w| w.txdmaen().clear_bit().rxdmaen().clear_bit());
+                self.txchannel.stop();
+                self.rxchannel.stop();
+            }
+        }
+
         impl<A, B, REMAP, PIN> crate::dma::WriteDma<A, B, u8> for SpiTxDma<$SPIi, REMAP, PIN, $TCi>
         where
             A: AsSlice<Element = u8>,
@@ -582,10 +747,135 @@ macro_rules! spi_dma {
                 Transfer::r(buffer, self)
             }
         }
+
+        impl<B, REMAP, PIN> crate::dma::ReadDma<B, u8> for SpiRxDma<$SPIi, REMAP, PIN, $RCi>
+        where
+            B: AsMutSlice<Element = u8>,
+        {
+            fn read(mut self, buffer: &'static mut B) -> Transfer<W, &'static mut B, Self> {
+                {
+                    let buffer = buffer.as_mut_slice();
+                    self.channel.set_peripheral_address(
+                        unsafe { &(*$SPIi::ptr()).dr as *const _ as u32 },
+                        false,
+                    );
+                    self.channel
+                        .set_memory_address(buffer.as_ptr() as u32, true);
+                    self.channel.set_transfer_length(buffer.len());
+                }
+                atomic::compiler_fence(Ordering::Release);
+                self.channel.ch().cr.modify(|_, w| {
+                    w
+                        // memory to memory mode disabled
+                        .mem2mem()
+                        .clear_bit()
+                        // medium channel priority level
+                        .pl()
+                        .medium()
+                        // 8-bit memory size
+                        .msize()
+                        .bits8()
+                        // 8-bit peripheral size
+                        .psize()
+                        .bits8()
+                        // circular mode disabled
+                        .circ()
+                        .clear_bit()
+                        // write to memory
+                        .dir()
+                        .clear_bit()
+                });
+                self.start();
+
+                Transfer::w(buffer, self)
+            }
+        }
+
+        impl<RXB, TXB, REMAP, PIN> crate::dma::ReadWriteDma<RXB, TXB, u8>
+            for SpiRxTxDma<$SPIi, REMAP, PIN, $RCi, $TCi>
+        where
+            RXB: AsMutSlice<Element = u8>,
+            TXB: AsSlice<Element = u8>,
+        {
+            fn read_write(
+                mut self,
+                rx: &'static mut RXB,
+                tx: &'static TXB,
+            ) -> Transfer<W, (&'static mut RXB, &'static TXB), Self> {
+                {
+                    let rx = rx.as_mut_slice();
+                    let tx = tx.as_slice();
+                    assert!(
+                        rx.len() == tx.len(),
+                        "receive and transmit buffers have to be of the same length"
+                    );
+
+                    self.rxchannel.set_peripheral_address(
+                        unsafe { &(*$SPIi::ptr()).dr as *const _ as u32 },
+                        false,
+                    );
+                    self.rxchannel.set_memory_address(rx.as_ptr() as u32, true);
+                    self.rxchannel.set_transfer_length(rx.len());
+
+                    self.txchannel.set_peripheral_address(
+                        unsafe { &(*$SPIi::ptr()).dr as *const _ as u32 },
+                        false,
+                    );
+                    self.txchannel.set_memory_address(tx.as_ptr() as u32, true);
+                    self.txchannel.set_transfer_length(tx.len());
+                }
+                atomic::compiler_fence(Ordering::Release);
+                self.rxchannel.ch().cr.modify(|_, w| {
+                    w
+                        // memory to memory mode disabled
+                        .mem2mem()
+                        .clear_bit()
+                        // medium channel priority level
+                        .pl()
+                        .medium()
+                        // 8-bit memory size
+                        .msize()
+                        .bits8()
+                        // 8-bit peripheral size
+                        .psize()
+                        .bits8()
+                        // circular mode disabled
+                        .circ()
+                        .clear_bit()
+                        // write to memory
+                        .dir()
+                        .clear_bit()
+                });
+                self.txchannel.ch().cr.modify(|_, w| {
+                    w
+                        // memory to memory mode disabled
+                        .mem2mem()
+                        .clear_bit()
+                        // medium channel priority level
+                        .pl()
+                        .medium()
+                        // 8-bit memory size
+                        .msize()
+                        .bits8()
+                        // 8-bit peripheral size
+                        .psize()
+                        .bits8()
+                        // circular mode disabled
+                        .circ()
+                        .clear_bit()
+                        // read from memory
+                        .dir()
+                        .set_bit()
+                });
+                self.start();
+
+                Transfer::w((rx, tx), self)
+            }
+        }
     };
 }
 
-spi_dma!(SPI1, C3);
-spi_dma!(SPI2, C5);
+spi_dma!(SPI1, dma1::C2, dma1::C3);
+spi_dma!(SPI2, dma1::C4, dma1::C5);
 #[cfg(feature = "connectivity")]
-spi_dma!(SPI3, C2);
+spi_dma!(SPI3, dma2::C1, dma2::C2);
@@ -183,12 +183,33 @@ impl Timer<SYST> {
         timer
     }
 
+    /// Updates the timer clock after the clocks have been reconfigured
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        self.clk = clocks.hclk();
+    }
+
     pub fn release(self) -> SYST {
         self.tim
     }
 }
 
 impl CountDownTimer<SYST> {
+    /// Rescales the reload value after the clocks have been reconfigured so that the timeout
+    /// stays the same
+    ///
+    /// If the timeout does not fit the 24-bit reload value at the new clock, the longest
+    /// possible timeout is used.
+    pub fn reconfigure(&mut self, clocks: &Clocks) {
+        let clk = clocks.hclk();
+        let ticks = u64(SYST::get_reload() + 1) * u64(clk.0) / u64(self.clk.0);
+        // A timeout shorter than one tick of the new clock is rounded up to one tick
+        let rvr = u32((ticks.max(1) - 1).min((1 << 24) - 1)).unwrap();
+
+        self.clk = clk;
+        self.tim.set_reload(rvr);
+        self.tim.clear_current();
+    }
+
     /// Starts listening for an `event`
     pub fn listen(&mut self, event: Event) {
         match event {
@@ -326,6 +347,11 @@ macro_rules! hal {
                     dbg.cr.modify(|_, w| w.$dbg_timX_stop().bit(state));
                 }
 
+                /// Updates the timer clock after the clocks have been reconfigured
+                pub fn reconfigure(&mut self, clocks: &Clocks) {
+                    self.clk = <$TIMX as RccBus>::Bus::get_timer_frequency(clocks);
+                }
+
                 /// Releases the TIM Peripheral
                 pub fn release(self) -> $TIMX {
                     self.tim
@@ -381,6 +407,31 @@ macro_rules! hal {
                     u32(1_000_000 * cnt / freq_divider).unwrap()
                 }
 
+                /// Rescales the prescaler and the auto-reload value after the clocks have been
+                /// reconfigured so that the timeout stays the same
+                ///
+                /// If the timeout does not fit the timer at the new clock, the longest possible
+                /// timeout is used.
+                pub fn reconfigure(&mut self, clocks: &Clocks) {
+                    let clk = <$TIMX as RccBus>::Bus::get_timer_frequency(clocks);
+                    let psc = u64(self.tim.psc.read().psc().bits());
+                    let arr = u64(self.tim.arr.read().arr().bits());
+                    let ticks = (psc + 1) * arr * u64(clk.0) / u64(self.clk.0);
+                    // A timeout shorter than one tick of the new clock is rounded up to one tick
+                    let ticks = ticks.max(1).min(u64(u32::MAX));
+                    let (psc, arr) = ticks_to_arr_presc(u32(ticks).unwrap());
+                    self.clk = clk;
+
+                    self.tim.psc.write(|w| w.psc().bits(psc) );
+
+                    // TODO: Remove this `allow` once this field is made safe for stm32f100
+                    #[allow(unused_unsafe)]
+                    self.tim.arr.write(|w| unsafe { w.arr().bits(arr) });
+
+                    // Trigger an update event to load the prescaler value to the clock
+                    self.reset();
+                }
+
                 /// Resets the counter
                 pub fn reset(&mut self) {
                     // Sets the URS bit to prevent an interrupt from being triggered by
@@ -449,7 +500,11 @@ macro_rules! hal {
 
 #[inline(always)]
 fn compute_arr_presc(freq: u32, clock: u32) -> (u16, u16) {
-    let ticks = clock / freq;
+    ticks_to_arr_presc(clock / freq)
+}
+
+#[inline(always)]
+fn ticks_to_arr_presc(ticks: u32) -> (u16, u16) {
     let psc = u16((ticks - 1) / (1 << 16)).unwrap();
     let arr = u16(ticks / u32(psc + 1)).unwrap();
     (psc, arr)